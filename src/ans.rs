@@ -0,0 +1,368 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use serde::Serialize;
+
+use crate::bitio::{BitBuffer, BitReader, BitWriter, DecodeError};
+use crate::histogram::Histogram;
+use crate::huffman::canonical::HuffmanSymbol;
+
+/*
+    rANS (range asymmetric numeral systems) with static tables.
+
+    Symbol frequencies are quantized so that they sum to M = 2^scale_bits. The whole
+    message is kept in a single integer state `x`, normalized to [L, 256 L):
+        encode s:  x' = (x / f_s) * M + (x mod f_s) + c_s
+        decode:    slot = x' mod M, s = symbol owning slot, x = f_s * (x' / M) + slot - c_s
+    where f_s is the frequency and c_s the cumulative frequency of s. Before encoding,
+    the encoder shifts bytes out of `x` so the result stays in range; the decoder shifts
+    them back in after decoding.
+
+    rANS is last-in first-out: symbols are encoded in reverse and the output is reversed,
+    so the decoder reads forward. Decoding uses a table with one entry per slot, so
+    finding the symbol is a single lookup.
+*/
+
+const STATE_LOW: u32 = 1 << 23;
+const COUNT_BITS: u32 = 32;
+const WIDTH_BITS: u32 = 7;
+const SCALE_FIELD_BITS: u32 = 5;
+
+pub const DEFAULT_SCALE_BITS: u32 = 14;
+// Keeps (L / M) * 256 * f within 32 bits
+pub const MAX_SCALE_BITS: u32 = 16;
+
+/// Table size for `symbols` distinct symbols: `DEFAULT_SCALE_BITS`, or more so that
+/// every symbol gets at least 2 slots on average. None if they don't fit at all.
+pub fn scale_bits_for(symbols: usize) -> Option<u32> {
+    let bits = (symbols.max(1) * 2).next_power_of_two().trailing_zeros().max(DEFAULT_SCALE_BITS);
+    if bits <= MAX_SCALE_BITS {
+        Some(bits)
+    } else if symbols <= 1 << MAX_SCALE_BITS {
+        Some(MAX_SCALE_BITS)
+    } else {
+        None
+    }
+}
+
+/// Static rANS table. A table without symbols is the table of empty data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RansTable<T>
+where
+    T: HuffmanSymbol,
+{
+    scale_bits: u32,
+    // Symbols in increasing order, with their quantized and cumulative frequencies
+    symbols: Vec<T>,
+    freqs: Vec<u32>,
+    cumulative: Vec<u32>,
+    indices: HashMap<T, usize>,
+    // Index of the symbol owning each of the 2^scale_bits slots
+    slots: Vec<u32>,
+}
+
+impl<T> RansTable<T>
+where
+    T: HuffmanSymbol + Display + Serialize,
+{
+    /// Quantize the histogram to a total of 2^scale_bits, keeping every symbol at least 1.
+    pub fn from_histogram(histogram: &Histogram<T>, scale_bits: u32) -> Self {
+        assert!(
+            (1..=MAX_SCALE_BITS).contains(&scale_bits),
+            "scale bits must be between 1 and {}",
+            MAX_SCALE_BITS
+        );
+        let mut counts: Vec<(T, usize)> = histogram.counts().iter().map(|(&s, &c)| (s, c)).collect();
+        counts.sort();
+        let total = 1u64 << scale_bits;
+        assert!(
+            counts.len() as u64 <= total,
+            "{} symbols do not fit in a table of {} slots",
+            counts.len(),
+            total
+        );
+        if counts.is_empty() {
+            return Self::from_frequencies(Vec::new(), Vec::new(), scale_bits);
+        }
+
+        let total_count = histogram.total_count() as u64;
+        let mut freqs: Vec<u32> = counts
+            .iter()
+            .map(|&(_, c)| ((c as u64 * total + total_count / 2) / total_count).max(1) as u32)
+            .collect();
+
+        // Fix the rounding error on the most frequent symbols, where it costs the least
+        let mut order: Vec<usize> = (0..freqs.len()).collect();
+        order.sort_by_key(|&i| std::cmp::Reverse(freqs[i]));
+        let mut sum: i64 = freqs.iter().map(|&f| f as i64).sum();
+        let mut k = 0;
+        while sum != total as i64 {
+            let i = order[k % order.len()];
+            if sum < total as i64 {
+                freqs[i] += 1;
+                sum += 1;
+            } else if freqs[i] > 1 {
+                freqs[i] -= 1;
+                sum -= 1;
+            }
+            k += 1;
+        }
+
+        let symbols = counts.into_iter().map(|(s, _)| s).collect();
+        Self::from_frequencies(symbols, freqs, scale_bits)
+    }
+}
+
+impl<T> RansTable<T>
+where
+    T: HuffmanSymbol,
+{
+    fn from_frequencies(symbols: Vec<T>, freqs: Vec<u32>, scale_bits: u32) -> Self {
+        assert!(
+            (1..=MAX_SCALE_BITS).contains(&scale_bits),
+            "scale bits must be between 1 and {}",
+            MAX_SCALE_BITS
+        );
+        let mut cumulative = Vec::with_capacity(freqs.len());
+        let mut slots = Vec::with_capacity(1 << scale_bits);
+        let mut sum = 0;
+        for (i, &f) in freqs.iter().enumerate() {
+            cumulative.push(sum);
+            sum += f;
+            slots.resize(sum as usize, i as u32);
+        }
+        assert!(freqs.is_empty() || sum == 1 << scale_bits, "frequencies must sum to 2^scale_bits");
+        let indices = symbols.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+        RansTable {
+            scale_bits,
+            symbols,
+            freqs,
+            cumulative,
+            indices,
+            slots,
+        }
+    }
+
+    pub fn scale_bits(&self) -> u32 {
+        self.scale_bits
+    }
+
+    // Quantized frequency of every symbol, in increasing symbol order.
+    pub fn frequencies(&self) -> Vec<(T, u32)> {
+        self.symbols.iter().copied().zip(self.freqs.iter().copied()).collect()
+    }
+
+    /// Write the frequency table.
+    ///
+    /// Layout: scale bits (5 bits), number of entries (32 bits), smallest symbol
+    /// (`T::BITS` bits), width of the symbol offsets (7 bits), then one
+    /// (offset, frequency - 1) pair per symbol, frequencies on `scale_bits` bits.
+    pub fn write_header(&self, writer: &mut BitWriter) {
+        let min = self.symbols.first().map_or(0, |symbol| symbol.to_u64());
+        let max = self.symbols.last().map_or(0, |symbol| symbol.to_u64());
+        let width = u64::BITS - (max - min).leading_zeros();
+
+        writer.write_bits(self.scale_bits as u64, SCALE_FIELD_BITS);
+        writer.write_bits(self.symbols.len() as u64, COUNT_BITS);
+        writer.write_bits(min, T::BITS);
+        writer.write_bits(width as u64, WIDTH_BITS);
+        for (&symbol, &freq) in self.symbols.iter().zip(&self.freqs) {
+            writer.write_bits(symbol.to_u64() - min, width);
+            writer.write_bits(freq as u64 - 1, self.scale_bits);
+        }
+    }
+
+    /// Rebuild the table from a header written by `write_header`.
+    pub fn read_header(reader: &mut BitReader) -> Option<Self> {
+        let scale_bits = reader.read_bits(SCALE_FIELD_BITS)? as u32;
+        let count = reader.read_bits(COUNT_BITS)? as usize;
+        let min = reader.read_bits(T::BITS)?;
+        let width = reader.read_bits(WIDTH_BITS)? as u32;
+        let valid = (1..=MAX_SCALE_BITS).contains(&scale_bits) && width <= 64;
+        if !valid || count > 1 << scale_bits {
+            return None;
+        }
+        let mut symbols = Vec::with_capacity(count);
+        let mut freqs = Vec::with_capacity(count);
+        for _ in 0..count {
+            symbols.push(T::from_u64(min.wrapping_add(reader.read_bits(width)?)));
+            freqs.push(reader.read_bits(scale_bits)? as u32 + 1);
+        }
+        if count > 0 && freqs.iter().map(|&f| f as u64).sum::<u64>() != 1 << scale_bits {
+            return None;
+        }
+        Some(Self::from_frequencies(symbols, freqs, scale_bits))
+    }
+
+    /// Encode `data` (which must be replayable, since rANS works backwards).
+    pub fn encode(&self, data: &[T], writer: &mut BitWriter) {
+        let mut bytes = Vec::new();
+        let mut x = STATE_LOW;
+        for symbol in data.iter().rev() {
+            let index = *self
+                .indices
+                .get(symbol)
+                .expect("symbol is not part of the rANS table");
+            let freq = self.freqs[index];
+            let x_max = ((STATE_LOW >> self.scale_bits) << 8) * freq;
+            while x >= x_max {
+                bytes.push(x as u8);
+                x >>= 8;
+            }
+            x = ((x / freq) << self.scale_bits) + (x % freq) + self.cumulative[index];
+        }
+        bytes.extend_from_slice(&x.to_le_bytes());
+        for &byte in bytes.iter().rev() {
+            writer.write_bits(byte as u64, 8);
+        }
+    }
+
+    /// Decode `len` symbols.
+    pub fn decode(&self, reader: &mut BitReader, len: usize) -> Option<Vec<T>> {
+        let mask = (1 << self.scale_bits) - 1;
+        let mut x = reader.read_bits(32)? as u32;
+        // `len` may come from a corrupt stream, so don't trust it for the allocation
+        let mut decoded = Vec::with_capacity(len.min(reader.remaining() + 1));
+        for _ in 0..len {
+            let slot = x & mask;
+            // (no slots at all in the table of empty data)
+            let index = *self.slots.get(slot as usize)? as usize;
+            decoded.push(self.symbols[index]);
+            x = self.freqs[index] * (x >> self.scale_bits) + slot - self.cumulative[index];
+            while x < STATE_LOW {
+                x = (x << 8) | reader.read_bits(8)? as u32;
+            }
+        }
+        Some(decoded)
+    }
+}
+
+/// rANS-coded data: frequency table header, symbol count (32 bits) and the rANS bytes.
+pub struct RansEncoded<T>
+where
+    T: HuffmanSymbol,
+{
+    pub table: RansTable<T>,
+    pub table_bits: usize,
+    pub encoded_bits: BitBuffer,
+}
+
+impl<T> RansEncoded<T>
+where
+    T: HuffmanSymbol,
+{
+    // Total size, frequency table included.
+    pub fn bits(&self) -> usize {
+        self.encoded_bits.len()
+    }
+
+    // Decode using only the bitstream (the table is read back from the header).
+    pub fn decode(&self) -> Result<Vec<T>, DecodeError> {
+        rans_decode(&self.encoded_bits)
+    }
+}
+
+pub fn rans_encode<T, I>(data: I, scale_bits: u32) -> RansEncoded<T>
+where
+    T: HuffmanSymbol + Display + Serialize,
+    I: IntoIterator<Item = T>,
+{
+    let data: Vec<T> = data.into_iter().collect();
+    let histogram = Histogram::from_iter(data.iter().copied());
+    let table = RansTable::from_histogram(&histogram, scale_bits);
+
+    let mut writer = BitWriter::new();
+    table.write_header(&mut writer);
+    let table_bits = writer.position();
+    writer.write_bits(data.len() as u64, COUNT_BITS);
+    table.encode(&data, &mut writer);
+    RansEncoded {
+        table,
+        table_bits,
+        encoded_bits: writer.finish(),
+    }
+}
+
+pub fn rans_decode<T>(bits: &BitBuffer) -> Result<Vec<T>, DecodeError>
+where
+    T: HuffmanSymbol,
+{
+    let mut reader = bits.reader();
+    let table = RansTable::read_header(&mut reader).ok_or(DecodeError::InvalidHeader)?;
+    let len = reader.read_bits(COUNT_BITS).ok_or(DecodeError::InvalidHeader)? as usize;
+    let position = reader.position();
+    let decoded = table.decode(&mut reader, len).ok_or(DecodeError::InvalidCode { position })?;
+    if !reader.is_empty() {
+        return Err(DecodeError::InvalidCode { position: reader.position() });
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[i32], scale_bits: u32) -> RansEncoded<i32> {
+        let encoded = rans_encode(data.iter().copied(), scale_bits);
+        assert_eq!(encoded.decode().unwrap(), data);
+        encoded
+    }
+
+    #[test]
+    fn empty_input() {
+        let encoded = round_trip(&[], DEFAULT_SCALE_BITS);
+        assert!(encoded.table.frequencies().is_empty());
+        // Header, symbol count and the final state
+        assert_eq!(encoded.bits(), encoded.table_bits + COUNT_BITS as usize + 32);
+    }
+
+    #[test]
+    fn one_symbol() {
+        // The only symbol owns every slot, so it costs nothing past the final state
+        let encoded = round_trip(&[5; 1000], DEFAULT_SCALE_BITS);
+        assert_eq!(encoded.table.frequencies(), vec![(5, 1 << DEFAULT_SCALE_BITS)]);
+        assert_eq!(encoded.bits(), encoded.table_bits + COUNT_BITS as usize + 32);
+    }
+
+    #[test]
+    fn skewed_input() {
+        let mut data = vec![0; 5000];
+        data.extend([1; 50]);
+        data.extend([i32::MIN, i32::MAX, -1]);
+        for scale_bits in [3, DEFAULT_SCALE_BITS, MAX_SCALE_BITS] {
+            let encoded = round_trip(&data, scale_bits);
+            let frequencies = encoded.table.frequencies();
+            assert!(frequencies.iter().all(|&(_, freq)| freq >= 1));
+            assert_eq!(frequencies.iter().map(|&(_, freq)| freq).sum::<u32>(), 1 << scale_bits);
+        }
+    }
+
+    #[test]
+    fn table_sizes() {
+        assert_eq!(scale_bits_for(0), Some(DEFAULT_SCALE_BITS));
+        assert_eq!(scale_bits_for(256), Some(DEFAULT_SCALE_BITS));
+        assert_eq!(scale_bits_for(20_000), Some(MAX_SCALE_BITS));
+        assert_eq!(scale_bits_for(1 << MAX_SCALE_BITS), Some(MAX_SCALE_BITS));
+        assert_eq!(scale_bits_for((1 << MAX_SCALE_BITS) + 1), None);
+    }
+
+    #[test]
+    fn invalid_streams_are_errors() {
+        let encoded = round_trip(&[3, 1, 4, 1, 5, 9, 2, 6], DEFAULT_SCALE_BITS);
+        let bits = &encoded.encoded_bits;
+
+        let truncated = BitBuffer::from_bytes(bits.as_bytes().to_vec(), bits.len() - 8).unwrap();
+        assert!(matches!(rans_decode::<i32>(&truncated), Err(DecodeError::InvalidCode { .. })));
+
+        let mut bytes = bits.as_bytes().to_vec();
+        bytes.push(0);
+        let trailing = BitBuffer::from_bytes(bytes, bits.len() + 8).unwrap();
+        assert!(matches!(rans_decode::<i32>(&trailing), Err(DecodeError::InvalidCode { .. })));
+
+        // Scale bits of 0 aren't valid
+        let mut bytes = bits.as_bytes().to_vec();
+        bytes[0] &= 0b0000_0111;
+        let header = BitBuffer::from_bytes(bytes, bits.len()).unwrap();
+        assert_eq!(rans_decode::<i32>(&header), Err(DecodeError::InvalidHeader));
+    }
+}
@@ -0,0 +1,670 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt::{self, Display};
+use std::hash::Hash;
+
+use serde::Serialize;
+
+use crate::bitio::{BitBuffer, BitReader, BitWriter, DecodeError};
+use crate::histogram::Histogram;
+use crate::huffman::canonical::HuffmanSymbol;
+
+/*
+    Integer arithmetic coding (range coder variant).
+
+    The coder keeps an interval [low, low + range) of 32-bit width. To encode a symbol
+    with cumulative frequency `cum`, frequency `freq` and model total `total`, the interval
+    is narrowed to
+        r = range / total
+        low += r * cum
+        range = r * freq
+    and whenever `range` drops below 2^24 the top byte of `low` is shifted out.
+
+    Adding to `low` can carry into bytes that were already shifted out. The encoder keeps
+    the last byte (`cache`) plus a run of pending 0xFF bytes unwritten until it knows
+    whether a carry will propagate into them, so the output never needs to be revisited.
+
+    Model totals must stay at or below `MAX_TOTAL` (2^16) so that `r` keeps 8 bits of precision.
+
+    The decoder reads back exactly the bytes the encoder wrote, so a stream that runs out
+    early, or has bytes left over, is corrupt. A static model is sent ahead of the stream
+    in a header (see `StaticModel::write_header`); an adaptive model starts from a state
+    both sides know.
+*/
+
+pub const MAX_TOTAL: u32 = 1 << 16;
+// Largest alphabets of `StaticModel` and `AdaptiveModel`
+pub const MAX_STATIC_SYMBOLS: usize = MAX_TOTAL as usize - 1;
+pub const MAX_ADAPTIVE_SYMBOLS: usize = (MAX_TOTAL / 2) as usize;
+const TOP: u32 = 1 << 24;
+const COUNT_BITS: u32 = 32;
+const WIDTH_BITS: u32 = 7;
+const FREQUENCY_BITS: u32 = 16;
+
+/// A symbol the model can't code, at `index` in the input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownSymbol {
+    pub index: usize,
+}
+
+impl fmt::Display for UnknownSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "symbol {} of the input is not part of the model's alphabet", self.index)
+    }
+}
+
+impl Error for UnknownSymbol {}
+
+/// Cumulative frequency model shared by the encoder and the decoder.
+///
+/// Symbols are handled through their index in the model's alphabet.
+pub trait FrequencyModel {
+    type Symbol: Copy;
+
+    fn total(&self) -> u32;
+    // Index of `symbol` in the alphabet (None if the model can't code it)
+    fn index_of(&self, symbol: Self::Symbol) -> Option<usize>;
+    fn symbol(&self, index: usize) -> Self::Symbol;
+    // (cumulative frequency, frequency) of the symbol at `index`
+    fn range_of(&self, index: usize) -> (u32, u32);
+    // Index of the symbol whose range contains `target` (0 <= target < total)
+    fn find(&self, target: u32) -> usize;
+    // Called after every coded symbol; static models ignore it.
+    fn update(&mut self, _index: usize) {}
+    // Size of the header the decoder needs to rebuild the model, in bits (nothing for
+    // models that start from a state known to both sides)
+    fn table_bits(&self) -> usize {
+        0
+    }
+}
+
+/// Fixed frequencies, scaled from a `Histogram` to a total of at most `MAX_TOTAL`.
+/// A model without symbols is the model of empty data.
+#[derive(Debug, Clone)]
+pub struct StaticModel<T> {
+    symbols: Vec<T>,
+    // cumulative[i] = sum of the frequencies of symbols[..i]; one extra entry for the total
+    cumulative: Vec<u32>,
+    indices: HashMap<T, usize>,
+}
+
+impl<T> StaticModel<T>
+where
+    T: Hash + Eq + Copy + Ord + Display + Serialize,
+{
+    pub fn from_histogram(histogram: &Histogram<T>) -> Self {
+        let mut symbols: Vec<T> = histogram.counts().keys().copied().collect();
+        symbols.sort();
+        assert!(
+            symbols.len() <= MAX_STATIC_SYMBOLS,
+            "the histogram must have at most {} symbols",
+            MAX_STATIC_SYMBOLS
+        );
+
+        // Every symbol keeps a frequency of at least 1; the sum is at most MAX_TOTAL
+        let budget = (MAX_TOTAL as usize - symbols.len()) as u64;
+        let total_count = histogram.total_count() as u64;
+        let freqs = symbols
+            .iter()
+            .map(|symbol| (histogram.counts()[symbol] as u64 * budget / total_count).max(1) as u32)
+            .collect();
+        Self::from_frequencies(symbols, freqs)
+    }
+
+}
+
+impl<T> StaticModel<T>
+where
+    T: Hash + Eq + Copy,
+{
+    fn from_frequencies(symbols: Vec<T>, freqs: Vec<u32>) -> Self {
+        let mut cumulative = Vec::with_capacity(freqs.len() + 1);
+        let mut sum = 0;
+        for &freq in &freqs {
+            cumulative.push(sum);
+            sum += freq;
+        }
+        cumulative.push(sum);
+        let indices = symbols.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+        StaticModel { symbols, cumulative, indices }
+    }
+
+    // Scaled frequency of every symbol, in increasing symbol order.
+    pub fn frequencies(&self) -> Vec<(T, u32)> {
+        self.symbols
+            .iter()
+            .zip(self.cumulative.windows(2))
+            .map(|(&s, w)| (s, w[1] - w[0]))
+            .collect()
+    }
+}
+
+impl<T> StaticModel<T>
+where
+    T: HuffmanSymbol,
+{
+    /// Write the frequency table.
+    ///
+    /// Layout, as in the rANS header: number of symbols (32 bits), smallest symbol
+    /// (`T::BITS` bits), width of the symbol offsets (7 bits), then one
+    /// (offset, frequency - 1) pair per symbol, frequencies on 16 bits.
+    pub fn write_header(&self, writer: &mut BitWriter) {
+        let min = self.symbols.first().map_or(0, |symbol| symbol.to_u64());
+        let max = self.symbols.last().map_or(0, |symbol| symbol.to_u64());
+        let width = u64::BITS - (max - min).leading_zeros();
+
+        writer.write_bits(self.symbols.len() as u64, COUNT_BITS);
+        writer.write_bits(min, T::BITS);
+        writer.write_bits(width as u64, WIDTH_BITS);
+        for (symbol, freq) in self.frequencies() {
+            writer.write_bits(symbol.to_u64() - min, width);
+            writer.write_bits(freq as u64 - 1, FREQUENCY_BITS);
+        }
+    }
+
+    /// Rebuild the model from a header written by `write_header`.
+    pub fn read_header(reader: &mut BitReader) -> Option<Self> {
+        let count = reader.read_bits(COUNT_BITS)? as usize;
+        let min = reader.read_bits(T::BITS)?;
+        let width = reader.read_bits(WIDTH_BITS)? as u32;
+        if count > MAX_STATIC_SYMBOLS || width > 64 {
+            return None;
+        }
+        let mut symbols = Vec::with_capacity(count);
+        let mut freqs = Vec::with_capacity(count);
+        let mut previous = None;
+        for _ in 0..count {
+            // Offsets are written in increasing order, so every symbol is different
+            let offset = reader.read_bits(width)?;
+            if previous.is_some_and(|previous| offset <= previous) {
+                return None;
+            }
+            previous = Some(offset);
+            symbols.push(T::from_u64(min.wrapping_add(offset)));
+            freqs.push(reader.read_bits(FREQUENCY_BITS)? as u32 + 1);
+        }
+        if freqs.iter().map(|&f| f as u64).sum::<u64>() > MAX_TOTAL as u64 {
+            return None;
+        }
+        Some(Self::from_frequencies(symbols, freqs))
+    }
+}
+
+impl<T> FrequencyModel for StaticModel<T>
+where
+    T: HuffmanSymbol,
+{
+    type Symbol = T;
+
+    fn total(&self) -> u32 {
+        *self.cumulative.last().unwrap()
+    }
+
+    fn index_of(&self, symbol: T) -> Option<usize> {
+        self.indices.get(&symbol).copied()
+    }
+
+    fn symbol(&self, index: usize) -> T {
+        self.symbols[index]
+    }
+
+    fn range_of(&self, index: usize) -> (u32, u32) {
+        let cum = self.cumulative[index];
+        (cum, self.cumulative[index + 1] - cum)
+    }
+
+    fn find(&self, target: u32) -> usize {
+        self.cumulative.partition_point(|&c| c <= target) - 1
+    }
+
+    fn table_bits(&self) -> usize {
+        let mut writer = BitWriter::new();
+        self.write_header(&mut writer);
+        writer.position()
+    }
+}
+
+/// Frequencies learned while coding: every symbol of a known alphabet starts with a count
+/// of 1, coded symbols gain `INCREMENT`, and all counts are halved when the total would
+/// exceed `MAX_TOTAL`. Cumulative counts live in a Fenwick tree.
+#[derive(Debug, Clone)]
+pub struct AdaptiveModel<T> {
+    symbols: Vec<T>,
+    indices: HashMap<T, usize>,
+    counts: Vec<u32>,
+    // 1-based Fenwick tree over `counts`
+    tree: Vec<u32>,
+    total: u32,
+}
+
+const INCREMENT: u32 = 32;
+
+impl<T> AdaptiveModel<T>
+where
+    T: Hash + Eq + Copy,
+{
+    pub fn new<I>(alphabet: I) -> Self
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let symbols: Vec<T> = alphabet.into_iter().collect();
+        assert!(
+            !symbols.is_empty() && symbols.len() <= MAX_ADAPTIVE_SYMBOLS,
+            "the alphabet must have between 1 and {} symbols",
+            MAX_ADAPTIVE_SYMBOLS
+        );
+        let indices = symbols.iter().enumerate().map(|(i, &s)| (s, i)).collect();
+        let counts = vec![1; symbols.len()];
+        let mut model = AdaptiveModel {
+            symbols,
+            indices,
+            tree: Vec::new(),
+            total: 0,
+            counts,
+        };
+        model.rebuild();
+        model
+    }
+
+    fn rebuild(&mut self) {
+        let n = self.counts.len();
+        self.tree = vec![0; n + 1];
+        for i in 1..=n {
+            self.tree[i] += self.counts[i - 1];
+            let parent = i + (i & i.wrapping_neg());
+            if parent <= n {
+                self.tree[parent] += self.tree[i];
+            }
+        }
+        self.total = self.counts.iter().sum();
+    }
+
+    // Sum of counts[..index]
+    fn prefix(&self, index: usize) -> u32 {
+        let mut sum = 0;
+        let mut i = index;
+        while i > 0 {
+            sum += self.tree[i];
+            i &= i - 1;
+        }
+        sum
+    }
+}
+
+impl<T> FrequencyModel for AdaptiveModel<T>
+where
+    T: Hash + Eq + Copy,
+{
+    type Symbol = T;
+
+    fn total(&self) -> u32 {
+        self.total
+    }
+
+    fn index_of(&self, symbol: T) -> Option<usize> {
+        self.indices.get(&symbol).copied()
+    }
+
+    fn symbol(&self, index: usize) -> T {
+        self.symbols[index]
+    }
+
+    fn range_of(&self, index: usize) -> (u32, u32) {
+        (self.prefix(index), self.counts[index])
+    }
+
+    fn find(&self, target: u32) -> usize {
+        // Walk down the Fenwick tree to the last index whose prefix sum is <= target
+        let n = self.counts.len();
+        let mut index = 0;
+        let mut remaining = target;
+        let mut step = n.next_power_of_two();
+        while step > 0 {
+            let next = index + step;
+            if next <= n && self.tree[next] <= remaining {
+                index = next;
+                remaining -= self.tree[next];
+            }
+            step >>= 1;
+        }
+        index
+    }
+
+    fn update(&mut self, index: usize) {
+        if self.total + INCREMENT > MAX_TOTAL {
+            for count in self.counts.iter_mut() {
+                *count = (*count).div_ceil(2);
+            }
+            self.rebuild();
+        }
+        self.counts[index] += INCREMENT;
+        self.total += INCREMENT;
+        let n = self.counts.len();
+        let mut i = index + 1;
+        while i <= n {
+            self.tree[i] += INCREMENT;
+            i += i & i.wrapping_neg();
+        }
+    }
+}
+
+pub struct ArithmeticEncoder {
+    writer: BitWriter,
+    low: u64,
+    range: u32,
+    // Last byte shifted out of `low`, not yet written (it may still receive a carry)
+    cache: u8,
+    // Number of bytes held back: `cache` plus a run of 0xFF bytes
+    pending: u64,
+    // The very first cached byte is always 0 and is never written
+    first: bool,
+    // Number of symbols coded so far
+    len: usize,
+}
+
+impl ArithmeticEncoder {
+    pub fn new() -> Self {
+        ArithmeticEncoder {
+            writer: BitWriter::new(),
+            low: 0,
+            range: u32::MAX,
+            cache: 0,
+            pending: 1,
+            first: true,
+            len: 0,
+        }
+    }
+
+    /// Code `symbol` and update the model. Fails, coding nothing, if the model can't code it.
+    pub fn encode<M: FrequencyModel>(&mut self, model: &mut M, symbol: M::Symbol) -> Result<(), UnknownSymbol> {
+        let index = model.index_of(symbol).ok_or(UnknownSymbol { index: self.len })?;
+        let (cum, freq) = model.range_of(index);
+        let r = self.range / model.total();
+        self.low += r as u64 * cum as u64;
+        self.range = r * freq;
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+        model.update(index);
+        self.len += 1;
+        Ok(())
+    }
+
+    fn shift_low(&mut self) {
+        if self.low < 0xFF00_0000 || self.low >= 1 << 32 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            while self.pending > 0 {
+                if !self.first {
+                    self.writer.write_bits(byte.wrapping_add(carry) as u64, 8);
+                }
+                self.first = false;
+                byte = 0xFF;
+                self.pending -= 1;
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.pending += 1;
+        self.low = (self.low & 0x00FF_FFFF) << 8;
+    }
+
+    pub fn finish(mut self) -> BitBuffer {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.writer.finish()
+    }
+}
+
+impl Default for ArithmeticEncoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct ArithmeticDecoder<'a> {
+    reader: BitReader<'a>,
+    code: u32,
+    range: u32,
+}
+
+impl<'a> ArithmeticDecoder<'a> {
+    pub fn new(mut reader: BitReader<'a>) -> Result<Self, DecodeError> {
+        let mut code = 0;
+        for _ in 0..4 {
+            code = (code << 8) | Self::next_byte(&mut reader)?;
+        }
+        Ok(ArithmeticDecoder {
+            reader,
+            code,
+            range: u32::MAX,
+        })
+    }
+
+    fn next_byte(reader: &mut BitReader) -> Result<u32, DecodeError> {
+        let position = reader.position();
+        reader.read_bits(8).map(|byte| byte as u32).ok_or(DecodeError::InvalidCode { position })
+    }
+
+    /// Decode one symbol and update the model.
+    pub fn decode<M: FrequencyModel>(&mut self, model: &mut M) -> Result<M::Symbol, DecodeError> {
+        let total = model.total();
+        let r = self.range / total.max(1);
+        // The encoder keeps the code below r * total; past it the stream is corrupt
+        let target = self.code / r;
+        if target >= total {
+            return Err(DecodeError::InvalidCode { position: self.reader.position() });
+        }
+        let index = model.find(target);
+        let (cum, freq) = model.range_of(index);
+        self.code -= r * cum;
+        self.range = r * freq;
+        while self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | Self::next_byte(&mut self.reader)?;
+        }
+        let symbol = model.symbol(index);
+        model.update(index);
+        Ok(symbol)
+    }
+
+    /// Fail unless every byte of the stream has been read.
+    pub fn finish(&self) -> Result<(), DecodeError> {
+        if self.reader.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::InvalidCode { position: self.reader.position() })
+        }
+    }
+}
+
+/// Arithmetic-coded data together with the initial state of its model.
+pub struct ArithmeticEncoded<M> {
+    pub model: M,
+    pub len: usize,
+    pub encoded_bits: BitBuffer,
+}
+
+impl<M> ArithmeticEncoded<M>
+where
+    M: FrequencyModel + Clone,
+{
+    /// Total size, the header of a static model included.
+    pub fn bits(&self) -> usize {
+        self.encoded_bits.len() + self.model.table_bits()
+    }
+
+    /// Size of the coded symbols alone.
+    pub fn payload_bits(&self) -> usize {
+        self.encoded_bits.len()
+    }
+
+    pub fn bits_per_symbol(&self) -> f64 {
+        self.bits() as f64 / self.len as f64
+    }
+
+    pub fn decode(&self) -> Result<Vec<M::Symbol>, DecodeError> {
+        let mut model = self.model.clone();
+        let mut decoder = ArithmeticDecoder::new(self.encoded_bits.reader())?;
+        // `len` may not match the stream, so don't trust it for the allocation
+        let mut decoded = Vec::with_capacity(self.len.min(self.encoded_bits.len()));
+        for _ in 0..self.len {
+            decoded.push(decoder.decode(&mut model)?);
+        }
+        decoder.finish()?;
+        Ok(decoded)
+    }
+}
+
+/// Code `data` with `model`, failing on the first symbol outside its alphabet.
+pub fn arithmetic_encode<M, I>(model: M, data: I) -> Result<ArithmeticEncoded<M>, UnknownSymbol>
+where
+    M: FrequencyModel + Clone,
+    I: IntoIterator<Item = M::Symbol>,
+{
+    let mut encoder = ArithmeticEncoder::new();
+    let mut state = model.clone();
+    for symbol in data {
+        encoder.encode(&mut state, symbol)?;
+    }
+    Ok(ArithmeticEncoded {
+        model,
+        len: encoder.len,
+        encoded_bits: encoder.finish(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn static_round_trip(data: &[i32]) -> ArithmeticEncoded<StaticModel<i32>> {
+        let model = StaticModel::from_histogram(&Histogram::from_iter(data.iter().copied()));
+        let encoded = arithmetic_encode(model, data.iter().copied()).unwrap();
+        assert_eq!(encoded.decode().unwrap(), data);
+        encoded
+    }
+
+    fn adaptive_round_trip(data: &[i32], alphabet: std::ops::RangeInclusive<i32>) -> ArithmeticEncoded<AdaptiveModel<i32>> {
+        let encoded = arithmetic_encode(AdaptiveModel::new(alphabet), data.iter().copied()).unwrap();
+        assert_eq!(encoded.decode().unwrap(), data);
+        encoded
+    }
+
+    // Deterministic pseudo-random values below `n`
+    fn lcg(len: usize, n: u32) -> Vec<i32> {
+        let mut state = 12345u32;
+        (0..len)
+            .map(|_| {
+                state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+                ((state >> 16) % n) as i32
+            })
+            .collect()
+    }
+
+    #[test]
+    fn empty_input() {
+        let encoded = static_round_trip(&[]);
+        assert!(encoded.model.frequencies().is_empty());
+        // The four bytes of the final interval
+        assert_eq!(encoded.payload_bits(), 32);
+        assert_eq!(encoded.bits(), 32 + (COUNT_BITS + i32::BITS + WIDTH_BITS) as usize);
+        adaptive_round_trip(&[], 0..=255);
+    }
+
+    #[test]
+    fn one_symbol() {
+        // The only symbol takes the whole interval and costs nothing
+        let encoded = static_round_trip(&[7; 1000]);
+        assert_eq!(encoded.payload_bits(), 32);
+        let encoded = adaptive_round_trip(&[7; 1000], 0..=255);
+        assert!(encoded.payload_bits() < 1000);
+    }
+
+    #[test]
+    fn skewed_input() {
+        let mut data = vec![0; 5000];
+        data.extend(lcg(500, 256));
+        data.extend([i32::MIN, i32::MAX]);
+        let encoded = static_round_trip(&data);
+        let frequencies = encoded.model.frequencies();
+        assert!(frequencies.iter().all(|&(_, freq)| freq >= 1));
+        assert!(frequencies.iter().map(|&(_, freq)| freq).sum::<u32>() <= MAX_TOTAL);
+        adaptive_round_trip(&lcg(20_000, 1000), 0..=999);
+    }
+
+    #[test]
+    fn long_carry_chains() {
+        // With 16 equally likely symbols, this pattern keeps the interval straddling 2^32,
+        // so every byte shifted out is a held-back 0xFF; the final symbol lands above 2^32
+        // and carries through all of them, which come out as 0x00.
+        let model = StaticModel::from_histogram(&Histogram::from_iter(0..16u8));
+        let mut data: Vec<u8> = [15, 15, 0, 0, 0, 0].into_iter().cycle().take(999).collect();
+        data.push(1);
+        let encoded = arithmetic_encode(model, data.iter().copied()).unwrap();
+        assert_eq!(encoded.decode().unwrap(), data);
+        let longest_run = encoded
+            .encoded_bits
+            .as_bytes()
+            .split(|&byte| byte != 0)
+            .map(|run| run.len())
+            .max()
+            .unwrap();
+        assert!(longest_run >= 400, "longest run of carried bytes: {}", longest_run);
+
+        // And a long random input, where short carries happen all the time
+        static_round_trip(&lcg(200_000, 3));
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let data = [-3, 100, 100, 7, -3, 100];
+        let encoded = static_round_trip(&data);
+        let mut writer = BitWriter::new();
+        encoded.model.write_header(&mut writer);
+        assert_eq!(writer.position(), encoded.model.table_bits());
+        let header = writer.finish();
+
+        let model = StaticModel::<i32>::read_header(&mut header.reader()).unwrap();
+        assert_eq!(model.frequencies(), encoded.model.frequencies());
+        let decoded = ArithmeticEncoded { model, len: data.len(), encoded_bits: encoded.encoded_bits };
+        assert_eq!(decoded.decode().unwrap(), data);
+    }
+
+    #[test]
+    fn unknown_symbols_are_errors() {
+        let result = arithmetic_encode(AdaptiveModel::new(0..=3), [1, 2, 4, 0]);
+        assert_eq!(result.err(), Some(UnknownSymbol { index: 2 }));
+    }
+
+    #[test]
+    fn invalid_streams_are_errors() {
+        let data = lcg(100, 10);
+        let encoded = static_round_trip(&data);
+        let bits = &encoded.encoded_bits;
+        let with_bits = |encoded_bits| ArithmeticEncoded { model: encoded.model.clone(), len: data.len(), encoded_bits };
+
+        let truncated = BitBuffer::from_bytes(bits.as_bytes().to_vec(), bits.len() - 8).unwrap();
+        assert!(matches!(with_bits(truncated).decode(), Err(DecodeError::InvalidCode { .. })));
+
+        let mut bytes = bits.as_bytes().to_vec();
+        bytes.push(0);
+        let trailing = BitBuffer::from_bytes(bytes, bits.len() + 8).unwrap();
+        assert!(matches!(with_bits(trailing).decode(), Err(DecodeError::InvalidCode { .. })));
+
+        // A code past the model's total
+        let model = StaticModel::from_histogram(&Histogram::from_iter([0, 1]));
+        let corrupt = ArithmeticEncoded { model, len: 1, encoded_bits: BitBuffer::from_bytes(vec![0xFF; 4], 32).unwrap() };
+        assert!(matches!(corrupt.decode(), Err(DecodeError::InvalidCode { .. })));
+
+        // Repeated symbols in a header
+        let mut writer = BitWriter::new();
+        writer.write_bits(2, COUNT_BITS);
+        writer.write_bits(0, u8::BITS);
+        writer.write_bits(1, WIDTH_BITS);
+        writer.write_bits(0b1_0000_0000_0000_0000, 17);
+        writer.write_bits(0b1_0000_0000_0000_0000, 17);
+        assert!(StaticModel::<u8>::read_header(&mut writer.finish().reader()).is_none());
+    }
+}
@@ -0,0 +1,429 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::ops::Range;
+use std::path::Path;
+
+use ndarray::Array2;
+
+use crate::bitio::BitBuffer;
+use crate::fileio::{read_prologue, write_checksum, write_prologue, FileError};
+use crate::golomb::encode::{custom_encode_with_m, default_limit, escape_parameters, CustomGolombEncodedImage};
+use crate::golomb::mapping::SignedMapping;
+use crate::golomb::search::golomb_bits;
+use crate::histogram::Histogram;
+
+/*
+    Lossless coding of 16-bit PCM audio, as in FLAC.
+
+    Every channel is predicted with a fixed polynomial predictor, the 1-D analogue of
+    `prediction_err_matrix` (samples before the start of the signal count as 0):
+
+        order 0:  pred[n] = 0
+        order 1:  pred[n] = s[n-1]
+        order 2:  pred[n] = 2 s[n-1] - s[n-2]
+        order 3:  pred[n] = 3 s[n-1] - 3 s[n-2] + s[n-3]
+        order 4:  pred[n] = 4 s[n-1] - 6 s[n-2] + 4 s[n-3] - s[n-4]
+
+    The signal is cut into blocks of `block_size` samples per channel. For every block
+    and channel, the order and the Rice parameter k (a Golomb code with M = 2^k, zigzag
+    mapping) with the smallest size are chosen, and the residuals are coded by
+    `custom_encode_with_m`. Predictions use the previous samples even across blocks,
+    since the decoder restores the blocks in order.
+
+    Compressed file layout (integers are little-endian):
+
+        magic        4 bytes   "EETA"
+        version      u8        `VERSION`
+        channels     u16
+        sample rate  u32
+        frames       u64       samples per channel
+        block size   u32
+        then, for every block and every channel of the block:
+            order        u8
+            k            u8        Rice parameter
+            LIMIT        u8
+            escape bits  u8
+            payload len  u64       number of payload bits
+            payload      ...       packed residual codes, the last byte zero-padded
+        checksum     u32       CRC-32 of every byte above
+*/
+
+pub const MAGIC: [u8; 4] = *b"EETA";
+pub const VERSION: u8 = 1;
+
+pub const BITS_PER_SAMPLE: u16 = 16;
+pub const MAX_ORDER: u8 = 4;
+pub const MAX_RICE_PARAMETER: u32 = 24;
+// Samples per channel in a block, as in FLAC
+pub const DEFAULT_BLOCK_SIZE: u32 = 4096;
+
+const MAPPING: SignedMapping = SignedMapping::Zigzag;
+
+/// Samples of every channel of a 16-bit PCM signal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pcm {
+    pub sample_rate: u32,
+    // One vector of samples per channel, all of the same length
+    pub channels: Vec<Vec<i32>>,
+}
+
+impl Pcm {
+    // Samples per channel.
+    pub fn frames(&self) -> usize {
+        self.channels.first().map_or(0, Vec::len)
+    }
+}
+
+/// Residuals of a channel in a block.
+pub struct Subframe {
+    pub order: u8,
+    pub residuals: CustomGolombEncodedImage,
+}
+
+impl Subframe {
+    // Rice parameter: M = 2^k
+    pub fn k(&self) -> u32 {
+        self.residuals.m.trailing_zeros()
+    }
+}
+
+/// A compressed PCM signal.
+pub struct AudioFile {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub frames: usize,
+    pub block_size: u32,
+    // blocks[block][channel]
+    pub blocks: Vec<Vec<Subframe>>,
+}
+
+#[derive(Debug)]
+pub enum AudioError {
+    File(FileError),
+    Wav(hound::Error),
+    // Only 16-bit integer PCM is coded
+    UnsupportedFormat { bits_per_sample: u16, float: bool },
+}
+
+impl fmt::Display for AudioError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AudioError::File(err) => write!(f, "{}", err),
+            AudioError::Wav(err) => write!(f, "WAV error: {}", err),
+            AudioError::UnsupportedFormat { bits_per_sample, float } => write!(
+                f,
+                "only 16-bit integer PCM is supported, not {}-bit {}",
+                bits_per_sample,
+                if *float { "float" } else { "integer" }
+            ),
+        }
+    }
+}
+
+impl Error for AudioError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AudioError::File(err) => Some(err),
+            AudioError::Wav(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<FileError> for AudioError {
+    fn from(err: FileError) -> Self {
+        AudioError::File(err)
+    }
+}
+
+impl From<io::Error> for AudioError {
+    fn from(err: io::Error) -> Self {
+        AudioError::File(FileError::Io(err))
+    }
+}
+
+impl From<hound::Error> for AudioError {
+    fn from(err: hound::Error) -> Self {
+        AudioError::Wav(err)
+    }
+}
+
+/// Prediction of `samples[n]` by the fixed predictor of `order` (0 to `MAX_ORDER`),
+/// from the samples before it (`samples` may end at `n`). None for higher orders.
+pub fn fixed_prediction(samples: &[i32], n: usize, order: u8) -> Option<i32> {
+    let s = |back: usize| if back <= n { samples[n - back] } else { 0 };
+    match order {
+        0 => Some(0),
+        1 => Some(s(1)),
+        2 => Some(2 * s(1) - s(2)),
+        3 => Some(3 * s(1) - 3 * s(2) + s(3)),
+        4 => Some(4 * s(1) - 6 * s(2) + 4 * s(3) - s(4)),
+        _ => None,
+    }
+}
+
+/// Prediction error of `samples[range]` with the fixed predictor of `order`, or None
+/// if there's no predictor of that order.
+pub fn fixed_residuals(samples: &[i32], range: Range<usize>, order: u8) -> Option<Vec<i32>> {
+    range.map(|n| Some(samples[n] - fixed_prediction(samples, n, order)?)).collect()
+}
+
+/// Inverse of `fixed_residuals`: append the samples restored from `residuals` to `samples`.
+///
+/// Fails if a restored sample leaves the 16-bit range, which only corrupt residuals do,
+/// or if there's no predictor of `order`.
+pub fn restore_samples(samples: &mut Vec<i32>, residuals: &[i32], order: u8) -> Result<(), AudioError> {
+    for &residual in residuals {
+        let prediction = fixed_prediction(samples, samples.len(), order)
+            .ok_or(FileError::Corrupt("no fixed predictor of that order"))?;
+        let sample = prediction
+            .checked_add(residual)
+            .filter(|sample| i16::try_from(*sample).is_ok())
+            .ok_or(FileError::Corrupt("sample out of the 16-bit range"))?;
+        samples.push(sample);
+    }
+    Ok(())
+}
+
+// Size of `residuals` with the best Rice parameter, and that parameter
+fn best_rice_parameter(residuals: &Array2<i32>, limit: u32) -> (usize, u32) {
+    let histogram = Histogram::from_iter(residuals.iter().map(|&v| MAPPING.map(v)));
+    let (limit, escape_bits) = escape_parameters(residuals, limit, MAPPING);
+    (0..=MAX_RICE_PARAMETER)
+        .map(|k| (golomb_bits(&histogram, 1 << k, limit, escape_bits, MAPPING), k))
+        .min()
+        .unwrap()
+}
+
+// Code `samples[range]` with the order and Rice parameter that give the fewest bits
+fn encode_subframe(samples: &[i32], range: Range<usize>, limit: u32) -> Subframe {
+    let (_, order, k, residuals) = (0..=MAX_ORDER)
+        .map(|order| {
+            let residuals = fixed_residuals(samples, range.clone(), order).expect("orders up to MAX_ORDER");
+            let residuals = Array2::from_shape_vec((range.len(), 1), residuals).unwrap();
+            let (bits, k) = best_rice_parameter(&residuals, limit);
+            (bits, order, k, residuals)
+        })
+        .min_by_key(|(bits, order, k, _)| (*bits, *order, *k))
+        .unwrap();
+    Subframe { order, residuals: custom_encode_with_m(&residuals, 1 << k, limit, MAPPING) }
+}
+
+impl AudioFile {
+    /// Compress `pcm` in blocks of `block_size` samples per channel, choosing the order
+    /// and Rice parameter of every block and channel.
+    pub fn encode(pcm: &Pcm, block_size: u32) -> Self {
+        assert!(block_size > 0, "the block size must be positive");
+        let limit = default_limit(BITS_PER_SAMPLE as u8);
+        let frames = pcm.frames();
+        let starts = (0..frames).step_by(block_size as usize);
+        let blocks = starts
+            .map(|start| {
+                let range = start..(start + block_size as usize).min(frames);
+                pcm.channels.iter().map(|samples| encode_subframe(samples, range.clone(), limit)).collect()
+            })
+            .collect();
+        AudioFile { sample_rate: pcm.sample_rate, channels: pcm.channels.len() as u16, frames, block_size, blocks }
+    }
+
+    /// Restore the samples of every channel, block by block.
+    pub fn decode(&self) -> Result<Pcm, AudioError> {
+        let mut channels = vec![Vec::with_capacity(self.frames); self.channels as usize];
+        for block in &self.blocks {
+            for (samples, subframe) in channels.iter_mut().zip(block) {
+                let residuals = subframe.residuals.decode().map_err(|_| FileError::Corrupt("residuals don't decode"))?;
+                restore_samples(samples, &residuals.into_raw_vec(), subframe.order)?;
+            }
+        }
+        Ok(Pcm { sample_rate: self.sample_rate, channels })
+    }
+
+    // Size of the coded residuals.
+    pub fn bits(&self) -> usize {
+        self.blocks.iter().flatten().map(|subframe| subframe.residuals.bits()).sum()
+    }
+
+    /// Serialize the file (see the layout above).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, AudioError> {
+        let mut bytes = write_prologue(MAGIC, VERSION);
+        bytes.extend_from_slice(&self.channels.to_le_bytes());
+        bytes.extend_from_slice(&self.sample_rate.to_le_bytes());
+        bytes.extend_from_slice(&(self.frames as u64).to_le_bytes());
+        bytes.extend_from_slice(&self.block_size.to_le_bytes());
+        for subframe in self.blocks.iter().flatten() {
+            let encoded = &subframe.residuals;
+            let limit = u8::try_from(encoded.limit).map_err(|_| FileError::Corrupt("LIMIT above 255"))?;
+            bytes.extend_from_slice(&[subframe.order, subframe.k() as u8, limit, encoded.escape_bits as u8]);
+            bytes.extend_from_slice(&(encoded.bits() as u64).to_le_bytes());
+            bytes.extend_from_slice(encoded.encoded_bits.as_bytes());
+        }
+        write_checksum(&mut bytes);
+        Ok(bytes)
+    }
+
+    /// Parse a file written by `to_bytes`, checking the checksum first.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, AudioError> {
+        let (version, mut fields) = read_prologue(bytes, MAGIC)?;
+        if version != VERSION {
+            return Err(FileError::UnsupportedVersion(version).into());
+        }
+        let channels = fields.u16()?;
+        let sample_rate = fields.u32()?;
+        let frames = usize::try_from(fields.u64()?).map_err(|_| FileError::Corrupt("too many samples"))?;
+        let block_size = fields.u32()?;
+        if block_size == 0 {
+            return Err(FileError::Corrupt("invalid block size").into());
+        }
+        if channels == 0 && frames > 0 {
+            return Err(FileError::Corrupt("samples without channels").into());
+        }
+
+        let mut blocks = Vec::new();
+        for start in (0..frames).step_by(block_size as usize) {
+            let len = (frames - start).min(block_size as usize);
+            let mut block = Vec::with_capacity(channels as usize);
+            for _ in 0..channels {
+                let order = fields.u8()?;
+                let k = fields.u8()? as u32;
+                let limit = fields.u8()? as u32;
+                let escape_bits = fields.u8()? as u32;
+                if order > MAX_ORDER || k > MAX_RICE_PARAMETER || escape_bits == 0 || limit < escape_bits + 2 {
+                    return Err(FileError::Corrupt("invalid subframe parameters").into());
+                }
+                let payload_bits = fields.u64()? as usize;
+                // Every residual takes at least one bit
+                if payload_bits < len {
+                    return Err(FileError::Corrupt("subframe payload too short").into());
+                }
+                let payload = fields.take(payload_bits.div_ceil(8))?.to_vec();
+                let encoded_bits =
+                    BitBuffer::from_bytes(payload, payload_bits).map_err(|_| FileError::Corrupt("subframe payload too short"))?;
+                block.push(Subframe {
+                    order,
+                    residuals: CustomGolombEncodedImage {
+                        m: 1 << k,
+                        mapping: MAPPING,
+                        limit,
+                        escape_bits,
+                        encoded_bits,
+                        shape: (len, 1),
+                    },
+                });
+            }
+            blocks.push(block);
+        }
+        fields.finish("trailing bytes after the last block")?;
+        Ok(AudioFile { sample_rate, channels, frames, block_size, blocks })
+    }
+}
+
+/// Read a 16-bit integer PCM WAV file.
+pub fn read_wav<P: AsRef<Path>>(path: P) -> Result<Pcm, AudioError> {
+    let mut reader = hound::WavReader::open(path)?;
+    let spec = reader.spec();
+    if spec.bits_per_sample != BITS_PER_SAMPLE || spec.sample_format != hound::SampleFormat::Int {
+        return Err(AudioError::UnsupportedFormat {
+            bits_per_sample: spec.bits_per_sample,
+            float: spec.sample_format == hound::SampleFormat::Float,
+        });
+    }
+    let count = spec.channels as usize;
+    let mut channels = vec![Vec::with_capacity(reader.duration() as usize); count];
+    for (index, sample) in reader.samples::<i16>().enumerate() {
+        channels[index % count].push(sample? as i32);
+    }
+    if channels.iter().any(|samples| samples.len() != channels[0].len()) {
+        return Err(FileError::Corrupt("incomplete frame at the end of the WAV file").into());
+    }
+    Ok(Pcm { sample_rate: spec.sample_rate, channels })
+}
+
+/// Write `pcm` as a 16-bit integer PCM WAV file.
+pub fn write_wav<P: AsRef<Path>>(path: P, pcm: &Pcm) -> Result<(), AudioError> {
+    let spec = hound::WavSpec {
+        channels: pcm.channels.len() as u16,
+        sample_rate: pcm.sample_rate,
+        bits_per_sample: BITS_PER_SAMPLE,
+        sample_format: hound::SampleFormat::Int,
+    };
+    let mut writer = hound::WavWriter::create(path, spec)?;
+    for n in 0..pcm.frames() {
+        for samples in &pcm.channels {
+            let sample = i16::try_from(samples[n]).map_err(|_| FileError::Corrupt("sample out of the 16-bit range"))?;
+            writer.write_sample(sample)?;
+        }
+    }
+    writer.finalize()?;
+    Ok(())
+}
+
+pub fn save_audio_file<P: AsRef<Path>>(path: P, file: &AudioFile) -> Result<(), AudioError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&file.to_bytes()?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_audio_file<P: AsRef<Path>>(path: P) -> Result<AudioFile, AudioError> {
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+    AudioFile::from_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sine(frames: usize) -> Pcm {
+        let channel = |phase: f64| (0..frames).map(|n| ((n as f64 * 0.05 + phase).sin() * 20000.0) as i32).collect();
+        Pcm { sample_rate: 8000, channels: vec![channel(0.0), channel(1.0)] }
+    }
+
+    #[test]
+    fn fixed_predictors_cancel_polynomials() {
+        let coefficients = [3, 5, -2, 1];
+        for degree in 0..coefficients.len() {
+            let samples: Vec<i32> = (0..30i32)
+                .map(|n| coefficients[..=degree].iter().rev().fold(0, |value, c| value * n + c))
+                .collect();
+            for order in 0..=MAX_ORDER {
+                let residuals = fixed_residuals(&samples, 0..samples.len(), order).unwrap();
+                // The first `order` samples are predicted from the zeros before the signal
+                let cancelled = residuals[order as usize..].iter().all(|&r| r == 0);
+                assert_eq!(cancelled, order as usize > degree, "degree {} order {}", degree, order);
+                let mut restored = Vec::new();
+                restore_samples(&mut restored, &residuals, order).unwrap();
+                assert_eq!(restored, samples);
+            }
+        }
+    }
+
+    #[test]
+    fn orders_above_the_maximum_are_errors() {
+        let samples = [1, 2, 3];
+        assert_eq!(fixed_prediction(&samples, 2, MAX_ORDER + 1), None);
+        assert_eq!(fixed_residuals(&samples, 0..3, MAX_ORDER + 1), None);
+        assert!(restore_samples(&mut Vec::new(), &samples, MAX_ORDER + 1).is_err());
+    }
+
+    #[test]
+    fn corrupt_files_are_errors() {
+        let pcm = sine(1000);
+        let mut file = AudioFile::encode(&pcm, 256);
+        assert_eq!(AudioFile::from_bytes(&file.to_bytes().unwrap()).unwrap().decode().unwrap(), pcm);
+
+        // A run of zeroes longer than the escape, behind a valid checksum
+        let residuals = &mut file.blocks[1][0].residuals;
+        let len = residuals.encoded_bits.len();
+        residuals.encoded_bits = BitBuffer::from_bytes(vec![0; len.div_ceil(8)], len).unwrap();
+        let reloaded = AudioFile::from_bytes(&file.to_bytes().unwrap()).unwrap();
+        assert!(matches!(reloaded.decode(), Err(AudioError::File(FileError::Corrupt(_)))));
+
+        // Residuals that take the samples out of the 16-bit range
+        let huge = Array2::from_elem((256, 1), i32::MAX);
+        file.blocks[1][0] = Subframe { order: 4, residuals: custom_encode_with_m(&huge, 1 << 20, 32, MAPPING) };
+        assert!(matches!(file.decode(), Err(AudioError::File(FileError::Corrupt(_)))));
+    }
+}
@@ -0,0 +1,199 @@
+use std::error::Error;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::{Path, PathBuf};
+
+use rayon::prelude::*;
+
+use crate::image_io::open_image;
+use crate::prediction::{predictor_from_name, PREDICTOR_NAMES};
+use crate::report::{Report, ReportFailure};
+
+// Extensions picked up when a directory is given
+pub const IMAGE_EXTENSIONS: [&str; 10] = ["png", "tif", "tiff", "bmp", "jpg", "jpeg", "gif", "pgm", "ppm", "pnm"];
+
+#[derive(Debug)]
+pub enum BatchError {
+    Pattern(glob::PatternError),
+    Io { path: PathBuf, error: std::io::Error },
+    UnknownPredictor(String),
+    ThreadPool(rayon::ThreadPoolBuildError),
+}
+
+impl fmt::Display for BatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BatchError::Pattern(err) => write!(f, "invalid pattern: {}", err),
+            BatchError::Io { path, error } => write!(f, "{}: {}", path.display(), error),
+            BatchError::UnknownPredictor(name) => write!(f, "unknown predictor {}", name),
+            BatchError::ThreadPool(err) => write!(f, "cannot start the thread pool: {}", err),
+        }
+    }
+}
+
+impl Error for BatchError {}
+
+/// Expand directories (their images, not recursively) and glob patterns into a
+/// sorted list of files. Paths that match nothing are kept, so they show up as
+/// failures in the report.
+pub fn collect_images<P: AsRef<str>>(inputs: &[P]) -> Result<Vec<PathBuf>, BatchError> {
+    let mut paths = Vec::new();
+    for input in inputs {
+        let input = input.as_ref();
+        let path = Path::new(input);
+        if path.is_dir() {
+            let entries = path.read_dir().map_err(|error| BatchError::Io { path: path.to_path_buf(), error })?;
+            for entry in entries {
+                let entry = entry.map_err(|error| BatchError::Io { path: path.to_path_buf(), error })?;
+                if is_image(&entry.path()) {
+                    paths.push(entry.path());
+                }
+            }
+        } else if path.exists() {
+            paths.push(path.to_path_buf());
+        } else {
+            let matches: Vec<PathBuf> = glob::glob(input)
+                .map_err(BatchError::Pattern)?
+                .filter_map(Result::ok)
+                .filter(|path| path.is_file())
+                .collect();
+            if matches.is_empty() {
+                paths.push(path.to_path_buf());
+            }
+            paths.extend(matches);
+        }
+    }
+    paths.sort();
+    paths.dedup();
+    Ok(paths)
+}
+
+fn is_image(path: &Path) -> bool {
+    path.is_file()
+        && path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Analyze every image on `jobs` threads (0 = one per CPU).
+///
+/// Images that can't be read or analyzed (including panics in a codec) become
+/// failures of the report instead of stopping the batch.
+pub fn batch_report(paths: &[PathBuf], predictor_name: &str, jobs: usize) -> Result<Report, BatchError> {
+    if !PREDICTOR_NAMES.contains(&predictor_name) {
+        return Err(BatchError::UnknownPredictor(predictor_name.to_string()));
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build()
+        .map_err(BatchError::ThreadPool)?;
+    let results: Vec<Result<Report, ReportFailure>> =
+        pool.install(|| paths.par_iter().map(|path| analyze_file(path, predictor_name)).collect());
+
+    let mut report = Report::default();
+    for result in results {
+        match result {
+            Ok(mut image) => report.append(&mut image),
+            Err(failure) => report.failures.push(failure),
+        }
+    }
+    Ok(report)
+}
+
+// Report of a single image (one row, or one per color transform)
+fn analyze_file(path: &Path, predictor_name: &str) -> Result<Report, ReportFailure> {
+    let name = path.display().to_string();
+    let failure = |error: String| ReportFailure { image: name.clone(), error };
+    let source = open_image(path, None).map_err(|err| failure(err.to_string()))?;
+    // Predictors aren't shared between threads: every task builds its own
+    let predictor = predictor_from_name(predictor_name, source.bit_depth()).unwrap();
+    catch_panics(&name, || {
+        let mut report = Report::default();
+        report.add_image(&name, &source.image, source.bit_depth(), predictor.as_ref());
+        report
+    })
+}
+
+// Run `analyze`, turning a panic into a failure of the image `name`
+fn catch_panics(name: &str, analyze: impl FnOnce() -> Report) -> Result<Report, ReportFailure> {
+    panic::catch_unwind(AssertUnwindSafe(analyze)).map_err(|payload| {
+        let message = payload
+            .downcast_ref::<String>()
+            .cloned()
+            .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+            .unwrap_or_else(|| "unknown error".to_string());
+        ReportFailure { image: name.to_string(), error: format!("panicked: {}", message) }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use image::{DynamicImage, GrayImage, Luma};
+
+    use super::*;
+    use crate::image_io::save_image;
+
+    // A directory with two images, a text file and a subdirectory holding a third image
+    fn images() -> tempfile::TempDir {
+        let dir = tempfile::tempdir().unwrap();
+        let img = DynamicImage::ImageLuma8(GrayImage::from_fn(8, 6, |x, y| Luma([(x * 30 + y) as u8])));
+        save_image(dir.path().join("a.pgm"), &img, 255, None).unwrap();
+        save_image(dir.path().join("b.PNG"), &img, 255, None).unwrap();
+        fs::write(dir.path().join("notes.txt"), "not an image").unwrap();
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        save_image(dir.path().join("sub").join("c.pgm"), &img, 255, None).unwrap();
+        dir
+    }
+
+    fn input(path: &Path) -> String {
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn collects_directories_globs_and_missing_paths() {
+        let dir = images();
+        let (a, b) = (dir.path().join("a.pgm"), dir.path().join("b.PNG"));
+        assert_eq!(collect_images(&[input(dir.path())]).unwrap(), [a.clone(), b.clone()]);
+        assert_eq!(collect_images(&[input(&dir.path().join("*.pgm"))]).unwrap(), [dir.path().join("a.pgm")]);
+        assert_eq!(collect_images(&[input(&dir.path().join("*").join("*.pgm"))]).unwrap(), [dir.path().join("sub").join("c.pgm")]);
+
+        // Every file once, sorted
+        let inputs = [input(&b), input(dir.path()), input(&dir.path().join("?.pgm")), input(&a)];
+        assert_eq!(collect_images(&inputs).unwrap(), [a, b]);
+
+        // Paths that match nothing are kept
+        let missing = [input(&dir.path().join("missing.png")), input(&dir.path().join("*.tif"))];
+        let mut expected: Vec<PathBuf> = missing.iter().map(PathBuf::from).collect();
+        expected.sort();
+        assert_eq!(collect_images(&missing).unwrap(), expected);
+
+        assert!(matches!(collect_images(&[input(&dir.path().join("[x"))]), Err(BatchError::Pattern(_))));
+    }
+
+    #[test]
+    fn reports_failures_and_carries_on() {
+        let dir = images();
+        fs::write(dir.path().join("corrupt.pgm"), "P5\n8 6\n255\n").unwrap();
+        let paths = collect_images(&[input(dir.path()), input(&dir.path().join("missing.png"))]).unwrap();
+        let report = batch_report(&paths, "med", 2).unwrap();
+        assert_eq!(report.images.len(), 2);
+        let failures: Vec<&str> = report.failures.iter().map(|failure| failure.image.as_str()).collect();
+        assert_eq!(failures, [input(&dir.path().join("corrupt.pgm")), input(&dir.path().join("missing.png"))]);
+
+        assert!(matches!(batch_report(&paths, "nope", 1), Err(BatchError::UnknownPredictor(_))));
+    }
+
+    #[test]
+    fn panics_become_failures() {
+        let report = catch_panics("ok.png", Report::default).unwrap();
+        assert!(report.images.is_empty() && report.failures.is_empty());
+
+        let failure = catch_panics("static.png", || panic!("static message")).unwrap_err();
+        assert_eq!((failure.image.as_str(), failure.error.as_str()), ("static.png", "panicked: static message"));
+        let failure = catch_panics("formatted.png", || panic!("{} values", 3)).unwrap_err();
+        assert_eq!((failure.image.as_str(), failure.error.as_str()), ("formatted.png", "panicked: 3 values"));
+    }
+}
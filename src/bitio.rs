@@ -0,0 +1,351 @@
+use std::error::Error;
+use std::fmt;
+
+use ndarray::Array2;
+
+/// Packed, MSB-first bit storage.
+///
+/// `bytes` holds the bits eight to a byte, with the first bit in the most
+/// significant position. The last byte is zero-padded; `len` keeps the
+/// exact number of meaningful bits.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct BitBuffer {
+    bytes: Vec<u8>,
+    len: usize,
+}
+
+impl BitBuffer {
+    /// Wrap already packed bytes holding exactly `len` meaningful bits.
+    ///
+    /// Fails if `bytes` is too short for `len` bits (e.g. a corrupt length field).
+    pub fn from_bytes(bytes: Vec<u8>, len: usize) -> Result<Self, BitLengthError> {
+        check_bit_length(&bytes, len)?;
+        Ok(BitBuffer { bytes, len })
+    }
+
+    /// Number of bits stored (without padding).
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Whether no bits are stored.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The packed bytes, including the zero padding of the last byte.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Take the packed bytes, including the zero padding of the last byte.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+
+    /// A reader of the stored bits, padding excluded.
+    pub fn reader(&self) -> BitReader<'_> {
+        BitReader { bytes: &self.bytes, pos: 0, len: self.len }
+    }
+
+    /// Expand to one bit per byte (useful for `print_as_bits`).
+    pub fn to_bits(&self) -> Vec<u8> {
+        let mut reader = self.reader();
+        let mut bits = Vec::with_capacity(self.len);
+        while let Some(bit) = reader.read_bit() {
+            bits.push(bit);
+        }
+        bits
+    }
+}
+
+/// Writes bits MSB-first into a growing byte vector.
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    bytes: Vec<u8>,
+    current: u8,
+    // Number of bits already stored in `current` (0..8)
+    filled: u32,
+}
+
+impl BitWriter {
+    /// An empty writer.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write a single bit. Only the least significant bit of `bit` is used.
+    pub fn write_bit(&mut self, bit: u8) {
+        self.current = (self.current << 1) | (bit & 1);
+        self.filled += 1;
+        if self.filled == 8 {
+            self.bytes.push(self.current);
+            self.current = 0;
+            self.filled = 0;
+        }
+    }
+
+    /// Write the `count` least significant bits of `value`, most significant first.
+    pub fn write_bits(&mut self, value: u64, count: u32) {
+        assert!(count <= 64, "cannot write more than 64 bits at once");
+        for i in (0..count).rev() {
+            self.write_bit(((value >> i) & 1) as u8);
+        }
+    }
+
+    /// Write `count` copies of the same bit (e.g. the zeroes of a unary code).
+    pub fn write_repeated(&mut self, bit: u8, count: usize) {
+        for _ in 0..count {
+            self.write_bit(bit);
+        }
+    }
+
+    /// Write a sequence of bits stored one per byte.
+    pub fn write_slice(&mut self, bits: &[u8]) {
+        for &bit in bits {
+            self.write_bit(bit);
+        }
+    }
+
+    /// Number of bits written so far.
+    pub fn position(&self) -> usize {
+        self.bytes.len() * 8 + self.filled as usize
+    }
+
+    /// Pad with zeroes up to the next byte boundary.
+    pub fn flush(&mut self) {
+        if self.filled != 0 {
+            self.current <<= 8 - self.filled;
+            self.bytes.push(self.current);
+            self.current = 0;
+            self.filled = 0;
+        }
+    }
+
+    /// Pad the last byte and return the packed buffer with its exact bit length.
+    pub fn finish(mut self) -> BitBuffer {
+        let len = self.position();
+        self.flush();
+        BitBuffer { bytes: self.bytes, len }
+    }
+}
+
+/// Reads bits MSB-first from a byte slice.
+#[derive(Debug, Clone)]
+pub struct BitReader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    len: usize,
+}
+
+impl<'a> BitReader<'a> {
+    /// Read every bit of `bytes`, padding included.
+    pub fn new(bytes: &'a [u8]) -> Self {
+        BitReader { bytes, pos: 0, len: bytes.len() * 8 }
+    }
+
+    /// Read only the first `len` bits of `bytes`.
+    ///
+    /// Fails if `bytes` is too short for `len` bits.
+    pub fn with_len(bytes: &'a [u8], len: usize) -> Result<Self, BitLengthError> {
+        check_bit_length(bytes, len)?;
+        Ok(BitReader { bytes, pos: 0, len })
+    }
+
+    /// Read the next bit, or None at the end of the stream.
+    pub fn read_bit(&mut self) -> Option<u8> {
+        if self.pos >= self.len {
+            return None;
+        }
+        let byte = self.bytes[self.pos / 8];
+        let bit = (byte >> (7 - self.pos % 8)) & 1;
+        self.pos += 1;
+        Some(bit)
+    }
+
+    /// Read `count` bits into the low bits of a u64, most significant first.
+    pub fn read_bits(&mut self, count: u32) -> Option<u64> {
+        assert!(count <= 64, "cannot read more than 64 bits at once");
+        if self.remaining() < count as usize {
+            return None;
+        }
+        let mut value = 0u64;
+        for _ in 0..count {
+            value = (value << 1) | self.read_bit()? as u64;
+        }
+        Some(value)
+    }
+
+    /// Count and consume bits equal to `bit` up to (and including) the first different one.
+    pub fn read_run(&mut self, bit: u8) -> Option<usize> {
+        let mut run = 0;
+        while self.read_bit()? == bit {
+            run += 1;
+        }
+        Some(run)
+    }
+
+    /// Skip to the next byte boundary.
+    pub fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+        self.pos = self.pos.min(self.len);
+    }
+
+    /// Number of bits consumed so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// Number of bits left to read.
+    pub fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.len
+    }
+}
+
+/// A bit length longer than the bytes holding the bits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BitLengthError {
+    pub len: usize,
+    pub bytes: usize,
+}
+
+impl fmt::Display for BitLengthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} bits don't fit in {} bytes", self.len, self.bytes)
+    }
+}
+
+impl Error for BitLengthError {}
+
+// Check that `bytes` can hold `len` bits
+fn check_bit_length(bytes: &[u8], len: usize) -> Result<(), BitLengthError> {
+    if len.div_ceil(8) > bytes.len() {
+        return Err(BitLengthError { len, bytes: bytes.len() });
+    }
+    Ok(())
+}
+
+/// Why a bitstream can't be decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The stream ends in the middle of a code, or holds a code the encoder never writes
+    InvalidCode { position: usize },
+    /// The code table or the coding parameters are invalid
+    InvalidHeader,
+    /// The stream holds a different number of values than expected
+    WrongLength { expected: usize, found: usize },
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DecodeError::InvalidCode { position } => write!(f, "invalid or truncated code at bit {}", position),
+            DecodeError::InvalidHeader => write!(f, "invalid code table or parameters"),
+            DecodeError::WrongLength { expected, found } => {
+                write!(f, "decoded {} values instead of {}", found, expected)
+            }
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Arrange decoded values into a matrix of `shape`, checking their number.
+pub fn values_to_matrix<T>(shape: (usize, usize), values: Vec<T>) -> Result<Array2<T>, DecodeError> {
+    let found = values.len();
+    Array2::from_shape_vec(shape, values).map_err(|_| DecodeError::WrongLength { expected: shape.0.saturating_mul(shape.1), found })
+}
+
+/// Pack bits stored one per byte into MSB-first bytes, zero-padding the last one.
+pub fn pack_bits(encoded_bits: &[u8]) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    writer.write_slice(encoded_bits);
+    writer.finish().into_bytes()
+}
+
+/// Inverse of `pack_bits`: expand the first `len` bits of `bytes` to one bit per byte.
+pub fn unpack_bits(bytes: &[u8], len: usize) -> Result<Vec<u8>, BitLengthError> {
+    Ok(BitBuffer::from_bytes(bytes.to_vec(), len)?.to_bits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn partial_final_byte_round_trips() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b1011, 4);
+        writer.write_bit(1);
+        assert_eq!(writer.position(), 5);
+        let buffer = writer.finish();
+        assert_eq!(buffer.len(), 5);
+        assert_eq!(buffer.as_bytes(), &[0b1011_1000]);
+        assert_eq!(buffer.to_bits(), vec![1, 0, 1, 1, 1]);
+
+        // The padding isn't read back
+        let mut reader = buffer.reader();
+        assert_eq!(reader.read_bits(5), Some(0b10111));
+        assert!(reader.is_empty());
+        assert_eq!(reader.read_bit(), None);
+    }
+
+    #[test]
+    fn write_bits_widths() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(u64::MAX, 0);
+        assert_eq!(writer.position(), 0);
+        writer.write_bits(0xDEAD_BEEF, 32);
+        writer.write_bits(1, 1);
+        writer.write_bits(0x0123_4567_89AB_CDEF, 64);
+        let buffer = writer.finish();
+        assert_eq!(buffer.len(), 97);
+
+        let mut reader = buffer.reader();
+        assert_eq!(reader.read_bits(0), Some(0));
+        assert_eq!(reader.read_bits(32), Some(0xDEAD_BEEF));
+        assert_eq!(reader.read_bits(1), Some(1));
+        assert_eq!(reader.read_bits(64), Some(0x0123_4567_89AB_CDEF));
+        assert_eq!(reader.remaining(), 0);
+    }
+
+    #[test]
+    fn reading_past_the_end() {
+        let buffer = BitBuffer::from_bytes(vec![0b0001_0000, 0xFF], 12).unwrap();
+        let mut reader = buffer.reader();
+        assert_eq!(reader.read_bits(13), None);
+        // A failed read consumes nothing
+        assert_eq!(reader.position(), 0);
+        assert_eq!(reader.read_run(0), Some(3));
+        assert_eq!(reader.read_bits(8), Some(0b0000_1111));
+        assert_eq!(reader.read_run(1), None);
+        assert!(reader.is_empty());
+
+        let mut reader = BitReader::new(&[]);
+        assert_eq!(reader.read_bit(), None);
+        reader.align();
+        assert_eq!(reader.position(), 0);
+    }
+
+    #[test]
+    fn pack_and_unpack() {
+        let bits = vec![1, 1, 0, 1, 0, 0, 0, 1, 1];
+        let packed = pack_bits(&bits);
+        assert_eq!(packed, vec![0b1101_0001, 0b1000_0000]);
+        assert_eq!(unpack_bits(&packed, bits.len()).unwrap(), bits);
+    }
+
+    #[test]
+    fn lengths_beyond_the_bytes_are_errors() {
+        let error = BitLengthError { len: 17, bytes: 2 };
+        assert_eq!(BitBuffer::from_bytes(vec![0; 2], 17), Err(error.clone()));
+        assert_eq!(BitReader::with_len(&[0; 2], 17).err(), Some(error.clone()));
+        assert_eq!(unpack_bits(&[0; 2], 17), Err(error));
+        assert_eq!(BitBuffer::from_bytes(vec![0; 2], 16).map(|buffer| buffer.len()), Ok(16));
+        assert!(BitReader::with_len(&[], usize::MAX).is_err());
+    }
+}
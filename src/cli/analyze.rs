@@ -0,0 +1,69 @@
+use eet51_lab3::report::Report;
+use eet51_lab3::batch::{batch_report, collect_images};
+use eet51_lab3::color::{check_color, plane_count, ColorTransform};
+use eet51_lab3::image_io::open_image;
+use std::error::Error;
+use std::path::PathBuf;
+use clap::ValueEnum;
+use super::lab::{color_tasks, complete_tasks};
+use super::{image_name, predictor};
+
+#[derive(Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ReportFormat {
+    Json,
+    Csv,
+    Markdown,
+}
+
+pub fn analyze(paths: &[PathBuf], predictor_name: &str, format: Option<ReportFormat>) -> Result<(), Box<dyn Error>> {
+    let mut report = Report::default();
+    for path in paths {
+        let source = open_image(path, None)?;
+        let bit_depth = source.bit_depth();
+        let predictor = predictor(predictor_name, bit_depth)?;
+        let img = source.image;
+        if format.is_none() {
+            check_color(&img)?;
+        }
+        match format {
+            None if plane_count(&img) > 1 => color_tasks(&img, bit_depth, &image_name(path), predictor.as_ref())?,
+            None if bit_depth > 8 => complete_tasks(&img.to_luma16(), bit_depth, &image_name(path), predictor.as_ref())?,
+            None => complete_tasks(&img.to_luma8(), bit_depth, &image_name(path), predictor.as_ref())?,
+            Some(_) => report.add_image(&image_name(path), &img, bit_depth, predictor.as_ref()),
+        }
+    }
+    if let Some(format) = format {
+        print!("{}", format_report(&report, format)?);
+    }
+    Ok(())
+}
+
+fn format_report(report: &Report, format: ReportFormat) -> Result<String, Box<dyn Error>> {
+    match format {
+        ReportFormat::Json => Ok(report.to_json()? + "\n"),
+        ReportFormat::Csv => report.to_csv(),
+        ReportFormat::Markdown => report.to_markdown(),
+    }
+}
+
+pub fn batch(
+    inputs: &[String],
+    predictor_name: &str,
+    format: ReportFormat,
+    jobs: usize,
+    output: Option<PathBuf>,
+) -> Result<(), Box<dyn Error>> {
+    let paths = collect_images(inputs)?;
+    let report = batch_report(&paths, predictor_name, jobs)?;
+    for failure in &report.failures {
+        eprintln!("{}: {}", failure.image, failure.error);
+    }
+    let analyzed = report.images.len() + report.colors.len() / ColorTransform::ALL.len();
+    eprintln!("{} images analyzed, {} failed", analyzed, report.failures.len());
+    let formatted = format_report(&report, format)?;
+    match output {
+        Some(path) => std::fs::write(path, formatted)?,
+        None => print!("{}", formatted),
+    }
+    Ok(())
+}
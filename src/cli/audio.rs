@@ -0,0 +1,43 @@
+use eet51_lab3::audio::{load_audio_file, read_wav, save_audio_file, write_wav, AudioFile, BITS_PER_SAMPLE, MAX_ORDER};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+pub fn encode_wav(input: &Path, output: Option<PathBuf>, block_size: u32) -> Result<(), Box<dyn Error>> {
+    let pcm = read_wav(input)?;
+    let file = AudioFile::encode(&pcm, block_size);
+    if file.decode()? != pcm {
+        return Err("decoded samples differ from the input".into());
+    }
+    let output = output.unwrap_or_else(|| input.with_extension("eea"));
+    save_audio_file(&output, &file)?;
+
+    // How often each order was chosen, and the mean Rice parameter
+    let subframes: Vec<_> = file.blocks.iter().flatten().collect();
+    for order in 0..=MAX_ORDER {
+        let count = subframes.iter().filter(|subframe| subframe.order == order).count();
+        println!("Order {}: {} of {} subframes", order, count, subframes.len());
+    }
+    let mean_k = subframes.iter().map(|subframe| subframe.k() as f64).sum::<f64>() / subframes.len() as f64;
+    println!("Mean Rice parameter: {:.4}", mean_k);
+
+    let file_bits = std::fs::metadata(&output)?.len() * 8;
+    let samples = (pcm.frames() * pcm.channels.len()) as f64;
+    println!(
+        "{} -> {}: {} channels, {} Hz, {} bits ({:.4} bits/sample, compression ratio {:.4})",
+        input.display(),
+        output.display(),
+        pcm.channels.len(),
+        pcm.sample_rate,
+        file_bits,
+        file_bits as f64 / samples,
+        samples * BITS_PER_SAMPLE as f64 / file_bits as f64
+    );
+    Ok(())
+}
+
+pub fn decode_wav(input: &Path, output: &Path) -> Result<(), Box<dyn Error>> {
+    let pcm = load_audio_file(input)?.decode()?;
+    write_wav(output, &pcm)?;
+    println!("{} -> {}: {} channels, {} samples per channel", input.display(), output.display(), pcm.channels.len(), pcm.frames());
+    Ok(())
+}
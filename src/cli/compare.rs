@@ -0,0 +1,126 @@
+use eet51_lab3::{universal_codes::universal_encode, ans::{rans_encode, scale_bits_for}, arithmetic::{arithmetic_encode, AdaptiveModel, StaticModel, MAX_ADAPTIVE_SYMBOLS, MAX_STATIC_SYMBOLS}, huffman::{adaptive::{adaptive_huffman_decode, adaptive_huffman_encode}, huffman_encode, length_limited::huffman_encode_limited}, histogram::Histogram, golomb::{adaptive::{adaptive_golomb_encode, GolombContexts}, encode::{custom_encode_with_limit, custom_encode_with_m, default_limit}, mapping::SignedMapping, search::golomb_cost_curve}};
+use eet51_lab3::prediction::{pred_err_alphabet, prediction_err_matrix, Predictor};
+use eet51_lab3::depth::{gray_plane, GrayBuffer, Sample};
+use eet51_lab3::color::{check_color, plane_count};
+use eet51_lab3::image_io::open_image;
+use eet51_lab3::container::{Codec, Payload};
+use eet51_lab3::wavelet::{max_levels, subbands, wavelet_forward, wavelet_inverse, DEFAULT_LEVELS};
+use eet51_lab3::verify::{verify_equality_arrays, MismatchError};
+use ndarray::Array2;
+use std::error::Error;
+use std::path::PathBuf;
+use super::{code_length_limit, image_name, predictor, UNIVERSAL_CODES};
+
+// Size of the prediction error of `img` (of `bit_depth` bits) with every codec, each
+// round-trip verified.
+fn compare_codecs<T: Sample>(
+    img: &GrayBuffer<T>,
+    bit_depth: u8,
+    predictor: &dyn Predictor,
+) -> Result<Vec<(String, usize)>, Box<dyn Error>> {
+    let limit = default_limit(bit_depth);
+    let prediction_err = prediction_err_matrix(predictor, img);
+    let mut results = Vec::new();
+    let mut add = |codec: String, bits: usize, matches: Result<(), MismatchError>| -> Result<(), Box<dyn Error>> {
+        matches.map_err(|err| format!("{} round-trip failed: {}", codec, err))?;
+        results.push((codec, bits));
+        Ok(())
+    };
+
+    let encoded = custom_encode_with_limit(&prediction_err, limit);
+    add(format!("Golomb (M = {})", encoded.m), encoded.bits(), verify_equality_arrays(&prediction_err, &encoded.decode()?))?;
+    let curve = golomb_cost_curve(&prediction_err, limit, SignedMapping::SignMagnitude, None);
+    let encoded = custom_encode_with_m(&prediction_err, curve.best_m, limit, SignedMapping::SignMagnitude);
+    add(format!("Golomb (optimal M = {})", encoded.m), encoded.bits(), verify_equality_arrays(&prediction_err, &encoded.decode()?))?;
+    for contexts in [GolombContexts::Global, GolombContexts::Gradient] {
+        let encoded = adaptive_golomb_encode(&prediction_err, contexts, bit_depth, limit, SignedMapping::Zigzag);
+        let codec = format!("Adaptive Golomb-Rice ({} contexts)", contexts.name());
+        add(codec, encoded.bits(), verify_equality_arrays(&prediction_err, &encoded.decode()?))?;
+    }
+    for code in UNIVERSAL_CODES {
+        let encoded = universal_encode(&prediction_err, code, SignedMapping::Zigzag);
+        add(code.name(), encoded.bits(), verify_equality_arrays(&prediction_err, &encoded.decode()?))?;
+    }
+
+    let encoded = huffman_encode(prediction_err.iter().copied());
+    let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+    add("Huffman".to_string(), encoded.bits(), verify_equality_arrays(&prediction_err, &decoded))?;
+    let max_code_length = code_length_limit(encoded.table.lengths().len());
+    let encoded = huffman_encode_limited(prediction_err.iter().copied(), max_code_length);
+    let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+    let codec = format!("Huffman (codes <= {} bits)", max_code_length);
+    add(codec, encoded.bits(), verify_equality_arrays(&prediction_err, &decoded))?;
+    let encoded = adaptive_huffman_encode(prediction_err.iter().copied());
+    let decoded = Array2::from_shape_vec(prediction_err.dim(), adaptive_huffman_decode(&encoded)?)?;
+    add("Adaptive Huffman".to_string(), encoded.len(), verify_equality_arrays(&prediction_err, &decoded))?;
+
+    // Transform coding: the subbands of the 5/3 wavelet coded one by one, then decoded,
+    // put back together and inverse transformed
+    let plane = gray_plane(img);
+    let levels = DEFAULT_LEVELS.min(max_levels(plane.dim()));
+    let coefficients = wavelet_forward(&plane, levels);
+    for (name, codec) in [("Golomb", Codec::Golomb), ("Huffman", Codec::Huffman)] {
+        let mut bits = 0;
+        let mut decoded = Array2::zeros(plane.dim());
+        for band in subbands(plane.dim(), levels) {
+            let matrix = band.extract(&coefficients);
+            let payload = Payload::encode(&matrix, bit_depth, codec, None);
+            bits += payload.bits();
+            band.insert(&mut decoded, &payload.decode(matrix.dim())?);
+        }
+        let codec = format!("Wavelet 5/3 ({} levels) + {}", levels, name);
+        add(codec, bits, verify_equality_arrays(&plane, &wavelet_inverse(&decoded, levels)))?;
+    }
+
+    // Coders whose alphabet doesn't fit are left out
+    let histogram = Histogram::from_iter(prediction_err.iter().copied());
+    let symbols = histogram.counts().len();
+    if symbols <= MAX_STATIC_SYMBOLS {
+        let encoded = arithmetic_encode(StaticModel::from_histogram(&histogram), prediction_err.iter().copied())?;
+        let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+        add("Arithmetic (static model)".to_string(), encoded.bits(), verify_equality_arrays(&prediction_err, &decoded))?;
+    }
+    let alphabet = pred_err_alphabet(bit_depth);
+    if alphabet.clone().count() <= MAX_ADAPTIVE_SYMBOLS {
+        let encoded = arithmetic_encode(AdaptiveModel::new(alphabet), prediction_err.iter().copied())?;
+        let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+        add("Arithmetic (adaptive model)".to_string(), encoded.bits(), verify_equality_arrays(&prediction_err, &decoded))?;
+    }
+    if let Some(scale_bits) = scale_bits_for(symbols) {
+        let encoded = rans_encode(prediction_err.iter().copied(), scale_bits);
+        let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+        add("rANS".to_string(), encoded.bits(), verify_equality_arrays(&prediction_err, &decoded))?;
+    }
+    Ok(results)
+}
+
+pub fn compare(paths: &[PathBuf], predictor_name: &str) -> Result<(), Box<dyn Error>> {
+    println!("{:<12} {:<42} {:>10} {:>10} {:>8}", "image", "codec", "bits", "bpp", "ratio");
+    for path in paths {
+        let source = open_image(path, None)?;
+        let bit_depth = source.bit_depth();
+        let predictor = predictor(predictor_name, bit_depth)?;
+        let img = source.image;
+        check_color(&img)?;
+        if plane_count(&img) > 1 {
+            return Err(format!("{}: compare only handles grayscale images (use analyze for color images)", path.display()).into());
+        }
+        let pixels = img.width() as f64 * img.height() as f64;
+        let results = if bit_depth > 8 {
+            compare_codecs(&img.to_luma16(), bit_depth, predictor.as_ref())?
+        } else {
+            compare_codecs(&img.to_luma8(), bit_depth, predictor.as_ref())?
+        };
+        for (codec, bits) in results {
+            println!(
+                "{:<12} {:<42} {:>10} {:>10.4} {:>8.4}",
+                image_name(path),
+                codec,
+                bits,
+                bits as f64 / pixels,
+                pixels * bit_depth as f64 / bits as f64
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,52 @@
+use eet51_lab3::huffman::file::{load_huffman_file, save_huffman_file, HuffmanFile};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+pub fn compress(input: &Path, output: Option<PathBuf>, block_size: u32) -> Result<(), Box<dyn Error>> {
+    let data = std::fs::read(input)?;
+    let file = HuffmanFile::compress(&data, block_size);
+    if file.decompress()? != data {
+        return Err("decompressed data differs from the input".into());
+    }
+    let output = output.unwrap_or_else(|| {
+        let mut name = input.as_os_str().to_owned();
+        name.push(".huf");
+        PathBuf::from(name)
+    });
+    save_huffman_file(&output, &file)?;
+
+    println!("{:>6} {:>10} {:>10} {:>10} {:>12} {:>10}", "block", "bytes", "entropy", "table", "payload", "bits/byte");
+    for (index, stats) in file.block_stats(&data).iter().enumerate() {
+        println!(
+            "{:>6} {:>10} {:>10.4} {:>10} {:>12} {:>10.4}",
+            index,
+            stats.bytes,
+            stats.entropy,
+            stats.table_bits,
+            stats.payload_bits,
+            stats.bits_per_byte()
+        );
+    }
+    let file_bits = std::fs::metadata(&output)?.len() * 8;
+    println!(
+        "{} -> {}: {} bits ({:.4} bits/byte, compression ratio {:.4})",
+        input.display(),
+        output.display(),
+        file_bits,
+        file_bits as f64 / data.len() as f64,
+        (data.len() * 8) as f64 / file_bits as f64
+    );
+    Ok(())
+}
+
+pub fn decompress(input: &Path, output: Option<PathBuf>) -> Result<(), Box<dyn Error>> {
+    let output = match output {
+        Some(output) => output,
+        None if input.extension().is_some_and(|ext| ext == "huf") => input.with_extension(""),
+        None => return Err("no output file given and the input doesn't end in .huf".into()),
+    };
+    let data = load_huffman_file(input)?.decompress()?;
+    std::fs::write(&output, &data)?;
+    println!("{} -> {}: {} bytes", input.display(), output.display(), data.len());
+    Ok(())
+}
@@ -0,0 +1,42 @@
+use eet51_lab3::color::ColorTransform;
+use eet51_lab3::image_io::{open_image, save_image, Endianness, RawFormat};
+use eet51_lab3::container::{load_container, save_container, Codec, Container};
+use std::error::Error;
+use std::path::{Path, PathBuf};
+
+pub fn encode(
+    input: &Path,
+    output: Option<PathBuf>,
+    codec: Codec,
+    predictor_name: &str,
+    m: Option<u32>,
+    transform: ColorTransform,
+    raw: Option<RawFormat>,
+) -> Result<(), Box<dyn Error>> {
+    let source = open_image(input, raw.as_ref())?;
+    let img = &source.image;
+    let container = Container::encode(img, source.maxval, predictor_name, transform, codec, m)?;
+    let output = output.unwrap_or_else(|| input.with_extension("eet"));
+    save_container(&output, &container)?;
+
+    let file_bits = std::fs::metadata(&output)?.len() * 8;
+    let pixels = img.width() as f64 * img.height() as f64;
+    let channels = container.planes.len() as f64;
+    println!(
+        "{} -> {}: {} bits ({:.4} bits/pixel, compression ratio {:.4})",
+        input.display(),
+        output.display(),
+        file_bits,
+        file_bits as f64 / pixels,
+        pixels * channels * container.bit_depth as f64 / file_bits as f64
+    );
+    Ok(())
+}
+
+pub fn decode(input: &Path, output: &Path, raw: Option<Endianness>) -> Result<(), Box<dyn Error>> {
+    let container = load_container(input)?;
+    let img = container.decode_image()?;
+    save_image(output, &img, container.maxval, raw)?;
+    println!("{} -> {}: {}x{} pixels", input.display(), output.display(), img.width(), img.height());
+    Ok(())
+}
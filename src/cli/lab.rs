@@ -0,0 +1,432 @@
+use std::io::Write;
+use eet51_lab3::{universal_codes::universal_encode, ans::{rans_encode, scale_bits_for}, arithmetic::{arithmetic_encode, AdaptiveModel, FrequencyModel, StaticModel, MAX_ADAPTIVE_SYMBOLS, MAX_STATIC_SYMBOLS}, huffman::{adaptive::{adaptive_huffman_decode, adaptive_huffman_encode}, huffman_encode, weighted_path_length, huffman_tree, length_limited::{huffman_encode_limited, limited_weighted_path_length}}, histogram::Histogram, entropy::{histogram_entropy, data_entropy}, golomb::{adaptive::{adaptive_golomb_encode, GolombContexts}, encode::{choose_m, custom_encode_with_limit, custom_encode_with_m, default_limit, ParameterChoice}, mapping::SignedMapping, search::golomb_cost_curve}};
+use eet51_lab3::prediction::{all_predictors, pred_err_alphabet, prediction_err_matrix, reconstruct_gray_clamped, Predictor};
+use eet51_lab3::depth::{gray_plane, max_value, GrayBuffer, Sample};
+use eet51_lab3::report::{ColorReport, SubbandReport};
+use eet51_lab3::wavelet::{max_levels, wavelet_forward, wavelet_inverse, DEFAULT_LEVELS};
+use eet51_lab3::color::{image_planes, ColorTransform};
+use eet51_lab3::container::{load_container, save_container, Codec, Container, Payload};
+use eet51_lab3::verify::{verify_equality_arrays, verify_equality_imgs, MismatchError};
+use image::DynamicImage;
+use ndarray::Array2;
+use std::error::Error;
+use super::{code_length_limit, UNIVERSAL_CODES};
+
+pub fn complete_tasks<T: Sample>(
+    img: &GrayBuffer<T>,
+    bit_depth: u8,
+    img_name: &str,
+    predictor: &dyn Predictor,
+) -> Result<(), Box<dyn Error>> {
+    // Original sizes count bit_depth bits per pixel of I and one more per value of P
+    let (bits_i, bits_p) = (bit_depth as usize, bit_depth as usize + 1);
+    let limit = default_limit(bit_depth);
+    println!("Bit depth: {}", bit_depth);
+    // Task A (Item 2): calculate the relative frequency of each pixel value in the image
+    let histogram = Histogram::from_iter(img.pixels().map(|p| p[0]));
+    // save to csv
+    let path = format!("{}.csv", img_name);
+    histogram.to_csv(&path, T::DEFAULT_MIN_VALUE, num_traits::cast(max_value(bit_depth)).unwrap())?;
+    // Task B (Item 3): calculate the entropy of a pixel modeled as a random variable
+    let entropy = histogram_entropy(&histogram);
+    println!("H(I): {}", entropy);
+    // Task C (Item 4): calculate the prediction error matrix
+    let prediction_err = prediction_err_matrix(predictor, img);
+    // Task D (Item 5): calculate the entropy of the prediction error matrix
+    let matrix_entropy = data_entropy(&prediction_err);
+    println!("H(P): {} (predictor {})", matrix_entropy, predictor.name());
+    // Entropy of the prediction error of every available predictor
+    for other in all_predictors(bit_depth) {
+        println!("H(P) with {}: {}", other.name(), data_entropy(prediction_err_matrix(other.as_ref(), img)));
+    }
+    // Task E (Item 6): reconstruct the image from the prediction error matrix
+    let reconstruction = reconstruct_gray_clamped::<_, T>(predictor, &prediction_err, bit_depth)?;
+    if !reconstruction.is_exact() {
+        println!("Reconstruction needed clamping at {} pixels", reconstruction.clamped.len());
+    }
+    let reconstructed_image = reconstruction.image;
+    //verify that they are equal
+    report_equality("Images", verify_equality_imgs(img, &reconstructed_image))?;
+
+    // Task F (Item 7): create a new image from the absolute value of the prediction error matrix
+    let abs_prediction_err = prediction_err.mapv(|x| x.abs());
+
+    // Task G (Item 8): calculate the entropy of the sign of the prediction error matrix
+    // and of the absolute value of the prediction error matrix
+    let abs_prediction_err_entropy = data_entropy(&abs_prediction_err);
+    let pixel_signs = prediction_err.iter().map(|&x| x < 0);
+    let pixel_signs_entropy = data_entropy(pixel_signs);
+    println!("H(|P|): {}", abs_prediction_err_entropy);
+    println!("H(sgn(P)): {}", pixel_signs_entropy);
+
+    // Task H (Item 9): Use the Custom Golomb encoding function to encode the prediction error matrix
+    println!("================");
+    println!("Custom encoding w/ Golomb");
+    println!("================");
+    let img_pixels = img.width() as usize * img.height() as usize;
+    let custom_encoded = custom_encode_with_limit(&prediction_err, limit);
+    println!("Original image size: {} bits", img_pixels * bits_p);
+    println!("Encoded image size: {} bits", custom_encoded.bits());
+    println!("Compression ratio of P: {}", (img_pixels * bits_p) as f32 / custom_encoded.bits() as f32);
+    println!("m: {}", custom_encoded.m);
+    println!("LIMIT: {} bits (escaped magnitudes on {} bits)", custom_encoded.limit, custom_encoded.escape_bits);
+
+    // This is not a task, but we will decode the encoded bytes and verify that they are equal
+    let custom_decoded = custom_encoded.decode()?;
+    report_equality("Arrays", verify_equality_arrays(&prediction_err, &custom_decoded))?;
+
+    // Golomb code with the optimal (not necessarily power of 2) M for a geometric source
+    let m = choose_m(&prediction_err, SignedMapping::SignMagnitude, ParameterChoice::Geometric);
+    let geometric_encoded = custom_encode_with_m(&prediction_err, m, limit, SignedMapping::SignMagnitude);
+    println!("Encoded image size, geometric M: {} bits", geometric_encoded.bits());
+    println!("Compression ratio of P, geometric M: {}", (img_pixels * bits_p) as f32 / geometric_encoded.bits() as f32);
+    println!("Geometric M: {}", geometric_encoded.m);
+    report_equality("Arrays", verify_equality_arrays(&prediction_err, &geometric_encoded.decode()?))?;
+
+    // Exact cost of every M, computed from the histogram of |P|
+    let curve = golomb_cost_curve(&prediction_err, limit, SignedMapping::SignMagnitude, None);
+    let path = format!("{}_golomb_costs.csv", img_name);
+    curve.to_csv(&path)?;
+    println!("Optimal M: {} ({} bits)", curve.best_m, curve.best_bits);
+    let heuristic_bits = curve.bits_for(custom_encoded.m).unwrap_or(custom_encoded.bits());
+    println!(
+        "Heuristic loss: {} bits ({:.4}%)",
+        heuristic_bits - curve.best_bits,
+        100.0 * (heuristic_bits - curve.best_bits) as f64 / curve.best_bits as f64
+    );
+    let optimal_encoded = custom_encode_with_m(&prediction_err, curve.best_m, limit, SignedMapping::SignMagnitude);
+    check_predicted_cost(optimal_encoded.bits(), curve.best_bits)?;
+    report_equality("Arrays", verify_equality_arrays(&prediction_err, &optimal_encoded.decode()?))?;
+
+    // Bit cost of each signed-to-unsigned mapping, with its own heuristic and optimal M
+    for mapping in SignedMapping::ALL {
+        let m = choose_m(&prediction_err, mapping, ParameterChoice::PowerOfTwo);
+        let curve = golomb_cost_curve(&prediction_err, limit, mapping, None);
+        let encoded = custom_encode_with_m(&prediction_err, curve.best_m, limit, mapping);
+        check_predicted_cost(encoded.bits(), curve.best_bits)?;
+        println!(
+            "Mapping {}: {} bits with heuristic M = {}, {} bits with optimal M = {}",
+            mapping.name(),
+            curve.bits_for(m).unwrap_or_else(|| custom_encode_with_m(&prediction_err, m, limit, mapping).bits()),
+            m,
+            curve.best_bits,
+            curve.best_m
+        );
+        report_equality("Arrays", verify_equality_arrays(&prediction_err, &encoded.decode()?))?;
+    }
+
+    // Adaptive Golomb-Rice: k follows the running mean of each context
+    for contexts in [GolombContexts::Global, GolombContexts::Gradient] {
+        let encoded = adaptive_golomb_encode(&prediction_err, contexts, bit_depth, limit, SignedMapping::Zigzag);
+        println!(
+            "Adaptive Golomb-Rice ({} contexts): {} bits, compression ratio of P: {}",
+            contexts.name(),
+            encoded.bits(),
+            (img_pixels * bits_p) as f32 / encoded.bits() as f32
+        );
+        report_equality("Arrays", verify_equality_arrays(&prediction_err, &encoded.decode()?))?;
+    }
+
+    // Universal codes on the prediction error matrix (zigzag mapping, no sign bits)
+    println!("================");
+    println!("Universal codes");
+    println!("================");
+    for code in UNIVERSAL_CODES {
+        let encoded = universal_encode(&prediction_err, code, SignedMapping::Zigzag);
+        println!(
+            "{}: {} bits, compression ratio of P: {}",
+            code.name(),
+            encoded.bits(),
+            (img_pixels * bits_p) as f32 / encoded.bits() as f32
+        );
+        report_equality("Arrays", verify_equality_arrays(&prediction_err, &encoded.decode()?))?;
+    }
+
+    // Comparison with Huffman encoding
+    println!("================");
+    println!("Huffman encoding");
+    println!("================");
+    // encode original image
+    let encoded = huffman_encode(img.pixels().map(|p| p[0]));
+    // print the bits
+    println!("Original image size (I): {} bits", img_pixels * bits_i);
+    println!("Encoded image size (I): {} bits", encoded.bits());
+    println!("Code table size (I): {} bits", encoded.table_bits);
+
+    // Compression ratio
+    println!("Compression ratio (I): {}", (img_pixels * bits_i) as f32 / encoded.bits() as f32);
+
+    // decode and verify that the Huffman path is lossless
+    let decoded = image_from_raw(img, encoded.decode()?)?;
+    report_equality("Images", verify_equality_imgs(img, &decoded))?;
+
+    let weighted_path_length_orig = weighted_path_length(img.pixels().map(|p| p[0]));
+    println!("Weighted path length (I): {}", weighted_path_length_orig);
+
+    // one-pass adaptive Huffman, compared against the static code above
+    let adaptive = adaptive_huffman_encode(img.pixels().map(|p| p[0]));
+    println!("Adaptive Huffman encoded size (I): {} bits", adaptive.len());
+    println!("Adaptive vs static (I): {:+} bits", adaptive.len() as i64 - encoded.bits() as i64);
+    let decoded = image_from_raw(img, adaptive_huffman_decode(&adaptive)?)?;
+    report_equality("Images", verify_equality_imgs(img, &decoded))?;
+
+    // encode prediction error matrix
+    let encoded = huffman_encode(prediction_err.iter().copied());
+    // print the bits
+    println!("Original image size (P): {} bits", prediction_err.len() * bits_p);
+    println!("Encoded image size (P): {} bits", encoded.bits());
+    println!("Code table size (P): {} bits", encoded.table_bits);
+
+    // Compression ratio
+    println!("Compression ratio of (P): {}", (prediction_err.len() * bits_p) as f32 / encoded.bits() as f32);
+
+    let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+    report_equality("Arrays", verify_equality_arrays(&prediction_err, &decoded))?;
+
+    let weighted_path_length_pred_err = weighted_path_length(prediction_err.iter());
+    println!("Weighted path length of (P): {}", weighted_path_length_pred_err);
+
+    let adaptive = adaptive_huffman_encode(prediction_err.iter().copied());
+    println!("Adaptive Huffman encoded size (P): {} bits", adaptive.len());
+    println!("Adaptive vs static (P): {:+} bits", adaptive.len() as i64 - encoded.bits() as i64);
+    let decoded = Array2::from_shape_vec(prediction_err.dim(), adaptive_huffman_decode(&adaptive)?)?;
+    report_equality("Arrays", verify_equality_arrays(&prediction_err, &decoded))?;
+    println!("Max code length of (P): {} bits", encoded.table.max_length());
+
+    // Length-limited codes for the prediction error matrix (long enough for every symbol)
+    let max_code_length = code_length_limit(encoded.table.lengths().len());
+    let limited_wpl = limited_weighted_path_length(prediction_err.iter(), max_code_length);
+    println!("Weighted path length of (P), codes <= {} bits: {}", max_code_length, limited_wpl);
+    println!(
+        "Increase over unconstrained: {} ({:.4}%)",
+        limited_wpl - weighted_path_length_pred_err,
+        100.0 * (limited_wpl - weighted_path_length_pred_err) / weighted_path_length_pred_err
+    );
+    let encoded_limited = huffman_encode_limited(prediction_err.iter().copied(), max_code_length);
+    println!("Encoded image size (P), codes <= {} bits: {} bits", max_code_length, encoded_limited.bits());
+    let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded_limited.decode()?)?;
+    report_equality("Arrays", verify_equality_arrays(&prediction_err, &decoded))?;
+
+    // Comparison with arithmetic coding (deep images can have too many symbols for the models)
+    println!("================");
+    println!("Arithmetic encoding");
+    println!("================");
+    let pixel_symbols = histogram.counts().len();
+    if pixel_symbols <= MAX_STATIC_SYMBOLS {
+        let encoded = arithmetic_encode(StaticModel::from_histogram(&histogram), img.pixels().map(|p| p[0]))?;
+        println!("Encoded image size (I), static model: {} bits", encoded.bits());
+        println!("Frequency table size (I), static model: {} bits", encoded.model.table_bits());
+        println!("Bits per symbol (I), static model: {} (H(I) = {})", encoded.bits_per_symbol(), entropy);
+        let decoded = image_from_raw(img, encoded.decode()?)?;
+        report_equality("Images", verify_equality_imgs(img, &decoded))?;
+    } else {
+        print_skipped("Static model (I)", pixel_symbols);
+    }
+
+    let max = max_value(bit_depth);
+    let pixel_alphabet = max as usize + 1;
+    if pixel_alphabet <= MAX_ADAPTIVE_SYMBOLS {
+        let alphabet = (0..=max).map(|value| num_traits::cast::<i32, T>(value).unwrap());
+        let encoded = arithmetic_encode(AdaptiveModel::new(alphabet), img.pixels().map(|p| p[0]))?;
+        println!("Encoded image size (I), adaptive model: {} bits", encoded.bits());
+        println!("Bits per symbol (I), adaptive model: {} (H(I) = {})", encoded.bits_per_symbol(), entropy);
+        let decoded = image_from_raw(img, encoded.decode()?)?;
+        report_equality("Images", verify_equality_imgs(img, &decoded))?;
+    } else {
+        print_skipped("Adaptive model (I)", pixel_alphabet);
+    }
+
+    let pred_err_histogram = Histogram::from_iter(prediction_err.iter().copied());
+    let pred_err_symbols = pred_err_histogram.counts().len();
+    if pred_err_symbols <= MAX_STATIC_SYMBOLS {
+        let encoded = arithmetic_encode(StaticModel::from_histogram(&pred_err_histogram), prediction_err.iter().copied())?;
+        println!("Encoded image size (P), static model: {} bits", encoded.bits());
+        println!("Frequency table size (P), static model: {} bits", encoded.model.table_bits());
+        println!("Bits per symbol (P), static model: {} (H(P) = {})", encoded.bits_per_symbol(), matrix_entropy);
+        let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+        report_equality("Arrays", verify_equality_arrays(&prediction_err, &decoded))?;
+    } else {
+        print_skipped("Static model (P)", pred_err_symbols);
+    }
+
+    let alphabet = pred_err_alphabet(bit_depth);
+    if alphabet.clone().count() <= MAX_ADAPTIVE_SYMBOLS {
+        let encoded = arithmetic_encode(AdaptiveModel::new(alphabet), prediction_err.iter().copied())?;
+        println!("Encoded image size (P), adaptive model: {} bits", encoded.bits());
+        println!("Bits per symbol (P), adaptive model: {} (H(P) = {})", encoded.bits_per_symbol(), matrix_entropy);
+        let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+        report_equality("Arrays", verify_equality_arrays(&prediction_err, &decoded))?;
+    } else {
+        print_skipped("Adaptive model (P)", alphabet.count());
+    }
+
+    // Comparison with rANS
+    println!("================");
+    println!("rANS encoding");
+    println!("================");
+    match scale_bits_for(pixel_symbols) {
+        Some(scale_bits) => {
+            let encoded = rans_encode(img.pixels().map(|p| p[0]), scale_bits);
+            println!("Original image size (I): {} bits", img_pixels * bits_i);
+            println!("Encoded image size (I): {} bits", encoded.bits());
+            println!("Frequency table size (I): {} bits", encoded.table_bits);
+            println!("Compression ratio (I): {}", (img_pixels * bits_i) as f32 / encoded.bits() as f32);
+            let decoded = image_from_raw(img, encoded.decode()?)?;
+            report_equality("Images", verify_equality_imgs(img, &decoded))?;
+        }
+        None => print_skipped("rANS (I)", pixel_symbols),
+    }
+
+    match scale_bits_for(pred_err_symbols) {
+        Some(scale_bits) => {
+            let encoded = rans_encode(prediction_err.iter().copied(), scale_bits);
+            println!("Original image size (P): {} bits", prediction_err.len() * bits_p);
+            println!("Encoded image size (P): {} bits", encoded.bits());
+            println!("Frequency table size (P): {} bits", encoded.table_bits);
+            println!("Compression ratio of (P): {}", (prediction_err.len() * bits_p) as f32 / encoded.bits() as f32);
+            let decoded = Array2::from_shape_vec(prediction_err.dim(), encoded.decode()?)?;
+            report_equality("Arrays", verify_equality_arrays(&prediction_err, &decoded))?;
+        }
+        None => print_skipped("rANS (P)", pred_err_symbols),
+    }
+
+    // Transform coding: the subbands of the 5/3 wavelet, each coded on its own, next to P
+    println!("================");
+    println!("Wavelet 5/3 transform");
+    println!("================");
+    let plane = gray_plane(img);
+    let levels = DEFAULT_LEVELS.min(max_levels(plane.dim()));
+    let coefficients = wavelet_forward(&plane, levels);
+    report_equality("Arrays", verify_equality_arrays(&plane, &wavelet_inverse(&coefficients, levels)))?;
+    let bands = SubbandReport::subbands(img_name, &plane, levels, bit_depth);
+    for band in &bands {
+        println!(
+            "{} ({}x{}): H = {}, Golomb: {} bits, Huffman: {} bits",
+            band.subband, band.width, band.height, band.entropy, band.golomb_bits, band.huffman_bits
+        );
+    }
+    let wavelet_entropy = bands.iter().map(|band| band.entropy * band.coefficients() as f64).sum::<f64>() / img_pixels as f64;
+    let wavelet_golomb: usize = bands.iter().map(|band| band.golomb_bits).sum();
+    let wavelet_huffman: usize = bands.iter().map(|band| band.huffman_bits).sum();
+    let predictive_huffman = Payload::encode(&prediction_err, bit_depth, Codec::Huffman, None).bits();
+    println!("H(W): {} ({} levels), H(P): {} (predictor {})", wavelet_entropy, levels, matrix_entropy, predictor.name());
+    println!("Golomb: {} bits with the wavelet, {} bits with prediction", wavelet_golomb, curve.best_bits);
+    println!("Huffman: {} bits with the wavelet, {} bits with prediction", wavelet_huffman, predictive_huffman);
+
+    // Compressed files: write P with Golomb and with Huffman, read them back and rebuild the image
+    println!("================");
+    println!("Compressed files");
+    println!("================");
+    let payloads = [
+        ("golomb", Payload::Golomb(custom_encoded)),
+        ("huffman", Payload::Huffman(huffman_encode(prediction_err.iter().copied()))),
+    ];
+    for (codec, payload) in payloads {
+        let container = Container {
+            width: img.width(),
+            height: img.height(),
+            bit_depth,
+            maxval: max_value(bit_depth) as u16,
+            predictor: predictor.name(),
+            transform: ColorTransform::None,
+            planes: vec![payload],
+        };
+        let path = format!("{}_{}.eet", img_name, codec);
+        save_container(&path, &container)?;
+        let file_bits = std::fs::metadata(&path)?.len() * 8;
+        println!("{}: {} bits, compression ratio: {}", path, file_bits, (img_pixels * bits_i) as f32 / file_bits as f32);
+        let decoded = T::gray_image(&load_container(&path)?.decode_image()?);
+        report_equality("Images", verify_equality_imgs(img, &decoded))?;
+    }
+
+    let huffman_tree = huffman_tree(prediction_err.iter());
+    // save to a file 
+    let path = format!("{}_huffman_tree.dot", img_name);
+    // use fmt::Display to print the tree to file
+    let mut file = std::fs::File::create(path)?;
+    write!(file, "{}", huffman_tree)?;
+    Ok(())
+}
+
+// Lab report of an RGB(A) image: entropy of every channel, of the planes of every
+// color transform and of their prediction error, and compressed files
+pub fn color_tasks(img: &DynamicImage, bit_depth: u8, img_name: &str, predictor: &dyn Predictor) -> Result<(), Box<dyn Error>> {
+    let channels = image_planes(img, ColorTransform::None)?;
+    for (name, channel) in ["R", "G", "B", "A"].iter().zip(&channels) {
+        println!("H({}): {}", name, data_entropy(channel));
+    }
+
+    let pixels = img.width() as f32 * img.height() as f32;
+    for transform in ColorTransform::ALL {
+        println!("================");
+        println!("Color transform: {}", transform.name());
+        println!("================");
+        let report = ColorReport::new(img_name, img, bit_depth, predictor, transform)?;
+        let entropies = [
+            (report.entropy_plane_0, report.entropy_p_0),
+            (report.entropy_plane_1, report.entropy_p_1),
+            (report.entropy_plane_2, report.entropy_p_2),
+        ];
+        for (name, (entropy, entropy_p)) in transform.plane_names().iter().zip(entropies) {
+            println!("H({}): {}, H(P) of {}: {} (predictor {})", name, entropy, name, entropy_p, predictor.name());
+        }
+        if let Some(entropy_p) = report.entropy_p_a {
+            println!("H(P) of A: {}", entropy_p);
+        }
+        println!("Golomb: {} bits ({} bits/pixel)", report.golomb_file_bits, report.golomb_bpp);
+        println!("Huffman: {} bits ({} bits/pixel)", report.huffman_file_bits, report.huffman_bpp);
+        if !report.exact {
+            return Err(format!("decoded image is not bit-exact with the {} transform", transform.name()).into());
+        }
+        println!("Decoded images are bit-exact");
+    }
+
+    // Compressed files with YCoCg-R, read back and compared with the image
+    println!("================");
+    println!("Compressed files");
+    println!("================");
+    let sample_bits = bit_depth as f32;
+    for (name, codec) in [("golomb", Codec::Golomb), ("huffman", Codec::Huffman)] {
+        let container = Container::encode(img, max_value(bit_depth) as u16, &predictor.name(), ColorTransform::YCoCgR, codec, None)?;
+        let path = format!("{}_{}.eet", img_name, name);
+        save_container(&path, &container)?;
+        let file_bits = std::fs::metadata(&path)?.len() * 8;
+        println!("{}: {} bits, compression ratio: {}", path, file_bits, pixels * sample_bits * channels.len() as f32 / file_bits as f32);
+        if load_container(&path)?.decode_image()? != *img {
+            return Err(format!("{} doesn't decode to the image", path).into());
+        }
+        println!("Images are equal");
+    }
+    Ok(())
+}
+
+// Print the outcome of a round-trip check
+fn report_equality(what: &str, result: Result<(), MismatchError>) -> Result<(), Box<dyn Error>> {
+    match result {
+        Ok(()) => {
+            println!("{} are equal", what);
+            Ok(())
+        }
+        Err(err) => Err(format!("{} are not equal: {}", what, err).into()),
+    }
+}
+
+fn check_predicted_cost(encoded_bits: usize, predicted_bits: usize) -> Result<(), Box<dyn Error>> {
+    if encoded_bits != predicted_bits {
+        return Err(format!(
+            "predicted Golomb cost ({} bits) doesn't match the encoder ({} bits)",
+            predicted_bits, encoded_bits
+        )
+        .into());
+    }
+    Ok(())
+}
+
+// Decoded pixels back into an image with the dimensions of `img`
+fn image_from_raw<T: Sample>(img: &GrayBuffer<T>, pixels: Vec<T>) -> Result<GrayBuffer<T>, Box<dyn Error>> {
+    GrayBuffer::from_raw(img.width(), img.height(), pixels).ok_or_else(|| "wrong number of decoded pixels".into())
+}
+
+fn print_skipped(coder: &str, symbols: usize) {
+    println!("{}: skipped, {} symbols are too many for the coder", coder, symbols);
+}
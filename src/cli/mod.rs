@@ -0,0 +1,39 @@
+// Subcommands of the command-line tool, one module per group
+pub mod analyze;
+pub mod audio;
+pub mod compare;
+pub mod files;
+pub mod images;
+pub mod lab;
+
+use eet51_lab3::prediction::{predictor_from_name, Predictor};
+use eet51_lab3::universal_codes::UniversalCode;
+use std::error::Error;
+use std::path::Path;
+
+// Maximum code length for the length-limited Huffman codes
+const MAX_CODE_LENGTH: u32 = 12;
+
+const UNIVERSAL_CODES: [UniversalCode; 7] = [
+    UniversalCode::ExpGolomb(0),
+    UniversalCode::ExpGolomb(1),
+    UniversalCode::ExpGolomb(2),
+    UniversalCode::ExpGolomb(3),
+    UniversalCode::EliasGamma,
+    UniversalCode::EliasDelta,
+    UniversalCode::Fibonacci,
+];
+
+// Name of the image without directory and extension, used to name the output files
+fn image_name(path: &Path) -> String {
+    path.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_else(|| "image".to_string())
+}
+
+fn predictor(name: &str, bit_depth: u8) -> Result<Box<dyn Predictor>, Box<dyn Error>> {
+    predictor_from_name(name, bit_depth).ok_or_else(|| format!("unknown predictor {}", name).into())
+}
+
+// `MAX_CODE_LENGTH`, or the shortest length that can code `symbols` symbols if longer
+fn code_length_limit(symbols: usize) -> u32 {
+    MAX_CODE_LENGTH.max(usize::BITS - symbols.saturating_sub(1).leading_zeros())
+}
@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use image::{ColorType, DynamicImage, ImageBuffer};
+use ndarray::Array2;
+
+use crate::depth::{gray_plane, image_bit_depth, max_value};
+
+/*
+    Reversible color transforms: integer-to-integer, so R, G and B come back exactly.
+    They decorrelate the channels (most of the detail ends up in the luma plane) before
+    each plane is predicted and coded on its own.
+
+    JPEG 2000 RCT:
+        Y  = floor((R + 2G + B) / 4)      G = Y - floor((Cb + Cr) / 4)
+        Cb = B - G                        R = Cr + G
+        Cr = R - G                        B = Cb + G
+
+    YCoCg-R (lifting, Malvar & Sullivan):
+        Co = R - B                        t = Y - floor(Cg / 2)
+        t  = B + floor(Co / 2)            G = Cg + t
+        Cg = G - t                        B = t - floor(Co / 2)
+        Y  = t + floor(Cg / 2)            R = B + Co
+
+    Y keeps the range of the input (0..=255); the chroma planes need one more bit
+    (-255..=255). The alpha channel, if any, is coded as a fourth plane, untransformed.
+    16-bit images, gray or color, keep the bit depth of their largest sample (see
+    `depth`). Gray images with alpha and floating-point images aren't supported.
+
+    Planes are indexed [[x, y]], with shape (width, height), like the prediction error.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ColorTransform {
+    // Code R, G and B as they are
+    None = 0,
+    Rct = 1,
+    YCoCgR = 2,
+}
+
+impl ColorTransform {
+    pub const ALL: [ColorTransform; 3] = [ColorTransform::None, ColorTransform::Rct, ColorTransform::YCoCgR];
+
+    pub fn from_id(id: u8) -> Option<Self> {
+        Self::ALL.into_iter().find(|&transform| transform as u8 == id)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            ColorTransform::None => "rgb",
+            ColorTransform::Rct => "rct",
+            ColorTransform::YCoCgR => "ycocg-r",
+        }
+    }
+
+    // Names of the three transformed planes.
+    pub fn plane_names(self) -> [&'static str; 3] {
+        match self {
+            ColorTransform::None => ["R", "G", "B"],
+            ColorTransform::Rct => ["Y", "Cb", "Cr"],
+            ColorTransform::YCoCgR => ["Y", "Co", "Cg"],
+        }
+    }
+
+    // Values a plane can take: the chroma planes of RCT and YCoCg-R are differences.
+    pub fn plane_range(self, plane: usize, bit_depth: u8) -> RangeInclusive<i32> {
+        let max = max_value(bit_depth);
+        match (self, plane) {
+            (ColorTransform::Rct | ColorTransform::YCoCgR, 1 | 2) => -max..=max,
+            _ => 0..=max,
+        }
+    }
+
+    pub fn forward(self, [r, g, b]: [i32; 3]) -> [i32; 3] {
+        match self {
+            ColorTransform::None => [r, g, b],
+            ColorTransform::Rct => [(r + 2 * g + b) >> 2, b - g, r - g],
+            ColorTransform::YCoCgR => {
+                let co = r - b;
+                let t = b + (co >> 1);
+                let cg = g - t;
+                [t + (cg >> 1), co, cg]
+            }
+        }
+    }
+
+    pub fn inverse(self, [c0, c1, c2]: [i32; 3]) -> [i32; 3] {
+        match self {
+            ColorTransform::None => [c0, c1, c2],
+            ColorTransform::Rct => {
+                let g = c0 - ((c1 + c2) >> 2);
+                [c2 + g, g, c1 + g]
+            }
+            ColorTransform::YCoCgR => {
+                let (y, co, cg) = (c0, c1, c2);
+                let t = y - (cg >> 1);
+                let g = cg + t;
+                let b = t - (co >> 1);
+                [b + co, g, b]
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ColorError {
+    // Only 1 (gray), 3 (RGB) and 4 (RGBA) planes make an image
+    PlaneCount(usize),
+    ShapeMismatch,
+    // A value doesn't fit in the bit depth after the inverse transform
+    OutOfRange { x: usize, y: usize, value: i32 },
+    // Only 8- and 16-bit gray, RGB and RGBA images can be coded
+    Unsupported(ColorType),
+}
+
+impl fmt::Display for ColorError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ColorError::PlaneCount(count) => write!(f, "{} planes don't make an image", count),
+            ColorError::ShapeMismatch => write!(f, "planes have different dimensions"),
+            ColorError::OutOfRange { x, y, value } => {
+                write!(f, "pixel ({}, {}) is out of range after the inverse transform: {}", x, y, value)
+            }
+            ColorError::Unsupported(color) => {
+                write!(f, "{:?} images aren't supported (only 8- and 16-bit gray, RGB and RGBA)", color)
+            }
+        }
+    }
+}
+
+impl Error for ColorError {}
+
+/// Fail unless `img` is one of the types the codecs handle without losing anything:
+/// 8- or 16-bit gray, RGB or RGBA.
+pub fn check_color(img: &DynamicImage) -> Result<(), ColorError> {
+    match img.color() {
+        ColorType::L8 | ColorType::L16 | ColorType::Rgb8 | ColorType::Rgb16 | ColorType::Rgba8 | ColorType::Rgba16 => Ok(()),
+        color => Err(ColorError::Unsupported(color)),
+    }
+}
+
+/// Number of planes used for `img`: 1 for grayscale, 3 for RGB, 4 with alpha.
+pub fn plane_count(img: &DynamicImage) -> usize {
+    let color = img.color();
+    match (color.has_color(), color.has_alpha()) {
+        (false, _) => 1,
+        (true, false) => 3,
+        (true, true) => 4,
+    }
+}
+
+/// Split `img` into planes, applying `transform` to color images. Pixels keep the bit
+/// depth of `image_bit_depth`.
+pub fn image_planes(img: &DynamicImage, transform: ColorTransform) -> Result<Vec<Array2<i32>>, ColorError> {
+    check_color(img)?;
+    let shape = (img.width() as usize, img.height() as usize);
+    let count = plane_count(img);
+    let deep = image_bit_depth(img) > 8;
+    if count == 1 && deep {
+        return Ok(vec![gray_plane(&img.to_luma16())]);
+    } else if count == 1 {
+        return Ok(vec![gray_plane(&img.to_luma8())]);
+    }
+
+    let pixels: Vec<[i32; 4]> = if deep {
+        img.to_rgba16().pixels().map(|pixel| pixel.0.map(i32::from)).collect()
+    } else {
+        img.to_rgba8().pixels().map(|pixel| pixel.0.map(i32::from)).collect()
+    };
+    let mut planes = vec![Array2::zeros(shape); count];
+    for (i, [r, g, b, a]) in pixels.into_iter().enumerate() {
+        let (x, y) = (i % shape.0, i / shape.0);
+        for (plane, value) in planes.iter_mut().zip(transform.forward([r, g, b])) {
+            plane[[x, y]] = value;
+        }
+        if count == 4 {
+            planes[3][[x, y]] = a;
+        }
+    }
+    Ok(planes)
+}
+
+/// Inverse of `image_planes`: a gray, RGB or RGBA image depending on the number of planes,
+/// with 16-bit samples if `bit_depth` is above 8.
+pub fn planes_to_image(
+    planes: &[Array2<i32>],
+    transform: ColorTransform,
+    bit_depth: u8,
+) -> Result<DynamicImage, ColorError> {
+    let count = planes.len();
+    if ![1, 3, 4].contains(&count) {
+        return Err(ColorError::PlaneCount(count));
+    }
+    let (width, height) = planes[0].dim();
+    if planes.iter().any(|plane| plane.dim() != (width, height)) {
+        return Err(ColorError::ShapeMismatch);
+    }
+
+    // Samples in the order of the image buffer: row by row, channels interleaved
+    let max = max_value(bit_depth.max(8));
+    let mut samples = Vec::with_capacity(width * height * count);
+    for y in 0..height {
+        for x in 0..width {
+            let mut pixel = [planes[0][[x, y]], 0, 0, 0];
+            if count >= 3 {
+                let rgb = transform.inverse([planes[0][[x, y]], planes[1][[x, y]], planes[2][[x, y]]]);
+                pixel[..3].copy_from_slice(&rgb);
+            }
+            if count == 4 {
+                pixel[3] = planes[3][[x, y]];
+            }
+            for &value in &pixel[..count] {
+                if !(0..=max).contains(&value) {
+                    return Err(ColorError::OutOfRange { x, y, value });
+                }
+            }
+            samples.extend_from_slice(&pixel[..count]);
+        }
+    }
+
+    let (w, h) = (width as u32, height as u32);
+    let img = if bit_depth > 8 {
+        let samples = samples.into_iter().map(|value| value as u16).collect();
+        match count {
+            1 => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageLuma16),
+            3 => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgb16),
+            _ => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgba16),
+        }
+    } else {
+        let samples = samples.into_iter().map(|value| value as u8).collect();
+        match count {
+            1 => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageLuma8),
+            3 => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgb8),
+            _ => ImageBuffer::from_raw(w, h, samples).map(DynamicImage::ImageRgba8),
+        }
+    };
+    Ok(img.expect("one sample per channel and pixel"))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayAlphaImage, LumaA, Rgb, Rgba, RgbaImage};
+
+    use super::*;
+
+    #[test]
+    fn transforms_are_reversible() {
+        for transform in ColorTransform::ALL {
+            for r in (0..=255).step_by(5) {
+                for g in (0..=255).step_by(3) {
+                    for b in [0, 1, 127, 128, 254, 255] {
+                        let planes = transform.forward([r, g, b]);
+                        assert_eq!(transform.inverse(planes), [r, g, b], "{}", transform.name());
+                        for (plane, value) in planes.iter().enumerate() {
+                            assert!(transform.plane_range(plane, 8).contains(value));
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn planes_round_trip() {
+        let rgba = RgbaImage::from_fn(7, 5, |x, y| Rgba([(x * 37) as u8, (y * 51) as u8, (x * y * 13) as u8, (x + y) as u8]));
+        let rgba16 = ImageBuffer::from_fn(7, 5, |x, y| Rgba([(x * 9001) as u16, (y * 16001) as u16, 65535, (x * y) as u16]));
+        let rgb12 = ImageBuffer::from_fn(7, 5, |x, y| Rgb([(x * 601) as u16, 4095 - (y * 800) as u16, (x * y * 97) as u16]));
+        let images = [
+            DynamicImage::ImageRgba8(rgba.clone()),
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(rgba.clone()).to_rgb8()),
+            DynamicImage::ImageLuma8(DynamicImage::ImageRgba8(rgba).to_luma8()),
+            DynamicImage::ImageRgba16(rgba16),
+            DynamicImage::ImageRgb16(rgb12),
+        ];
+        for img in images {
+            let bit_depth = image_bit_depth(&img);
+            for transform in ColorTransform::ALL {
+                let planes = image_planes(&img, transform).unwrap();
+                assert_eq!(planes.len(), plane_count(&img));
+                for (index, plane) in planes.iter().enumerate() {
+                    assert!(plane.iter().all(|value| transform.plane_range(index, bit_depth).contains(value)));
+                }
+                assert_eq!(planes_to_image(&planes, transform, bit_depth).unwrap(), img);
+            }
+        }
+    }
+
+    #[test]
+    fn unsupported_types_are_errors() {
+        let gray_alpha = DynamicImage::ImageLumaA8(GrayAlphaImage::from_pixel(3, 2, LumaA([10, 20])));
+        let float = DynamicImage::ImageRgb32F(ImageBuffer::from_pixel(3, 2, Rgb([0.5, 0.25, 1.0])));
+        for img in [gray_alpha, float] {
+            let color = img.color();
+            assert_eq!(image_planes(&img, ColorTransform::None), Err(ColorError::Unsupported(color)));
+        }
+    }
+}
@@ -0,0 +1,562 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use image::DynamicImage;
+use ndarray::Array2;
+
+use crate::bitio::{values_to_matrix, BitBuffer, BitReader, BitWriter, DecodeError};
+use crate::color::{image_planes, planes_to_image, ColorError, ColorTransform};
+use crate::depth::{check_maxval, maxval_bit_depth, DepthError};
+use crate::fileio::{read_prologue, write_checksum, write_prologue, Fields, FileError};
+use crate::golomb::encode::{custom_encode_with_m, default_limit, CustomGolombEncodedImage};
+use crate::golomb::mapping::SignedMapping;
+use crate::golomb::search::golomb_cost_curve;
+use crate::huffman::canonical::CanonicalHuffman;
+use crate::huffman::{huffman_encode, HuffmanEncoded};
+use crate::prediction::{plane_residuals, predictor_from_name, reconstruct_plane, ReconstructionError, PREDICTOR_NAMES};
+
+/*
+    Compressed file layout (integers are little-endian):
+
+        magic        4 bytes   "EET3"
+        version      u8        `VERSION`
+        width        u32
+        height       u32
+        maxval       u16       largest sample value of the original image (PNM maxval,
+                               2^depth - 1 otherwise); its bits are the bit depth
+                               (see `depth::maxval_bit_depth`)
+        predictor    u8        index in `PREDICTOR_NAMES`
+        transform    u8        `ColorTransform` id
+        planes       u8        1 (gray), 3 (RGB) or 4 (RGBA)
+        then, for every plane:
+            codec        u8        `Codec` id
+            params len   u32       number of parameter bytes
+            params       ...       codec parameters (see below)
+            payload len  u64       number of payload bits
+            payload      ...       packed payload bits, the last byte zero-padded
+        checksum     u32       CRC-32 of every byte above
+
+    Codec parameters:
+        Golomb:  m (u32), signed mapping (u8), LIMIT (u8), escape bits (u8)
+        Huffman: header bit length (u32), then the canonical code-length header
+                 of `CanonicalHuffman::write_header`, packed
+
+    Each payload is the prediction error matrix of a plane (see `color::image_planes`)
+    coded in raster order of the matrix (x outer, y inner), exactly as produced by
+    the encoders.
+*/
+
+pub const MAGIC: [u8; 4] = *b"EET3";
+pub const VERSION: u8 = 3;
+
+/// Entropy coder used for the payload.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    Golomb = 1,
+    Huffman = 2,
+}
+
+impl Codec {
+    fn from_id(id: u8) -> Option<Self> {
+        match id {
+            1 => Some(Codec::Golomb),
+            2 => Some(Codec::Huffman),
+            _ => None,
+        }
+    }
+}
+
+/// Coded prediction error matrix of a plane.
+pub enum Payload {
+    Golomb(CustomGolombEncodedImage),
+    Huffman(HuffmanEncoded<i32>),
+}
+
+impl Payload {
+    /// Code `matrix` (of an image of `bit_depth` bits) with `codec`. Golomb uses the
+    /// sign-magnitude mapping and, unless `m` is given, the M with the smallest cost.
+    pub fn encode(matrix: &Array2<i32>, bit_depth: u8, codec: Codec, m: Option<u32>) -> Self {
+        match codec {
+            Codec::Golomb => {
+                let mapping = SignedMapping::SignMagnitude;
+                let limit = default_limit(bit_depth);
+                let m = m.unwrap_or_else(|| golomb_cost_curve(matrix, limit, mapping, None).best_m);
+                Payload::Golomb(custom_encode_with_m(matrix, m, limit, mapping))
+            }
+            Codec::Huffman => Payload::Huffman(huffman_encode(matrix.iter().copied())),
+        }
+    }
+
+    pub fn codec(&self) -> Codec {
+        match self {
+            Payload::Golomb(_) => Codec::Golomb,
+            Payload::Huffman(_) => Codec::Huffman,
+        }
+    }
+
+    // Size of the coded data, parameters included.
+    pub fn bits(&self) -> usize {
+        match self {
+            Payload::Golomb(encoded) => encoded.bits(),
+            Payload::Huffman(encoded) => encoded.bits(),
+        }
+    }
+
+    /// Decode the coded matrix, which must have the given shape.
+    pub fn decode(&self, shape: (usize, usize)) -> Result<Array2<i32>, ContainerError> {
+        let matrix = match self {
+            Payload::Golomb(encoded) => encoded.decode(),
+            Payload::Huffman(encoded) => encoded.decode().and_then(|values| values_to_matrix(shape, values)),
+        }
+        .map_err(ContainerError::Decode)?;
+        if matrix.dim() != shape {
+            return Err(FileError::Corrupt("payload doesn't match the image dimensions").into());
+        }
+        Ok(matrix)
+    }
+}
+
+/// A compressed image: everything needed to rebuild it.
+pub struct Container {
+    pub width: u32,
+    pub height: u32,
+    // Bit depth of `maxval`
+    pub bit_depth: u8,
+    // Largest sample value of the image, written back to PNM files
+    pub maxval: u16,
+    // Name of the predictor, one of `PREDICTOR_NAMES`
+    pub predictor: String,
+    // Color transform of the RGB planes (`None` for grayscale images)
+    pub transform: ColorTransform,
+    pub planes: Vec<Payload>,
+}
+
+#[derive(Debug)]
+pub enum ContainerError {
+    File(FileError),
+    // A payload doesn't decode
+    Decode(DecodeError),
+    UnknownCodec(u8),
+    UnknownPredictor(String),
+    Reconstruction(ReconstructionError),
+    Color(ColorError),
+    Depth(DepthError),
+}
+
+impl fmt::Display for ContainerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContainerError::File(err) => write!(f, "{}", err),
+            ContainerError::Decode(err) => write!(f, "payload doesn't decode: {}", err),
+            ContainerError::UnknownCodec(id) => write!(f, "unknown codec id {}", id),
+            ContainerError::UnknownPredictor(name) => write!(f, "unknown predictor {}", name),
+            ContainerError::Reconstruction(err) => write!(f, "{}", err),
+            ContainerError::Color(err) => write!(f, "{}", err),
+            ContainerError::Depth(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ContainerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ContainerError::File(err) => Some(err),
+            ContainerError::Decode(err) => Some(err),
+            ContainerError::Reconstruction(err) => Some(err),
+            ContainerError::Color(err) => Some(err),
+            ContainerError::Depth(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<FileError> for ContainerError {
+    fn from(err: FileError) -> Self {
+        ContainerError::File(err)
+    }
+}
+
+impl From<io::Error> for ContainerError {
+    fn from(err: io::Error) -> Self {
+        ContainerError::File(FileError::Io(err))
+    }
+}
+
+impl From<ReconstructionError> for ContainerError {
+    fn from(err: ReconstructionError) -> Self {
+        ContainerError::Reconstruction(err)
+    }
+}
+
+impl From<ColorError> for ContainerError {
+    fn from(err: ColorError) -> Self {
+        ContainerError::Color(err)
+    }
+}
+
+impl From<DepthError> for ContainerError {
+    fn from(err: DepthError) -> Self {
+        ContainerError::Depth(err)
+    }
+}
+
+impl Container {
+    /// Compress `img`, with samples up to `maxval` (see `depth::check_maxval`): split it
+    /// into planes with `transform` (ignored for grayscale images), predict each plane
+    /// with the predictor called `predictor` and code it with `codec`. Other types than
+    /// those of `color::check_color` are an error.
+    pub fn encode(
+        img: &DynamicImage,
+        maxval: u16,
+        predictor: &str,
+        transform: ColorTransform,
+        codec: Codec,
+        m: Option<u32>,
+    ) -> Result<Self, ContainerError> {
+        let planes = image_planes(img, transform)?;
+        check_maxval(img, maxval)?;
+        let bit_depth = maxval_bit_depth(maxval);
+        // Built for the declared bit depth, as `decode_image` does
+        let predictor = predictor_from_name(predictor, bit_depth)
+            .ok_or_else(|| ContainerError::UnknownPredictor(predictor.to_string()))?;
+        let transform = if planes.len() == 1 { ColorTransform::None } else { transform };
+        Ok(Container {
+            width: img.width(),
+            height: img.height(),
+            bit_depth,
+            maxval,
+            predictor: predictor.name(),
+            transform,
+            planes: planes
+                .iter()
+                .map(|plane| Payload::encode(&plane_residuals(predictor.as_ref(), plane), bit_depth, codec, m))
+                .collect(),
+        })
+    }
+
+    /// Decode the payloads back into the prediction error matrix of every plane.
+    pub fn residuals(&self) -> Result<Vec<Array2<i32>>, ContainerError> {
+        let shape = (self.width as usize, self.height as usize);
+        self.planes.iter().map(|payload| payload.decode(shape)).collect()
+    }
+
+    /// Decode the payloads, undo the prediction and then the color transform.
+    pub fn decode_image(&self) -> Result<DynamicImage, ContainerError> {
+        let predictor = predictor_from_name(&self.predictor, self.bit_depth)
+            .ok_or_else(|| ContainerError::UnknownPredictor(self.predictor.clone()))?;
+        let mut planes = Vec::with_capacity(self.planes.len());
+        for (index, residuals) in self.residuals()?.iter().enumerate() {
+            let range = self.transform.plane_range(index, self.bit_depth);
+            planes.push(reconstruct_plane(predictor.as_ref(), residuals, range)?);
+        }
+        Ok(planes_to_image(&planes, self.transform, self.bit_depth)?)
+    }
+
+    // Total size of the coded planes, parameters included.
+    pub fn bits(&self) -> usize {
+        self.planes.iter().map(Payload::bits).sum()
+    }
+
+    /// Serialize the container (see the layout above).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, ContainerError> {
+        let predictor = PREDICTOR_NAMES
+            .iter()
+            .position(|&name| name == self.predictor)
+            .ok_or_else(|| ContainerError::UnknownPredictor(self.predictor.clone()))?;
+
+        let planes = u8::try_from(self.planes.len()).map_err(|_| FileError::Corrupt("too many planes"))?;
+
+        let mut bytes = write_prologue(MAGIC, VERSION);
+        bytes.extend_from_slice(&self.width.to_le_bytes());
+        bytes.extend_from_slice(&self.height.to_le_bytes());
+        bytes.extend_from_slice(&self.maxval.to_le_bytes());
+        bytes.push(predictor as u8);
+        bytes.push(self.transform as u8);
+        bytes.push(planes);
+        for payload in &self.planes {
+            bytes.push(payload.codec() as u8);
+            write_plane(&mut bytes, payload)?;
+        }
+        write_checksum(&mut bytes);
+        Ok(bytes)
+    }
+
+    /// Parse a container written by `to_bytes`, checking the checksum first.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, ContainerError> {
+        let (version, mut fields) = read_prologue(bytes, MAGIC)?;
+        if version != VERSION {
+            return Err(FileError::UnsupportedVersion(version).into());
+        }
+        let width = fields.u32()?;
+        let height = fields.u32()?;
+        let maxval = fields.u16()?;
+        if maxval == 0 {
+            return Err(FileError::Corrupt("invalid maxval").into());
+        }
+        let bit_depth = maxval_bit_depth(maxval);
+        let shape = (width as usize, height as usize);
+        let predictor_id = fields.u8()?;
+        let predictor = PREDICTOR_NAMES
+            .get(predictor_id as usize)
+            .ok_or_else(|| ContainerError::UnknownPredictor(predictor_id.to_string()))?
+            .to_string();
+
+        let transform = ColorTransform::from_id(fields.u8()?).ok_or(FileError::Corrupt("unknown color transform"))?;
+        let count = fields.u8()? as usize;
+        if ![1, 3, 4].contains(&count) {
+            return Err(FileError::Corrupt("invalid number of planes").into());
+        }
+        let mut planes = Vec::with_capacity(count);
+        for _ in 0..count {
+            let codec = read_codec(&mut fields)?;
+            planes.push(read_plane(&mut fields, codec, shape)?);
+        }
+        fields.finish("trailing bytes after the payload")?;
+        Ok(Container { width, height, bit_depth, maxval, predictor, transform, planes })
+    }
+}
+
+// Parameters and payload of a plane
+fn write_plane(bytes: &mut Vec<u8>, payload: &Payload) -> Result<(), ContainerError> {
+    let (params, payload) = match payload {
+        Payload::Golomb(encoded) => {
+            let mut params = encoded.m.to_le_bytes().to_vec();
+            let mapping = SignedMapping::ALL.iter().position(|&m| m == encoded.mapping).unwrap();
+            let limit = u8::try_from(encoded.limit).map_err(|_| FileError::Corrupt("LIMIT above 255"))?;
+            params.extend_from_slice(&[mapping as u8, limit, encoded.escape_bits as u8]);
+            (params, encoded.encoded_bits.clone())
+        }
+        Payload::Huffman(encoded) => {
+            let mut reader = encoded.encoded_bits.reader();
+            let mut header = BitWriter::new();
+            copy_bits(&mut header, &mut reader, encoded.table_bits);
+            let mut params = (encoded.table_bits as u32).to_le_bytes().to_vec();
+            params.extend_from_slice(header.finish().as_bytes());
+            let mut payload = BitWriter::new();
+            copy_bits(&mut payload, &mut reader, encoded.payload_bits());
+            (params, payload.finish())
+        }
+    };
+    bytes.extend_from_slice(&(params.len() as u32).to_le_bytes());
+    bytes.extend_from_slice(&params);
+    bytes.extend_from_slice(&(payload.len() as u64).to_le_bytes());
+    bytes.extend_from_slice(payload.as_bytes());
+    Ok(())
+}
+
+// Inverse of `write_plane`
+fn read_plane(fields: &mut Fields, codec: Codec, shape: (usize, usize)) -> Result<Payload, ContainerError> {
+    let params_len = fields.u32()? as usize;
+    let mut params = Fields::new(fields.take(params_len)?);
+    let payload_bits = fields.u64()? as usize;
+    let payload = fields.take(payload_bits.div_ceil(8))?.to_vec();
+    let payload = BitBuffer::from_bytes(payload, payload_bits).map_err(|_| FileError::Corrupt("payload too short"))?;
+
+    match codec {
+        Codec::Golomb => {
+            let m = params.u32()?;
+            let mapping = *SignedMapping::ALL
+                .get(params.u8()? as usize)
+                .ok_or(FileError::Corrupt("unknown signed mapping"))?;
+            let limit = params.u8()? as u32;
+            let escape_bits = params.u8()? as u32;
+            if m == 0 || escape_bits == 0 || limit < escape_bits + 2 {
+                return Err(FileError::Corrupt("invalid Golomb parameters").into());
+            }
+            Ok(Payload::Golomb(CustomGolombEncodedImage {
+                m,
+                mapping,
+                limit,
+                escape_bits,
+                encoded_bits: payload,
+                shape,
+            }))
+        }
+        Codec::Huffman => {
+            let header_bits = params.u32()? as usize;
+            let header = params.take(header_bits.div_ceil(8))?;
+            let mut reader =
+                BitReader::with_len(header, header_bits).map_err(|_| FileError::Corrupt("Huffman header too short"))?;
+            let table = CanonicalHuffman::read_header(&mut reader)
+                .ok_or(FileError::Corrupt("invalid Huffman code lengths"))?;
+
+            // Rebuild the bitstream of `huffman_encode`: header, then the symbols
+            let mut writer = BitWriter::new();
+            table.write_header(&mut writer);
+            let table_bits = writer.position();
+            copy_bits(&mut writer, &mut payload.reader(), payload.len());
+            Ok(Payload::Huffman(HuffmanEncoded {
+                table,
+                table_bits,
+                encoded_bits: writer.finish(),
+            }))
+        }
+    }
+}
+
+pub fn write_container<W: Write>(writer: &mut W, container: &Container) -> Result<(), ContainerError> {
+    writer.write_all(&container.to_bytes()?)?;
+    Ok(())
+}
+
+pub fn read_container<R: Read>(reader: &mut R) -> Result<Container, ContainerError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    Container::from_bytes(&bytes)
+}
+
+pub fn save_container<P: AsRef<Path>>(path: P, container: &Container) -> Result<(), ContainerError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    write_container(&mut writer, container)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_container<P: AsRef<Path>>(path: P) -> Result<Container, ContainerError> {
+    read_container(&mut BufReader::new(File::open(path)?))
+}
+
+fn read_codec(fields: &mut Fields) -> Result<Codec, ContainerError> {
+    let id = fields.u8()?;
+    Codec::from_id(id).ok_or(ContainerError::UnknownCodec(id))
+}
+
+// Copy the next `count` bits of `reader` to `writer`.
+fn copy_bits(writer: &mut BitWriter, reader: &mut BitReader, count: usize) {
+    let mut left = count;
+    while left > 0 {
+        let chunk = left.min(64) as u32;
+        writer.write_bits(reader.read_bits(chunk).unwrap(), chunk);
+        left -= chunk as usize;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayImage, ImageBuffer, Luma, LumaA, Rgba};
+
+    use super::*;
+
+    fn gradient() -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(16, 8, |x, y| Luma([(x * 13 + y * 7 + x * y % 5) as u8])))
+    }
+
+    // Serialize with a valid checksum, parse and decode again
+    fn reload(container: &Container) -> Result<DynamicImage, ContainerError> {
+        Container::from_bytes(&container.to_bytes()?)?.decode_image()
+    }
+
+    fn encoded_bits(payload: &mut Payload) -> &mut BitBuffer {
+        match payload {
+            Payload::Golomb(encoded) => &mut encoded.encoded_bits,
+            Payload::Huffman(encoded) => &mut encoded.encoded_bits,
+        }
+    }
+
+    #[test]
+    fn corrupt_payloads_are_errors() {
+        let img = gradient();
+        let predictor = "jpeg4";
+        for codec in [Codec::Golomb, Codec::Huffman] {
+            let mut container = Container::encode(&img, 255, predictor, ColorTransform::None, codec, None).unwrap();
+            assert_eq!(reload(&container).unwrap(), img, "{:?}", codec);
+
+            // Truncated payload
+            let bits = encoded_bits(&mut container.planes[0]);
+            let original = bits.clone();
+            *bits = BitBuffer::from_bytes(original.as_bytes().to_vec(), original.len() - 5).unwrap();
+            // The Golomb decoder stops in the middle of a code, the Huffman one after the
+            // last whole code, and the error keeps where or how many
+            let error = match reload(&container) {
+                Err(ContainerError::Decode(error)) => error,
+                other => panic!("{:?}: {:?}", codec, other.err()),
+            };
+            match (codec, error) {
+                (Codec::Golomb, DecodeError::InvalidCode { position }) => assert!(position < original.len()),
+                (Codec::Huffman, DecodeError::WrongLength { expected, found }) => assert!(found < expected && expected == 128),
+                (_, error) => panic!("{:?}: {:?}", codec, error),
+            }
+
+            // Zeroes decode to a different number of values (Huffman) or to a quotient
+            // longer than the escape (Golomb)
+            let bits = encoded_bits(&mut container.planes[0]);
+            let mut bytes = original.as_bytes().to_vec();
+            let end = bytes.len() - 1;
+            bytes[end - 8..end].fill(0);
+            *bits = BitBuffer::from_bytes(bytes, original.len()).unwrap();
+            assert!(matches!(reload(&container), Err(ContainerError::Decode(_))), "{:?}", codec);
+        }
+    }
+
+    #[test]
+    fn other_versions_are_rejected() {
+        let container = Container::encode(&gradient(), 255, "jpeg4", ColorTransform::None, Codec::Golomb, None).unwrap();
+        let mut bytes = container.to_bytes().unwrap();
+        for version in [1, VERSION + 1] {
+            bytes.truncate(bytes.len() - 4);
+            bytes[MAGIC.len()] = version;
+            write_checksum(&mut bytes);
+            let result = Container::from_bytes(&bytes);
+            assert!(matches!(result, Err(ContainerError::File(FileError::UnsupportedVersion(v))) if v == version));
+        }
+    }
+
+    #[test]
+    fn deep_color_images_round_trip() {
+        let rgba = ImageBuffer::from_fn(9, 6, |x, y| Rgba([(x * 7001) as u16, (y * 9001) as u16, (x * y * 613) as u16, 40000]));
+        let img = DynamicImage::ImageRgba16(rgba);
+        let predictor = "jpeg7";
+        for codec in [Codec::Golomb, Codec::Huffman] {
+            for transform in ColorTransform::ALL {
+                let container = Container::encode(&img, 65535, predictor, transform, codec, None).unwrap();
+                assert_eq!(container.bit_depth, 16);
+                assert_eq!(reload(&container).unwrap(), img, "{:?} {}", codec, transform.name());
+            }
+        }
+    }
+
+    #[test]
+    fn deep_gray_images_keep_their_bit_depth() {
+        let twelve = ImageBuffer::from_fn(11, 7, |x, y| Luma([(x * 373 + y * 41) as u16 % 4096]));
+        let ten = ImageBuffer::from_fn(11, 7, |x, y| Luma([(x * 97 + y * 3) as u16 % 1001]));
+        // Small samples in a 16-bit image stay 16-bit
+        let sixteen = ImageBuffer::from_fn(11, 7, |x, y| Luma([(x * 23 + y) as u16]));
+        for (gray, maxval, bit_depth) in [(twelve, 4095, 12), (ten, 1000, 10), (sixteen, 65535, 16)] {
+            let img = DynamicImage::ImageLuma16(gray);
+            for (predictor, codec) in PREDICTOR_NAMES.iter().flat_map(|p| [(p, Codec::Golomb), (p, Codec::Huffman)]) {
+                let container = Container::encode(&img, maxval, predictor, ColorTransform::None, codec, None).unwrap();
+                let reloaded = Container::from_bytes(&container.to_bytes().unwrap()).unwrap();
+                assert_eq!((reloaded.maxval, reloaded.bit_depth), (maxval, bit_depth));
+                assert_eq!(reloaded.decode_image().unwrap(), img, "{} {:?} {}", predictor, codec, maxval);
+            }
+        }
+    }
+
+    #[test]
+    fn samples_above_the_maxval_are_rejected() {
+        let img = DynamicImage::ImageLuma16(ImageBuffer::from_fn(4, 4, |x, y| Luma([(x * 1000 + y) as u16])));
+        let predictor = "med";
+        // 255 is the maxval of 8-bit images
+        for maxval in [255, 2047, 3002] {
+            let result = Container::encode(&img, maxval, predictor, ColorTransform::None, Codec::Golomb, None);
+            assert!(matches!(result, Err(ContainerError::Depth(_))), "{}", maxval);
+        }
+        assert!(Container::encode(&img, 3003, predictor, ColorTransform::None, Codec::Golomb, None).is_ok());
+    }
+
+    #[test]
+    fn unknown_predictors_are_rejected() {
+        let result = Container::encode(&gradient(), 255, "jpeg9", ColorTransform::None, Codec::Golomb, None);
+        assert!(matches!(result, Err(ContainerError::UnknownPredictor(name)) if name == "jpeg9"));
+    }
+
+    #[test]
+    fn gray_alpha_images_are_rejected() {
+        let img = DynamicImage::ImageLumaA8(ImageBuffer::from_pixel(4, 4, LumaA([1, 2])));
+        let result = Container::encode(&img, 255, "jpeg4", ColorTransform::None, Codec::Golomb, None);
+        assert!(matches!(result, Err(ContainerError::Color(ColorError::Unsupported(_)))));
+    }
+}
@@ -0,0 +1,138 @@
+use std::error::Error;
+use std::fmt::{self, Debug, Display};
+
+use image::{DynamicImage, ImageBuffer, Luma, Primitive};
+use ndarray::Array2;
+use serde::Serialize;
+
+use crate::huffman::canonical::HuffmanSymbol;
+
+/*
+    Bit depth of grayscale images.
+
+    8-bit images are `GrayImage`s (`Luma<u8>`). Deeper images (12-bit medical and
+    scientific images are usually stored in 16-bit files) are `Gray16Image`s. Their bit
+    depth is the one their file declares, not read from the pixels: the maxval of a PNM
+    file or the depth of a raw dump (see `image_io::SourceImage`), 16 for other 16-bit
+    files. A 12-bit image stored as a 12-bit PGM gets a 12-bit alphabet, while a 16-bit
+    PNG stays 16-bit even if its pixels all happen to be small. The depth of a 16-bit
+    image is at least 9, so that decoding gives back a 16-bit image, and 16-bit color
+    images follow the same rules.
+
+    With a depth of b bits, pixels are in 0..=2^b - 1 and the prediction error is counted
+    as b + 1 bits per value in compression ratios (9 bits for 8-bit images).
+*/
+
+pub type GrayBuffer<T> = ImageBuffer<Luma<T>, Vec<T>>;
+pub type Gray16Image = GrayBuffer<u16>;
+
+pub const MAX_BIT_DEPTH: u8 = 16;
+
+/// Pixel type of a grayscale image: `u8` or `u16`.
+pub trait Sample: Primitive + HuffmanSymbol + Display + Debug + Serialize + Into<i32> + From<u8> + Send + Sync {
+    // The grayscale image of `img` with pixels of this type
+    fn gray_image(img: &DynamicImage) -> GrayBuffer<Self>;
+}
+
+impl Sample for u8 {
+    fn gray_image(img: &DynamicImage) -> GrayBuffer<Self> {
+        img.to_luma8()
+    }
+}
+
+impl Sample for u16 {
+    fn gray_image(img: &DynamicImage) -> GrayBuffer<Self> {
+        img.to_luma16()
+    }
+}
+
+/// Pixels of `img` as a matrix indexed [[x, y]].
+pub fn gray_plane<T: Sample>(img: &GrayBuffer<T>) -> Array2<i32> {
+    let shape = (img.width() as usize, img.height() as usize);
+    Array2::from_shape_fn(shape, |(x, y)| img.get_pixel(x as u32, y as u32)[0].into())
+}
+
+/// Largest pixel value with `bit_depth` bits.
+pub fn max_value(bit_depth: u8) -> i32 {
+    (1 << bit_depth) - 1
+}
+
+/// Number of bits needed to write `value` (at least 1).
+pub fn significant_bits(value: u32) -> u8 {
+    (u32::BITS - value.leading_zeros()).max(1) as u8
+}
+
+/// Bit depth declared by the pixel type of `img`: 16 for 16-bit images, gray or
+/// color, and 8 for everything else.
+pub fn image_bit_depth(img: &DynamicImage) -> u8 {
+    match img {
+        DynamicImage::ImageLuma16(_) | DynamicImage::ImageRgb16(_) | DynamicImage::ImageRgba16(_) => MAX_BIT_DEPTH,
+        _ => 8,
+    }
+}
+
+/// Bit depth of an image whose samples go up to `maxval`: 8 below 256, the bits of
+/// `maxval` above.
+pub fn maxval_bit_depth(maxval: u16) -> u8 {
+    significant_bits(maxval as u32).max(8)
+}
+
+/// A bit depth or maxval that doesn't fit an image.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DepthError {
+    // 8-bit images have a depth of 8, 16-bit ones between 9 and 16
+    Unsupported { bit_depth: u8, type_bit_depth: u8 },
+    // A sample is above the largest value of the bit depth or maxval
+    SampleTooLarge { value: u16, max: u16 },
+}
+
+impl Display for DepthError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DepthError::Unsupported { bit_depth, type_bit_depth } => {
+                write!(f, "a {}-bit image can't have a bit depth of {}", type_bit_depth, bit_depth)
+            }
+            DepthError::SampleTooLarge { value, max } => write!(f, "sample {} is above {}", value, max),
+        }
+    }
+}
+
+impl Error for DepthError {}
+
+/// Check that `img` can be coded with `bit_depth` bits: 8 for 8-bit images, 9 to 16
+/// for 16-bit ones, with every sample in 0..=2^bit_depth - 1.
+pub fn check_bit_depth(img: &DynamicImage, bit_depth: u8) -> Result<(), DepthError> {
+    let type_bit_depth = image_bit_depth(img);
+    let valid = match type_bit_depth {
+        8 => bit_depth == 8,
+        _ => (9..=MAX_BIT_DEPTH).contains(&bit_depth),
+    };
+    if !valid {
+        return Err(DepthError::Unsupported { bit_depth, type_bit_depth });
+    }
+    check_samples(img, max_value(bit_depth) as u16)
+}
+
+/// Check that `img` fits in the bit depth of `maxval` (see `maxval_bit_depth`) with
+/// every sample in 0..=maxval.
+pub fn check_maxval(img: &DynamicImage, maxval: u16) -> Result<(), DepthError> {
+    check_bit_depth(img, maxval_bit_depth(maxval))?;
+    check_samples(img, maxval)
+}
+
+fn check_samples(img: &DynamicImage, max: u16) -> Result<(), DepthError> {
+    let above = |value: &u16| *value > max;
+    let found = match img {
+        DynamicImage::ImageLuma16(gray) => gray.as_raw().iter().copied().find(above),
+        DynamicImage::ImageRgb16(rgb) => rgb.as_raw().iter().copied().find(above),
+        DynamicImage::ImageRgba16(rgba) => rgba.as_raw().iter().copied().find(above),
+        DynamicImage::ImageLuma8(_) | DynamicImage::ImageRgb8(_) | DynamicImage::ImageRgba8(_) => {
+            img.as_bytes().iter().map(|&b| b as u16).find(above)
+        }
+        _ => None,
+    };
+    match found {
+        Some(value) => Err(DepthError::SampleTooLarge { value, max }),
+        None => Ok(()),
+    }
+}
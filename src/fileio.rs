@@ -0,0 +1,159 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/*
+    Pieces shared by the compressed file formats (`container`, `huffman::file` and
+    `audio`). Every file starts with a 4-byte magic number and a version byte, stores
+    its integers little-endian and ends with the CRC-32 of every byte before it:
+
+        magic        4 bytes
+        version      u8
+        ...          format-specific fields
+        checksum     u32       CRC-32 of every byte above
+*/
+
+#[derive(Debug)]
+pub enum FileError {
+    Io(io::Error),
+    BadMagic([u8; 4]),
+    UnsupportedVersion(u8),
+    ChecksumMismatch { expected: u32, found: u32 },
+    // The file is well-formed but its contents don't make sense
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for FileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FileError::Io(err) => write!(f, "I/O error: {}", err),
+            FileError::BadMagic(magic) => write!(f, "unknown file type (magic {:?})", magic),
+            FileError::UnsupportedVersion(version) => write!(f, "unsupported version {}", version),
+            FileError::ChecksumMismatch { expected, found } => write!(
+                f,
+                "checksum mismatch: stored {:08x}, computed {:08x}",
+                expected, found
+            ),
+            FileError::Corrupt(what) => write!(f, "corrupt file: {}", what),
+        }
+    }
+}
+
+impl Error for FileError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FileError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FileError {
+    fn from(err: io::Error) -> Self {
+        FileError::Io(err)
+    }
+}
+
+/// Start a file: its magic number and version.
+pub fn write_prologue(magic: [u8; 4], version: u8) -> Vec<u8> {
+    let mut bytes = magic.to_vec();
+    bytes.push(version);
+    bytes
+}
+
+/// Finish a file: append the CRC-32 of everything written so far.
+pub fn write_checksum(bytes: &mut Vec<u8>) {
+    let checksum = crc32(bytes);
+    bytes.extend_from_slice(&checksum.to_le_bytes());
+}
+
+/// Check the magic number and the checksum of a file, then read its version.
+///
+/// Returns the version and a reader of the fields after it (the checksum excluded).
+pub fn read_prologue(bytes: &[u8], magic: [u8; 4]) -> Result<(u8, Fields<'_>), FileError> {
+    if bytes.len() < 4 {
+        return Err(FileError::Corrupt("file too short"));
+    }
+    let (body, checksum) = bytes.split_at(bytes.len() - 4);
+    let mut fields = Fields { bytes: body };
+    let found_magic: [u8; 4] = fields.take(4)?.try_into().unwrap();
+    if found_magic != magic {
+        return Err(FileError::BadMagic(found_magic));
+    }
+    let expected = u32::from_le_bytes(checksum.try_into().unwrap());
+    let found = crc32(body);
+    if expected != found {
+        return Err(FileError::ChecksumMismatch { expected, found });
+    }
+    let version = fields.u8()?;
+    Ok((version, fields))
+}
+
+/// Sequential little-endian field reader.
+pub struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    pub fn new(bytes: &'a [u8]) -> Self {
+        Fields { bytes }
+    }
+
+    pub fn take(&mut self, count: usize) -> Result<&'a [u8], FileError> {
+        if count > self.bytes.len() {
+            return Err(FileError::Corrupt("file truncated"));
+        }
+        let (head, tail) = self.bytes.split_at(count);
+        self.bytes = tail;
+        Ok(head)
+    }
+
+    pub fn u8(&mut self) -> Result<u8, FileError> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn u16(&mut self) -> Result<u16, FileError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    pub fn u32(&mut self) -> Result<u32, FileError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, FileError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Fail unless every field has been read.
+    pub fn finish(&self, what: &'static str) -> Result<(), FileError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(FileError::Corrupt(what))
+        }
+    }
+}
+
+// CRC-32 (IEEE 802.3, reflected polynomial 0xEDB88320), as in zip and PNG
+pub fn crc32(bytes: &[u8]) -> u32 {
+    const TABLE: [u32; 256] = {
+        let mut table = [0u32; 256];
+        let mut i = 0;
+        while i < 256 {
+            let mut crc = i as u32;
+            let mut bit = 0;
+            while bit < 8 {
+                crc = if crc & 1 == 1 { (crc >> 1) ^ 0xEDB8_8320 } else { crc >> 1 };
+                bit += 1;
+            }
+            table[i] = crc;
+            i += 1;
+        }
+        table
+    };
+    let mut crc = u32::MAX;
+    for &byte in bytes {
+        crc = TABLE[((crc ^ byte as u32) & 0xFF) as usize] ^ (crc >> 8);
+    }
+    !crc
+}
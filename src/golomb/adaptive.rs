@@ -0,0 +1,293 @@
+use ndarray::Array2;
+
+use crate::bitio::{BitBuffer, BitWriter, DecodeError};
+use crate::depth::{max_value, MAX_BIT_DEPTH};
+
+use super::encode::{checked_max_q, escape_parameters, read_golomb, write_golomb};
+use super::mapping::{read_signed, write_signed, SignedMapping};
+
+/*
+    Context-adaptive Golomb-Rice coding, as in LOCO-I / JPEG-LS.
+
+    Instead of a single M for the whole matrix, every context keeps two running values:
+    `A`, the sum of the mapped values coded in the context, and `N`, how many were coded.
+    A value is coded with M = 2^k, where k is the smallest integer such that
+        N * 2^k >= A
+    i.e. 2^k is roughly the running mean. After coding, A += x and N += 1; when N reaches
+    `RESET`, both are halved, so old statistics fade out and the coder follows the local
+    behaviour of the data.
+
+    The decoder computes k from the same A and N before reading each value and updates
+    them the same way, so nothing but the escape parameters is transmitted.
+
+    Contexts (`GolombContexts::Gradient`): the causal neighbours of the current value
+        c b d
+        a x
+    give the gradients D1 = d - b, D2 = b - c and D3 = c - a, each quantized to
+    -4..=4 with the thresholds of `gradient_thresholds`. A context and its negation
+    (all signs flipped) are merged, which leaves 365 contexts. Neighbours outside
+    the matrix count as 0.
+
+    Unlike JPEG-LS, which takes the gradients on the reconstructed pixels, the coder
+    only sees the matrix it codes (usually a prediction error), so the gradients are
+    those of the coded values. The context then tells how busy the neighbourhood of
+    the error is rather than the image. The thresholds are the JPEG-LS defaults,
+    scaled with the bit depth as JPEG-LS does for a maxval above 255:
+        FACTOR = (min(maxval, 4095) + 128) / 256
+        T1 = FACTOR * (3 - 2) + 2,  T2 = FACTOR * (7 - 3) + 3,  T3 = FACTOR * (21 - 4) + 4
+    which gives (3, 7, 21) for 8-bit images and (18, 67, 276) from 12 bits up.
+*/
+
+// Statistics are halved when N reaches this value (JPEG-LS default)
+pub const RESET: u32 = 64;
+
+// Largest k, so that M = 2^k fits in a u32
+const MAX_K: u32 = 31;
+
+// Gradient quantization thresholds of JPEG-LS for 8-bit images
+const BASIC_THRESHOLDS: [i32; 3] = [3, 7, 21];
+
+// 9 levels per gradient; only half of them are used after merging signs
+const GRADIENT_CONTEXTS: usize = 9 * 9 * 9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GolombContexts {
+    // A single set of A/N accumulators for the whole matrix
+    Global,
+    // One set per quantized gradient context
+    Gradient,
+}
+
+impl GolombContexts {
+    pub fn name(self) -> &'static str {
+        match self {
+            GolombContexts::Global => "global",
+            GolombContexts::Gradient => "gradient",
+        }
+    }
+
+    fn count(self) -> usize {
+        match self {
+            GolombContexts::Global => 1,
+            GolombContexts::Gradient => GRADIENT_CONTEXTS,
+        }
+    }
+
+    // Context of matrix[[i, j]], from the values already coded (rows before i, then
+    // columns before j)
+    fn index(self, matrix: &Array2<i32>, i: usize, j: usize, thresholds: [i32; 3]) -> usize {
+        if self == GolombContexts::Global {
+            return 0;
+        }
+        let at = |i: Option<usize>, j: Option<usize>| match (i, j) {
+            (Some(i), Some(j)) => matrix.get((i, j)).copied().unwrap_or(0),
+            _ => 0,
+        };
+        let above = i.checked_sub(1);
+        let a = at(Some(i), j.checked_sub(1));
+        let b = at(above, Some(j));
+        let c = at(above, j.checked_sub(1));
+        let d = at(above, Some(j + 1));
+
+        let mut q = [d.saturating_sub(b), b.saturating_sub(c), c.saturating_sub(a)]
+            .map(|g| quantize_gradient(g, thresholds));
+        if q.iter().find(|&&g| g != 0).is_some_and(|&g| g < 0) {
+            q = q.map(|g| -g);
+        }
+        ((q[0] + 4) * 81 + (q[1] + 4) * 9 + (q[2] + 4)) as usize
+    }
+}
+
+/// Gradient quantization thresholds for values of `bit_depth`-bit images (see above).
+pub fn gradient_thresholds(bit_depth: u8) -> [i32; 3] {
+    let maxval = max_value(bit_depth.min(MAX_BIT_DEPTH));
+    let factor = (maxval.min(4095) + 128) >> 8;
+    let mut thresholds = [0; 3];
+    let mut previous = 1;
+    for (index, (threshold, basic)) in thresholds.iter_mut().zip(BASIC_THRESHOLDS).enumerate() {
+        *threshold = (factor * (basic - index as i32 - 2) + index as i32 + 2).clamp(previous, maxval);
+        previous = *threshold;
+    }
+    thresholds
+}
+
+fn quantize_gradient(g: i32, thresholds: [i32; 3]) -> i32 {
+    let level = thresholds.iter().take_while(|&&t| g.unsigned_abs() >= t as u32).count() as i32;
+    let level = if g == 0 { 0 } else { level + 1 };
+    g.signum() * level
+}
+
+/// Running statistics of one context.
+#[derive(Debug, Clone, Copy)]
+struct Accumulator {
+    // Sum of the mapped values
+    a: u64,
+    // Number of values
+    n: u64,
+}
+
+impl Accumulator {
+    fn new(initial_a: u64) -> Self {
+        Accumulator { a: initial_a, n: 1 }
+    }
+
+    // Smallest k such that N * 2^k >= A, at most `MAX_K`
+    fn k(&self) -> u32 {
+        let mut k = 0;
+        while k < MAX_K && (self.n << k) < self.a {
+            k += 1;
+        }
+        k
+    }
+
+    fn update(&mut self, x: u32) {
+        self.a += x as u64;
+        if self.n == RESET as u64 {
+            self.a >>= 1;
+            self.n >>= 1;
+        }
+        self.n += 1;
+    }
+}
+
+// Initial A of every context, from the range of the escaped values (as in JPEG-LS)
+fn initial_a(escape_bits: u32) -> u64 {
+    (((1u64 << escape_bits) + 32) / 64).max(2)
+}
+
+pub struct AdaptiveGolombEncodedImage {
+    pub contexts: GolombContexts,
+    pub mapping: SignedMapping,
+    // Maximum code length (without the sign bit); longer codes are escaped
+    pub limit: u32,
+    // Number of bits used to write an escaped magnitude
+    pub escape_bits: u32,
+    // Gradient quantization thresholds (see `gradient_thresholds`)
+    pub thresholds: [i32; 3],
+    pub encoded_bits: BitBuffer,
+    pub shape: (usize, usize),
+}
+
+impl AdaptiveGolombEncodedImage {
+    pub fn bits(&self) -> usize {
+        self.encoded_bits.len()
+    }
+
+    pub fn decode(&self) -> Result<Array2<i32>, DecodeError> {
+        adaptive_golomb_decode(self)
+    }
+}
+
+/// Code `matrix`, the values of a `bit_depth`-bit image or their prediction error.
+pub fn adaptive_golomb_encode(
+    matrix: &Array2<i32>,
+    contexts: GolombContexts,
+    bit_depth: u8,
+    limit: u32,
+    mapping: SignedMapping,
+) -> AdaptiveGolombEncodedImage {
+    let (limit, escape_bits) = escape_parameters(matrix, limit, mapping);
+    let max_q = limit - escape_bits - 1;
+    let thresholds = gradient_thresholds(bit_depth);
+    let mut stats = vec![Accumulator::new(initial_a(escape_bits)); contexts.count()];
+    let mut writer = BitWriter::new();
+
+    for ((i, j), &v) in matrix.indexed_iter() {
+        let context = &mut stats[contexts.index(matrix, i, j, thresholds)];
+        let m = 1u32 << context.k();
+        write_signed(&mut writer, v, mapping, |writer, x| {
+            write_golomb(writer, x, m, max_q, escape_bits)
+        });
+        context.update(mapping.map(v));
+    }
+    let shape = matrix.shape();
+    let shape = (shape[0], shape[1]);
+    AdaptiveGolombEncodedImage {
+        contexts,
+        mapping,
+        limit,
+        escape_bits,
+        thresholds,
+        encoded_bits: writer.finish(),
+        shape,
+    }
+}
+
+fn adaptive_golomb_decode(data: &AdaptiveGolombEncodedImage) -> Result<Array2<i32>, DecodeError> {
+    // Only the escape parameters can be invalid: every M = 2^k is at least 1
+    let max_q = checked_max_q(1, data.limit, data.escape_bits)?;
+    let mut stats = vec![Accumulator::new(initial_a(data.escape_bits)); data.contexts.count()];
+    let mut reader = data.encoded_bits.reader();
+
+    // Every value takes at least a bit: don't trust the shape for the allocation
+    let (rows, columns) = data.shape;
+    if rows.checked_mul(columns).is_none_or(|values| values > data.encoded_bits.len()) {
+        return Err(DecodeError::InvalidCode { position: data.encoded_bits.len() });
+    }
+
+    // Values are decoded in place, so the contexts only see already decoded neighbours
+    let mut decoded = Array2::zeros(data.shape);
+    for i in 0..rows {
+        for j in 0..columns {
+            let context = &mut stats[data.contexts.index(&decoded, i, j, data.thresholds)];
+            let m = 1u32 << context.k();
+            let position = reader.position();
+            let v = read_signed(&mut reader, data.mapping, |reader| {
+                read_golomb(reader, m, max_q, data.escape_bits)
+            })
+            .ok_or(DecodeError::InvalidCode { position })?;
+            context.update(data.mapping.map(v));
+            decoded[[i, j]] = v;
+        }
+    }
+    if !reader.is_empty() {
+        return Err(DecodeError::InvalidCode { position: reader.position() });
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extreme_values_round_trip() {
+        let values = [i32::MIN, i32::MAX, -1, 0, i32::MIN + 1, i32::MAX - 1];
+        let matrix = Array2::from_shape_fn((16, 9), |(i, j)| values[(i * 9 + j) % values.len()]);
+        for contexts in [GolombContexts::Global, GolombContexts::Gradient] {
+            for mapping in SignedMapping::ALL {
+                let encoded = adaptive_golomb_encode(&matrix, contexts, 16, 32, mapping);
+                assert_eq!(encoded.decode().unwrap(), matrix, "{} / {}", contexts.name(), mapping.name());
+            }
+        }
+    }
+
+    #[test]
+    fn thresholds_scale_with_bit_depth() {
+        assert_eq!(gradient_thresholds(8), [3, 7, 21]);
+        assert_eq!(gradient_thresholds(10), [6, 19, 72]);
+        assert_eq!(gradient_thresholds(12), [18, 67, 276]);
+        assert_eq!(gradient_thresholds(16), [18, 67, 276]);
+        assert_eq!(quantize_gradient(0, [3, 7, 21]), 0);
+        assert_eq!(quantize_gradient(-2, [3, 7, 21]), -1);
+        assert_eq!(quantize_gradient(21, [3, 7, 21]), 4);
+        assert_eq!(quantize_gradient(20, gradient_thresholds(12)), 2);
+    }
+
+    #[test]
+    fn shapes_too_large_for_the_stream_are_errors() {
+        let matrix = Array2::from_shape_fn((5, 4), |(i, j)| i as i32 - j as i32);
+        let mut encoded = adaptive_golomb_encode(&matrix, GolombContexts::Gradient, 8, 32, SignedMapping::Zigzag);
+        for shape in [(usize::MAX, 2), (1 << 40, 1 << 20), (encoded.bits() + 1, 1)] {
+            encoded.shape = shape;
+            assert!(matches!(encoded.decode(), Err(DecodeError::InvalidCode { .. })));
+        }
+    }
+
+    #[test]
+    fn k_is_capped() {
+        let context = Accumulator { a: u64::MAX, n: 1 };
+        assert_eq!(context.k(), MAX_K);
+        assert_eq!(Accumulator { a: 5, n: 1 }.k(), 3);
+    }
+}
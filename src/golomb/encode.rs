@@ -1,8 +1,26 @@
 use ndarray::Array2;
 
+use crate::bitio::{values_to_matrix, BitBuffer, BitReader, BitWriter, DecodeError};
+
+use super::mapping::{read_signed, write_signed, SignedMapping};
+
+/// Default maximum length of a code (without the sign bit), as in JPEG-LS for 8-bit images.
+pub const DEFAULT_LIMIT: u32 = 32;
+
+/// JPEG-LS LIMIT for images of `bit_depth` bits: 2 (bpp + max(8, bpp)), `DEFAULT_LIMIT` for 8 bits.
+pub fn default_limit(bit_depth: u8) -> u32 {
+    let bpp = bit_depth as u32;
+    2 * (bpp + bpp.max(8))
+}
+
 pub struct CustomGolombEncodedImage {
-    pub m: u8,
-    pub encoded_bits: Vec<u8>,
+    pub m: u32,
+    pub mapping: SignedMapping,
+    /// Maximum code length (without the sign bit); longer codes are escaped
+    pub limit: u32,
+    /// Number of bits used to write an escaped magnitude
+    pub escape_bits: u32,
+    pub encoded_bits: BitBuffer,
     pub shape: (usize, usize),
 }
 
@@ -11,7 +29,7 @@ impl CustomGolombEncodedImage {
         self.encoded_bits.len()
     }
 
-    pub fn decode(&self) -> Array2<i32> {
+    pub fn decode(&self) -> Result<Array2<i32>, DecodeError> {
         custom_decode(self)
     }
 }
@@ -28,6 +46,9 @@ impl CustomGolombEncodedImage {
        - In practice, it is convenient to choose `M` as a power of 2.
        - In this case, we will use `k` as the smallest integer greater than or equal to log2(mu/2), 
          where `mu` is the mean of the input data, and `M = 2^k`
+       - Alternatively (`ParameterChoice::Geometric`), we model the magnitudes as a geometric source
+         P(x) = (1 - theta) theta^x with theta = mu / (1 + mu), whose optimal parameter is
+         `M = ceil(log(1 + theta) / -log(theta))` (Gallager & Van Voorhis).
 
     2. **Encoding a Non-negative Integer:**
        - Divide the integer `x` by the parameter `M`, obtaining a quotient `q` and a remainder `r`.
@@ -39,6 +60,8 @@ impl CustomGolombEncodedImage {
     4. **Binary Coding of the Remainder:**
        - Encode the remainder `r` in binary. The binary code can be of fixed or variable length depending on the chosen `M`.
        - If `M` is a power of 2, simple binary encoding is used. Otherwise, some variations like truncated binary encoding may be applied.
+       - Truncated binary: with `b = ceil(log2 M)` and `u = 2^b - M`, the first `u` remainders are written
+         with `b - 1` bits and the others as `r + u` with `b` bits. For a power of 2, `u = 0` and this is plain binary.
 
     5. **Concatenation:**
        - Concatenate the unary code of `q` and the binary code of `r` to get the final Golomb code.
@@ -47,8 +70,14 @@ impl CustomGolombEncodedImage {
         Suppose we choose `M = 3` and we want to encode the number `10`.
         - Quotient and Remainder: `10 = 3 * 3 + 1`
         - Unary code of quotient: `0001`
-        - Binary code of remainder: `01`
-        - Concatenated Golomb code: `000101`
+        - Truncated binary code of remainder (`b = 2`, `u = 1`, so 0 -> `0`, 1 -> `10`, 2 -> `11`): `10`
+        - Concatenated Golomb code: `000110`
+
+    6. **Escape (limited length):**
+       - A large value would need a very long unary run. As in JPEG-LS, the code length is limited to `LIMIT` bits:
+         when `q >= LIMIT - qbpp - 1`, where `qbpp` is the number of bits needed for the largest magnitude,
+         we write `LIMIT - qbpp - 1` zeroes, a one, and then the value itself in `qbpp` bits.
+       - A normal code never has that many zeroes, so the decoder knows when to read an escaped value.
 
     Decoding involves reversing these steps, extracting the quotient and remainder from the encoded bitstream, 
     and reconstructing the original integer using the formula `x = q * M + r`.
@@ -56,88 +85,281 @@ impl CustomGolombEncodedImage {
     Note: Golomb coding is efficient for data where lower values are more probable than higher values, 
     making it suitable for applications like image compression and run-length encoding.
 */
-pub fn custom_encode(matrix: &Array2<i32>) -> CustomGolombEncodedImage
-{
-    let abs_value = matrix.mapv(|x| x.abs());
-    let abs_value_len = abs_value.len();
+pub fn custom_encode(matrix: &Array2<i32>) -> CustomGolombEncodedImage {
+    custom_encode_with_limit(matrix, DEFAULT_LIMIT)
+}
+
+pub fn custom_encode_with_limit(matrix: &Array2<i32>, limit: u32) -> CustomGolombEncodedImage {
+    let mapping = SignedMapping::SignMagnitude;
+    let m = choose_m(matrix, mapping, ParameterChoice::PowerOfTwo);
+    custom_encode_with_m(matrix, m, limit, mapping)
+}
+
+/// How `choose_m` picks the Golomb parameter from the data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterChoice {
+    /// Smallest power of 2 at or above mean/2
+    PowerOfTwo,
+    /// Optimal M for a geometric source with the measured mean
+    Geometric,
+    Fixed(u32),
+}
+
+// Mean of the values given to the Golomb coder (the absolute values, unless zigzag is used)
+fn mean_mapped(matrix: &Array2<i32>, mapping: SignedMapping) -> f64 {
     let mut mean = 0.0;
-    for n in abs_value {
-        mean += n as f32;
-    }
-    mean /= abs_value_len as f32;
-    // Golomb encoding parameter
-    let mut m = 1u8;
-    let mut b = 0;
-    while (m as f32) < mean / 2.0 {
-        m *= 2;
-        b += 1;
+    for &v in matrix {
+        mean += mapping.map(v) as f64;
     }
-    let mut encoded_bits: Vec<u8> = Vec::new();
+    mean / matrix.len() as f64
+}
 
-    for &v in matrix {
-        // Quotient and Remainder Calculation
-        let v_abs = v.unsigned_abs() as u8;
-        let v_sign = v < 0;
-        let q = v_abs / m;
-        let r = v_abs % m;
-
-        // Add the sign bit
-        encoded_bits.push(v_sign as u8);
-        // Unary Encoding of the Quotient
-        // add q values of 0
-        encoded_bits.resize(encoded_bits.len() + q as usize, 0);
-        // add a 1
-        encoded_bits.push(1);
-
-        // Truncated Binary Encoding of the Remainder
-        if r < m {
-            for i in (0..b).rev() {
-                encoded_bits.push((r >> i) & 1);
-            }
-        } else {
-            let adjusted_r = r + m;
-            for i in (0..=b).rev() {
-                encoded_bits.push((adjusted_r >> i) & 1);
+pub fn choose_m(matrix: &Array2<i32>, mapping: SignedMapping, choice: ParameterChoice) -> u32 {
+    match choice {
+        ParameterChoice::PowerOfTwo => {
+            let mean = mean_mapped(matrix, mapping);
+            let mut m = 1u32;
+            while (m as f64) < mean / 2.0 {
+                m *= 2;
             }
+            m
+        }
+        ParameterChoice::Geometric => optimal_geometric_m(mean_mapped(matrix, mapping)),
+        ParameterChoice::Fixed(m) => {
+            assert!(m > 0, "the Golomb parameter must be positive");
+            m
         }
     }
-    // println!("Number of bits: {}", encoded_bits.len());
+}
+
+/// Optimal Golomb parameter for a geometric source with the given mean.
+pub fn optimal_geometric_m(mean: f64) -> u32 {
+    if mean.is_nan() || mean <= 0.0 {
+        return 1;
+    }
+    let theta = mean / (1.0 + mean);
+    let m = ((1.0 + theta).ln() / -theta.ln()).ceil();
+    m.clamp(1.0, (1u32 << 31) as f64) as u32
+}
+
+/// Effective (limit, escape_bits) for a matrix: escaped values use as many bits as the
+/// largest mapped value, and the limit must leave room for at least one normal quotient.
+pub fn escape_parameters(matrix: &Array2<i32>, limit: u32, mapping: SignedMapping) -> (u32, u32) {
+    let max = matrix.iter().map(|&v| mapping.map(v)).max().unwrap_or(0);
+    let escape_bits = (u32::BITS - max.leading_zeros()).max(1);
+    (limit.max(escape_bits + 2), escape_bits)
+}
+
+/// Number of bits b = ceil(log2 m) of the longest truncated binary remainder.
+pub fn remainder_bits(m: u32) -> u32 {
+    u32::BITS - (m - 1).leading_zeros()
+}
+
+/// Length of the truncated binary code of `r` (0 <= r < m).
+pub fn truncated_binary_len(r: u32, m: u32) -> u32 {
+    let b = remainder_bits(m);
+    if b == 0 {
+        return 0;
+    }
+    let u = ((1u64 << b) - m as u64) as u32;
+    if r < u { b - 1 } else { b }
+}
+
+/// Write `r` (0 <= r < m) with a truncated binary code.
+pub fn write_truncated_binary(writer: &mut BitWriter, r: u32, m: u32) {
+    let b = remainder_bits(m);
+    if b == 0 {
+        return;
+    }
+    let u = ((1u64 << b) - m as u64) as u32;
+    if r < u {
+        writer.write_bits(r as u64, b - 1);
+    } else {
+        writer.write_bits(r as u64 + u as u64, b);
+    }
+}
+
+/// Inverse of `write_truncated_binary`. None if the stream ends.
+pub fn read_truncated_binary(reader: &mut BitReader, m: u32) -> Option<u32> {
+    let b = remainder_bits(m);
+    if b == 0 {
+        return Some(0);
+    }
+    let u = ((1u64 << b) - m as u64) as u32;
+    let x = reader.read_bits(b - 1)? as u32;
+    if x < u {
+        Some(x)
+    } else {
+        let x = ((x as u64) << 1) | reader.read_bit()? as u64;
+        Some((x - u as u64) as u32)
+    }
+}
+
+/// Golomb code of `x` with parameter `m`, escaped to `escape_bits` raw bits once the
+/// quotient reaches `max_q`.
+pub fn write_golomb(writer: &mut BitWriter, x: u32, m: u32, max_q: u32, escape_bits: u32) {
+    // Quotient and Remainder Calculation
+    let q = x / m;
+    let r = x % m;
+
+    // Escape: max_q zeroes, a one, and the raw value
+    if q >= max_q {
+        writer.write_repeated(0, max_q as usize);
+        writer.write_bit(1);
+        writer.write_bits(x as u64, escape_bits);
+        return;
+    }
+
+    // Unary Encoding of the Quotient
+    // add q values of 0
+    writer.write_repeated(0, q as usize);
+    // add a 1
+    writer.write_bit(1);
+
+    // Truncated Binary Encoding of the Remainder
+    write_truncated_binary(writer, r, m);
+}
+
+/// Inverse of `write_golomb`. None if the stream ends or holds a code `write_golomb`
+/// can't produce.
+pub fn read_golomb(reader: &mut BitReader, m: u32, max_q: u32, escape_bits: u32) -> Option<u32> {
+    let q = reader.read_run(0)?;
+    if q == max_q as usize {
+        return Some(reader.read_bits(escape_bits)? as u32);
+    }
+    if q > max_q as usize {
+        return None;
+    }
+    let r = read_truncated_binary(reader, m)?;
+    u32::try_from(q as u64 * m as u64 + r as u64).ok()
+}
+
+/// Check the parameters of a Golomb stream and return its largest quotient `max_q`.
+pub fn checked_max_q(m: u32, limit: u32, escape_bits: u32) -> Result<u32, DecodeError> {
+    if m == 0 || !(1..=u32::BITS).contains(&escape_bits) || limit < escape_bits + 2 {
+        return Err(DecodeError::InvalidHeader);
+    }
+    Ok(limit - escape_bits - 1)
+}
+
+pub fn custom_encode_with_m(
+    matrix: &Array2<i32>,
+    m: u32,
+    limit: u32,
+    mapping: SignedMapping,
+) -> CustomGolombEncodedImage {
+    assert!(m > 0, "the Golomb parameter must be positive");
+    let (limit, escape_bits) = escape_parameters(matrix, limit, mapping);
+    let max_q = limit - escape_bits - 1;
+    let mut writer = BitWriter::new();
+
+    for &v in matrix {
+        write_signed(&mut writer, v, mapping, |writer, x| {
+            write_golomb(writer, x, m, max_q, escape_bits)
+        });
+    }
+    let encoded_bits = writer.finish();
     let shape = matrix.shape();
     let shape = (shape[0], shape[1]);
-    CustomGolombEncodedImage { m, encoded_bits, shape }
+    CustomGolombEncodedImage { m, mapping, limit, escape_bits, encoded_bits, shape }
 }
 
-fn custom_decode(data: &CustomGolombEncodedImage) -> Array2<i32> {
+fn custom_decode(data: &CustomGolombEncodedImage) -> Result<Array2<i32>, DecodeError> {
     let mut decoded_pixels: Vec<i32> = Vec::new();
     let m = data.m;
-    let bits = &data.encoded_bits;
+    let max_q = checked_max_q(m, data.limit, data.escape_bits)?;
+    let mut reader = data.encoded_bits.reader();
+
+    while !reader.is_empty() {
+        let position = reader.position();
+        let v = read_signed(&mut reader, data.mapping, |reader| {
+            read_golomb(reader, m, max_q, data.escape_bits)
+        });
+        decoded_pixels.push(v.ok_or(DecodeError::InvalidCode { position })?);
+    }
+    values_to_matrix(data.shape, decoded_pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::golomb::search::golomb_bits;
+    use crate::histogram::Histogram;
 
-    let mut b = 0;
-    while (1 << b) < m {
-        b += 1;
+    fn matrix(values: Vec<i32>) -> Array2<i32> {
+        Array2::from_shape_vec((values.len(), 1), values).unwrap()
     }
 
-    let mut i = 0;
+    // Round trip with every mapping and a few parameters, checking `golomb_bits` against the encoder
+    fn round_trip(matrix: &Array2<i32>) {
+        for mapping in SignedMapping::ALL {
+            let m = choose_m(matrix, mapping, ParameterChoice::PowerOfTwo);
+            for m in [1, 3, m, optimal_geometric_m(mean_mapped(matrix, mapping)), 1000] {
+                let encoded = custom_encode_with_m(matrix, m, DEFAULT_LIMIT, mapping);
+                assert_eq!(&encoded.decode().unwrap(), matrix, "m = {}, {}", m, mapping.name());
 
-    while i < bits.len() {
-        let sign = bits[i] == 1;
-        i += 1;
-        let mut q = 0;
-        while bits[i] == 0 {
-            q += 1;
-            i += 1;
-        }
-        i += 1;
-        let mut r = 0;
-        for j in 0..b {
-            r |= bits[i + j] << (b - j - 1);
-        }
-        i += b;
-        if r >= m {
-            r -= m;
+                let histogram = Histogram::from_iter(matrix.iter().map(|&v| mapping.map(v)));
+                let (limit, escape_bits) = escape_parameters(matrix, DEFAULT_LIMIT, mapping);
+                assert_eq!(golomb_bits(&histogram, m, limit, escape_bits, mapping), encoded.bits());
+            }
         }
-        let v = (q * m + r) as i32;
-        decoded_pixels.push(if sign { -v } else { v });
     }
-    Array2::from_shape_vec(data.shape, decoded_pixels).unwrap()
+
+    #[test]
+    fn example_code() {
+        let mut writer = BitWriter::new();
+        write_golomb(&mut writer, 10, 3, 20, 8);
+        assert_eq!(writer.finish().to_bits(), vec![0, 0, 0, 1, 1, 0]);
+    }
+
+    #[test]
+    fn empty_input() {
+        let empty = Array2::zeros((0, 4));
+        round_trip(&empty);
+        assert_eq!(custom_encode(&empty).bits(), 0);
+    }
+
+    #[test]
+    fn one_symbol() {
+        round_trip(&matrix(vec![0; 64]));
+        round_trip(&matrix(vec![-9; 64]));
+    }
+
+    #[test]
+    fn skewed_input() {
+        // Mostly small residuals, with values that need the escape
+        let mut values: Vec<i32> = (0..500).map(|i| (i % 5) - 2).collect();
+        values.extend([255, -255, 40_000, i32::MAX, i32::MIN, 1 << 20]);
+        round_trip(&matrix(values));
+    }
+
+    #[test]
+    fn invalid_streams_are_errors() {
+        let encoded = custom_encode(&matrix(vec![3, -1, 0, 7, 100]));
+        let bits = &encoded.encoded_bits;
+        let truncated = CustomGolombEncodedImage {
+            encoded_bits: BitBuffer::from_bytes(bits.as_bytes().to_vec(), bits.len() - 1).unwrap(),
+            ..encoded
+        };
+        assert!(matches!(truncated.decode(), Err(DecodeError::InvalidCode { .. })));
+
+        // A positive escaped magnitude of 2^31 after a valid value
+        let mut writer = BitWriter::new();
+        write_signed(&mut writer, 5, SignedMapping::SignMagnitude, |writer, x| write_golomb(writer, x, 4, 1, 32));
+        let position = writer.position();
+        write_signed(&mut writer, 0, SignedMapping::SignMagnitude, |writer, _| write_golomb(writer, 1 << 31, 4, 1, 32));
+        let overflow = CustomGolombEncodedImage {
+            m: 4,
+            mapping: SignedMapping::SignMagnitude,
+            limit: 34,
+            escape_bits: 32,
+            encoded_bits: writer.finish(),
+            shape: (2, 1),
+        };
+        assert_eq!(overflow.decode(), Err(DecodeError::InvalidCode { position }));
+
+        assert_eq!(checked_max_q(0, 32, 8), Err(DecodeError::InvalidHeader));
+        assert_eq!(checked_max_q(4, 9, 8), Err(DecodeError::InvalidHeader));
+        assert_eq!(checked_max_q(4, 32, 8), Ok(23));
+    }
 }
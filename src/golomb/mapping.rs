@@ -0,0 +1,165 @@
+use crate::bitio::{BitReader, BitWriter};
+
+/// How a signed residual is turned into the non-negative integer coded by Golomb.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SignedMapping {
+    // Sign bit followed by the code of |v| (zero also gets a sign bit)
+    SignMagnitude,
+    // Rice interleaving 0, -1, 1, -2, 2, ... -> 0, 1, 2, 3, 4, ...; no sign bit
+    Zigzag,
+    // Code of |v|, then a sign bit only if v != 0
+    SignIfNonzero,
+}
+
+impl SignedMapping {
+    pub const ALL: [SignedMapping; 3] = [
+        SignedMapping::SignMagnitude,
+        SignedMapping::Zigzag,
+        SignedMapping::SignIfNonzero,
+    ];
+
+    // Non-negative value given to the Golomb coder.
+    pub fn map(self, v: i32) -> u32 {
+        match self {
+            SignedMapping::Zigzag => zigzag(v),
+            SignedMapping::SignMagnitude | SignedMapping::SignIfNonzero => v.unsigned_abs(),
+        }
+    }
+
+    // Number of sign bits spent on a value whose mapped value is `x`.
+    pub fn sign_bits(self, x: u32) -> usize {
+        match self {
+            SignedMapping::SignMagnitude => 1,
+            SignedMapping::Zigzag => 0,
+            SignedMapping::SignIfNonzero => (x != 0) as usize,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            SignedMapping::SignMagnitude => "sign+magnitude",
+            SignedMapping::Zigzag => "zigzag",
+            SignedMapping::SignIfNonzero => "sign if nonzero",
+        }
+    }
+}
+
+/// Write `v` with `mapping`, using `write_value` for the non-negative mapped value.
+pub fn write_signed<F>(writer: &mut BitWriter, v: i32, mapping: SignedMapping, mut write_value: F)
+where
+    F: FnMut(&mut BitWriter, u32),
+{
+    let x = mapping.map(v);
+    let sign = (v < 0) as u8;
+    match mapping {
+        SignedMapping::SignMagnitude => {
+            writer.write_bit(sign);
+            write_value(writer, x);
+        }
+        SignedMapping::Zigzag => write_value(writer, x),
+        // The sign bit comes after the magnitude, so the decoder knows if it's there
+        SignedMapping::SignIfNonzero => {
+            write_value(writer, x);
+            if x != 0 {
+                writer.write_bit(sign);
+            }
+        }
+    }
+}
+
+/// Inverse of `write_signed`. None if the stream ends, or if a magnitude doesn't fit
+/// in an i32 with its sign (only -2^31 has a magnitude of 2^31).
+pub fn read_signed<F>(reader: &mut BitReader, mapping: SignedMapping, mut read_value: F) -> Option<i32>
+where
+    F: FnMut(&mut BitReader) -> Option<u32>,
+{
+    let v = match mapping {
+        SignedMapping::SignMagnitude => {
+            let sign = reader.read_bit()? == 1;
+            let v = read_value(reader)? as i64;
+            if sign { -v } else { v }
+        }
+        SignedMapping::Zigzag => unzigzag(read_value(reader)?) as i64,
+        SignedMapping::SignIfNonzero => {
+            let v = read_value(reader)? as i64;
+            if v != 0 && reader.read_bit()? == 1 { -v } else { v }
+        }
+    };
+    i32::try_from(v).ok()
+}
+
+pub fn zigzag(v: i32) -> u32 {
+    ((v << 1) ^ (v >> 31)) as u32
+}
+
+pub fn unzigzag(x: u32) -> i32 {
+    ((x >> 1) as i32) ^ -((x & 1) as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EXTREMES: [i32; 7] = [0, 1, -1, i32::MAX, i32::MIN, i32::MAX - 1, i32::MIN + 1];
+
+    #[test]
+    fn zigzag_interleaves() {
+        let mapped: Vec<u32> = [0, -1, 1, -2, 2].into_iter().map(zigzag).collect();
+        assert_eq!(mapped, vec![0, 1, 2, 3, 4]);
+        assert_eq!(zigzag(i32::MAX), u32::MAX - 1);
+        assert_eq!(zigzag(i32::MIN), u32::MAX);
+        for v in EXTREMES {
+            assert_eq!(unzigzag(zigzag(v)), v);
+        }
+    }
+
+    #[test]
+    fn extremes_round_trip() {
+        for mapping in SignedMapping::ALL {
+            let mut writer = BitWriter::new();
+            let mut bits = 0;
+            for v in EXTREMES {
+                write_signed(&mut writer, v, mapping, |writer, x| writer.write_bits(x as u64, 32));
+                bits += 32 + mapping.sign_bits(mapping.map(v));
+            }
+            let buffer = writer.finish();
+            assert_eq!(buffer.len(), bits, "{}", mapping.name());
+
+            let mut reader = buffer.reader();
+            for v in EXTREMES {
+                let read = read_signed(&mut reader, mapping, |reader| reader.read_bits(32).map(|x| x as u32));
+                assert_eq!(read, Some(v), "{}", mapping.name());
+            }
+            assert!(reader.is_empty());
+        }
+    }
+
+    #[test]
+    fn magnitudes_beyond_i32_are_rejected() {
+        for mapping in [SignedMapping::SignMagnitude, SignedMapping::SignIfNonzero] {
+            for (magnitude, negative, expected) in
+                [(1u64 << 31, 1, Some(i32::MIN)), (1 << 31, 0, None), (u32::MAX as u64, 1, None), (u32::MAX as u64, 0, None)]
+            {
+                let mut writer = BitWriter::new();
+                if mapping == SignedMapping::SignMagnitude {
+                    writer.write_bit(negative);
+                }
+                writer.write_bits(magnitude, 32);
+                if mapping == SignedMapping::SignIfNonzero {
+                    writer.write_bit(negative);
+                }
+                let buffer = writer.finish();
+                let read = read_signed(&mut buffer.reader(), mapping, |reader| reader.read_bits(32).map(|x| x as u32));
+                assert_eq!(read, expected, "{} {} {}", mapping.name(), magnitude, negative);
+            }
+        }
+    }
+
+    #[test]
+    fn magnitudes() {
+        assert_eq!(SignedMapping::SignMagnitude.map(i32::MIN), 1 << 31);
+        assert_eq!(SignedMapping::SignIfNonzero.map(-5), 5);
+        assert_eq!(SignedMapping::SignIfNonzero.sign_bits(0), 0);
+        assert_eq!(SignedMapping::SignMagnitude.sign_bits(0), 1);
+    }
+}
@@ -1 +1,4 @@
+pub mod adaptive;
 pub mod encode;
+pub mod mapping;
+pub mod search;
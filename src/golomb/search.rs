@@ -0,0 +1,231 @@
+use std::error::Error;
+
+use ndarray::Array2;
+use serde::Serialize;
+
+use crate::histogram::Histogram;
+
+use super::encode::{escape_parameters, remainder_bits, truncated_binary_len};
+use super::mapping::SignedMapping;
+
+#[derive(Serialize)]
+struct CsvRow {
+    m: u32,
+    bits: usize,
+    bits_per_symbol: f64,
+}
+
+/// Exact encoded size of a matrix for every Golomb parameter that can be the best one.
+pub struct GolombCostCurve {
+    // (m, encoded bits) from m = 1 up; `bits_for` is None past the end of the curve
+    pub costs: Vec<(u32, usize)>,
+    pub best_m: u32,
+    pub best_bits: usize,
+    pub symbols: usize,
+}
+
+impl GolombCostCurve {
+    pub fn bits_for(&self, m: u32) -> Option<usize> {
+        self.costs.iter().find(|&&(cm, _)| cm == m).map(|&(_, bits)| bits)
+    }
+
+    // Save the curve as m, bits, bits per symbol.
+    pub fn to_csv(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        let mut writer = csv::Writer::from_path(path)?;
+        for &(m, bits) in &self.costs {
+            writer.serialize(CsvRow {
+                m,
+                bits,
+                bits_per_symbol: bits as f64 / self.symbols as f64,
+            })?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Size in bits of the mapped values in `histogram` coded by `custom_encode_with_m`
+/// (sign bits included), without encoding anything.
+pub fn golomb_bits(
+    histogram: &Histogram<u32>,
+    m: u32,
+    limit: u32,
+    escape_bits: u32,
+    mapping: SignedMapping,
+) -> usize {
+    let max_q = limit - escape_bits - 1;
+    let mut bits = 0;
+    for (&x, &count) in histogram.counts() {
+        let q = x / m;
+        let len = if q >= max_q {
+            max_q + 1 + escape_bits
+        } else {
+            q + 1 + truncated_binary_len(x % m, m)
+        };
+        bits += (mapping.sign_bits(x) + len as usize) * count;
+    }
+    bits
+}
+
+// Mapped values in increasing order with the running total of their counts, so the
+// number of values in any range takes two binary searches.
+struct SortedCounts {
+    values: Vec<u64>,
+    // below[i] = number of values smaller than values[i]; one extra entry for the total
+    below: Vec<usize>,
+}
+
+impl SortedCounts {
+    fn new(histogram: &Histogram<u32>) -> Self {
+        let mut counts: Vec<(u64, usize)> = histogram.counts().iter().map(|(&x, &count)| (x as u64, count)).collect();
+        counts.sort();
+        let mut below = Vec::with_capacity(counts.len() + 1);
+        let mut sum = 0;
+        below.push(0);
+        for &(_, count) in &counts {
+            sum += count;
+            below.push(sum);
+        }
+        SortedCounts { values: counts.into_iter().map(|(x, _)| x).collect(), below }
+    }
+
+    // Number of values smaller than `x`
+    fn count_below(&self, x: u64) -> usize {
+        self.below[self.values.partition_point(|&v| v < x)]
+    }
+
+    // `golomb_bits` without the sign bits, one quotient block at a time: the values with
+    // quotient q cost q + 1 + b bits, minus one for the remainders below u = 2^b - m.
+    // Empty blocks are skipped, so the cost is at most one step per distinct value.
+    fn golomb_bits(&self, m: u32, max_q: u32, escape_bits: u32) -> usize {
+        let m = m as u64;
+        let b = remainder_bits(m as u32) as usize;
+        let u = (1u64 << b) - m;
+        let mut bits = 0;
+        let mut q = 0;
+        while q < max_q as u64 {
+            // Jump to the block of the next value
+            let next = self.values.partition_point(|&v| v < q * m);
+            match self.values.get(next) {
+                Some(&value) => q = value / m,
+                None => break,
+            }
+            if q >= max_q as u64 {
+                break;
+            }
+            let start = q * m;
+            let in_block = self.count_below(start + m) - self.count_below(start);
+            let short = self.count_below(start + u) - self.count_below(start);
+            bits += in_block * (q as usize + 1 + b) - short;
+            q += 1;
+        }
+        let escaped = self.below[self.values.len()] - self.count_below(max_q as u64 * m);
+        bits + escaped * (max_q + 1 + escape_bits) as usize
+    }
+}
+
+/// Cost of every parameter from 1 to `max_m` (by default the largest mapped value, past
+/// which every value has a zero quotient and larger parameters only cost more).
+///
+/// The curve ends early at the first M where even a quotient of 0 and a remainder of
+/// floor(log2 M) bits for every value cost at least the best size so far: no larger
+/// parameter can do better, so `best_m` is still the optimum over 1 to `max_m`.
+pub fn golomb_cost_curve(
+    matrix: &Array2<i32>,
+    limit: u32,
+    mapping: SignedMapping,
+    max_m: Option<u32>,
+) -> GolombCostCurve {
+    let histogram = Histogram::from_iter(matrix.iter().map(|&v| mapping.map(v)));
+    let (limit, escape_bits) = escape_parameters(matrix, limit, mapping);
+    let max_value = histogram.counts().keys().copied().max().unwrap_or(0);
+    let max_m = max_m.unwrap_or(max_value).max(1);
+    let max_q = limit - escape_bits - 1;
+    let sign_bits: usize = histogram.counts().iter().map(|(&x, &count)| mapping.sign_bits(x) * count).sum();
+    let values = SortedCounts::new(&histogram);
+
+    // m = 1 is always tried, so the curve is never empty
+    let mut costs: Vec<(u32, usize)> = Vec::new();
+    let mut best = (1, usize::MAX);
+    for m in 1..=max_m {
+        let shortest = (u32::BITS - m.leading_zeros()).min(max_q + 1 + escape_bits) as usize;
+        if sign_bits + matrix.len() * shortest >= best.1 {
+            break;
+        }
+        let bits = sign_bits + values.golomb_bits(m, max_q, escape_bits);
+        if bits < best.1 {
+            best = (m, bits);
+        }
+        costs.push((m, bits));
+    }
+    let (best_m, best_bits) = best;
+    GolombCostCurve {
+        costs,
+        best_m,
+        best_bits,
+        symbols: matrix.len(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn matrix(values: Vec<i32>) -> Array2<i32> {
+        Array2::from_shape_vec((1, values.len()), values).unwrap()
+    }
+
+    // Cost of every parameter in 1..=max_m with a full pass over the histogram each
+    fn brute_force(matrix: &Array2<i32>, mapping: SignedMapping, max_m: u32) -> Vec<usize> {
+        let histogram = Histogram::from_iter(matrix.iter().map(|&v| mapping.map(v)));
+        let (limit, escape_bits) = escape_parameters(matrix, 32, mapping);
+        (1..=max_m).map(|m| golomb_bits(&histogram, m, limit, escape_bits, mapping)).collect()
+    }
+
+    #[test]
+    fn curve_matches_golomb_bits() {
+        let values: Vec<i32> = (0..3000).map(|i| ((i * 7919) % 601) - 300).collect();
+        let matrix = matrix(values);
+        for mapping in SignedMapping::ALL {
+            let curve = golomb_cost_curve(&matrix, 32, mapping, None);
+            let expected = brute_force(&matrix, mapping, 600);
+            for &(m, bits) in &curve.costs {
+                assert_eq!(bits, expected[m as usize - 1], "M = {} with {}", m, mapping.name());
+            }
+            assert_eq!(curve.best_bits, *expected.iter().min().unwrap());
+            assert_eq!(curve.best_bits, expected[curve.best_m as usize - 1]);
+        }
+    }
+
+    #[test]
+    fn best_parameter_above_a_thousand() {
+        // 16-bit-like values: the best parameter is in the thousands, and a few outliers
+        // make the full range huge
+        let mut values: Vec<i32> = (0..400).map(|i| (i * 104_729) % 40_000).collect();
+        values.extend([1_000_000, -1_000_000]);
+        let matrix = matrix(values);
+        let mapping = SignedMapping::Zigzag;
+        let curve = golomb_cost_curve(&matrix, 32, mapping, None);
+        assert!(curve.best_m > 1024);
+
+        let expected = brute_force(&matrix, mapping, curve.costs.len() as u32);
+        assert_eq!(curve.costs.iter().map(|&(_, bits)| bits).collect::<Vec<_>>(), expected);
+        // Past the end of the curve, no parameter is better
+        let histogram = Histogram::from_iter(matrix.iter().map(|&v| mapping.map(v)));
+        let (limit, escape_bits) = escape_parameters(&matrix, 32, mapping);
+        for m in (curve.costs.len() as u32 + 1..=2_000_001).step_by(9973) {
+            assert!(golomb_bits(&histogram, m, limit, escape_bits, mapping) >= curve.best_bits);
+        }
+    }
+
+    #[test]
+    fn explicit_max_m_and_empty_input() {
+        let matrix = matrix(vec![0, 5, -5, 100]);
+        let curve = golomb_cost_curve(&matrix, 32, SignedMapping::SignMagnitude, Some(3));
+        assert!(curve.costs.len() <= 3);
+        assert!(curve.best_m <= 3);
+
+        let curve = golomb_cost_curve(&Array2::zeros((0, 0)), 32, SignedMapping::Zigzag, None);
+        assert_eq!((curve.best_m, curve.best_bits), (1, 0));
+    }
+}
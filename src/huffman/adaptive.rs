@@ -0,0 +1,293 @@
+use std::collections::HashMap;
+
+use crate::bitio::{BitBuffer, BitReader, BitWriter, DecodeError};
+
+use super::canonical::HuffmanSymbol;
+
+/*
+    Adaptive Huffman coding (algorithm FGK: Faller, Gallager, Knuth).
+
+    Encoder and decoder start from the same tree, holding a single zero-weight
+    "not yet transmitted" (NYT) leaf, and update it identically after every symbol,
+    so no code table is ever transmitted and the data is read only once.
+
+    - A known symbol is sent with its current code.
+    - A new symbol is sent as the code of the NYT leaf followed by the raw symbol
+      (`T::BITS` bits). The NYT leaf is then split into a new NYT leaf and a leaf
+      for the symbol.
+
+    The tree keeps the sibling property: nodes listed in decreasing implicit number
+    (root first) have non-increasing weights. Before a node's weight is incremented
+    it is swapped with the highest numbered node of its weight block (skipping its
+    parent), so the property still holds after the increment. This is repeated up
+    to the root.
+*/
+
+#[derive(Debug, Clone)]
+struct Node<T> {
+    weight: u64,
+    parent: Option<usize>,
+    // (left, right) children of internal nodes
+    children: Option<(usize, usize)>,
+    // Symbol of the leaf (None for internal nodes and for the NYT leaf)
+    symbol: Option<T>,
+}
+
+#[derive(Debug, Clone)]
+pub struct AdaptiveHuffman<T>
+where
+    T: HuffmanSymbol,
+{
+    nodes: Vec<Node<T>>,
+    // Node ids in decreasing implicit number: order[0] is the root
+    order: Vec<usize>,
+    // Index of each node id in `order`
+    position: Vec<usize>,
+    leaves: HashMap<T, usize>,
+    root: usize,
+    nyt: usize,
+}
+
+impl<T> AdaptiveHuffman<T>
+where
+    T: HuffmanSymbol,
+{
+    pub fn new() -> Self {
+        let nyt = Node {
+            weight: 0,
+            parent: None,
+            children: None,
+            symbol: None,
+        };
+        AdaptiveHuffman {
+            nodes: vec![nyt],
+            order: vec![0],
+            position: vec![0],
+            leaves: HashMap::new(),
+            root: 0,
+            nyt: 0,
+        }
+    }
+
+    pub fn encode_symbol(&mut self, symbol: T, writer: &mut BitWriter) {
+        match self.leaves.get(&symbol) {
+            Some(&leaf) => self.write_code(leaf, writer),
+            None => {
+                self.write_code(self.nyt, writer);
+                writer.write_bits(symbol.to_u64(), T::BITS);
+            }
+        }
+        self.update(symbol);
+    }
+
+    // Returns `None` at the end of the stream.
+    pub fn decode_symbol(&mut self, reader: &mut BitReader) -> Option<T> {
+        let mut node = self.root;
+        while let Some((left, right)) = self.nodes[node].children {
+            node = if reader.read_bit()? == 0 { left } else { right };
+        }
+        let symbol = match self.nodes[node].symbol {
+            Some(symbol) => symbol,
+            None => T::from_u64(reader.read_bits(T::BITS)?),
+        };
+        self.update(symbol);
+        Some(symbol)
+    }
+
+    // Write the path from the root to `node` (0 = left, 1 = right).
+    fn write_code(&self, node: usize, writer: &mut BitWriter) {
+        let mut path = Vec::new();
+        let mut current = node;
+        while let Some(parent) = self.nodes[current].parent {
+            let (left, _) = self.nodes[parent].children.unwrap();
+            path.push(if left == current { 0 } else { 1 });
+            current = parent;
+        }
+        path.reverse();
+        writer.write_slice(&path);
+    }
+
+    fn update(&mut self, symbol: T) {
+        let mut node = match self.leaves.get(&symbol) {
+            Some(&leaf) => leaf,
+            None => self.split_nyt(symbol),
+        };
+        loop {
+            let leader = self.block_leader(node);
+            if leader != node {
+                self.swap(node, leader);
+            }
+            self.nodes[node].weight += 1;
+            match self.nodes[node].parent {
+                Some(parent) => node = parent,
+                None => break,
+            }
+        }
+    }
+
+    // Give the NYT leaf two children: a new NYT leaf (left) and a leaf for `symbol` (right).
+    // Returns the id of the new symbol leaf.
+    fn split_nyt(&mut self, symbol: T) -> usize {
+        let old_nyt = self.nyt;
+        let leaf = self.nodes.len();
+        let nyt = leaf + 1;
+        self.nodes.push(Node {
+            weight: 0,
+            parent: Some(old_nyt),
+            children: None,
+            symbol: Some(symbol),
+        });
+        self.nodes.push(Node {
+            weight: 0,
+            parent: Some(old_nyt),
+            children: None,
+            symbol: None,
+        });
+        self.nodes[old_nyt].children = Some((nyt, leaf));
+
+        // The NYT leaf is always last in the order; the new leaf goes right before it
+        self.position.push(self.order.len());
+        self.order.push(leaf);
+        self.position.push(self.order.len());
+        self.order.push(nyt);
+
+        self.leaves.insert(symbol, leaf);
+        self.nyt = nyt;
+        leaf
+    }
+
+    // Highest numbered node with the same weight as `node`, excluding its parent.
+    //
+    // Only the nodes numbered above `node` are searched: they haven't been touched
+    // by the current update yet, so their weights are still sorted. If the parent
+    // leads the block (`node` is the sibling of the NYT leaf), every node between
+    // the parent and `node` has the same weight, so the one after the parent is used.
+    fn block_leader(&self, node: usize) -> usize {
+        let weight = self.nodes[node].weight;
+        let candidates = &self.order[..=self.position[node]];
+        let mut index = candidates.partition_point(|&id| self.nodes[id].weight > weight);
+        if Some(self.order[index]) == self.nodes[node].parent {
+            index += 1;
+        }
+        self.order[index]
+    }
+
+    // Exchange two subtrees (neither is an ancestor of the other).
+    fn swap(&mut self, a: usize, b: usize) {
+        let parent_a = self.nodes[a].parent.unwrap();
+        let parent_b = self.nodes[b].parent.unwrap();
+        if parent_a == parent_b {
+            let (left, right) = self.nodes[parent_a].children.unwrap();
+            self.nodes[parent_a].children = Some((right, left));
+        } else {
+            Self::replace_child(&mut self.nodes[parent_a], a, b);
+            Self::replace_child(&mut self.nodes[parent_b], b, a);
+            self.nodes[a].parent = Some(parent_b);
+            self.nodes[b].parent = Some(parent_a);
+        }
+
+        let (pos_a, pos_b) = (self.position[a], self.position[b]);
+        self.order.swap(pos_a, pos_b);
+        self.position[a] = pos_b;
+        self.position[b] = pos_a;
+    }
+
+    fn replace_child(parent: &mut Node<T>, old: usize, new: usize) {
+        let (left, right) = parent.children.unwrap();
+        parent.children = Some(if left == old { (new, right) } else { (left, new) });
+    }
+}
+
+impl<T> Default for AdaptiveHuffman<T>
+where
+    T: HuffmanSymbol,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// One-pass encoding: works on any iterator, no `Clone` needed.
+pub fn adaptive_huffman_encode<T, I>(data: I) -> BitBuffer
+where
+    T: HuffmanSymbol,
+    I: IntoIterator<Item = T>,
+{
+    let mut model = AdaptiveHuffman::new();
+    let mut writer = BitWriter::new();
+    for symbol in data {
+        model.encode_symbol(symbol, &mut writer);
+    }
+    writer.finish()
+}
+
+pub fn adaptive_huffman_decode<T>(bits: &BitBuffer) -> Result<Vec<T>, DecodeError>
+where
+    T: HuffmanSymbol,
+{
+    let mut model = AdaptiveHuffman::new();
+    let mut reader = bits.reader();
+    let mut decoded = Vec::new();
+    while !reader.is_empty() {
+        let position = reader.position();
+        let symbol = model.decode_symbol(&mut reader).ok_or(DecodeError::InvalidCode { position })?;
+        decoded.push(symbol);
+    }
+    Ok(decoded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip<T: HuffmanSymbol + std::fmt::Debug>(data: &[T]) -> BitBuffer {
+        let bits = adaptive_huffman_encode(data.iter().copied());
+        assert_eq!(adaptive_huffman_decode::<T>(&bits).unwrap(), data);
+        bits
+    }
+
+    // Weights are non-increasing in `order`, and every internal node weighs as much as its children
+    fn check_sibling_property<T: HuffmanSymbol>(model: &AdaptiveHuffman<T>) {
+        let weights: Vec<u64> = model.order.iter().map(|&id| model.nodes[id].weight).collect();
+        assert!(weights.windows(2).all(|pair| pair[0] >= pair[1]), "{:?}", weights);
+        for node in &model.nodes {
+            if let Some((left, right)) = node.children {
+                assert_eq!(node.weight, model.nodes[left].weight + model.nodes[right].weight);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(round_trip::<u8>(&[]).is_empty());
+    }
+
+    #[test]
+    fn one_symbol() {
+        // The raw symbol once, then one bit per repetition
+        let bits = round_trip(&[i16::MIN; 50]);
+        assert_eq!(bits.len(), i16::BITS as usize + 49);
+    }
+
+    #[test]
+    fn skewed_input() {
+        let mut data: Vec<u32> = (0..3000).map(|i| if i % 10 == 0 { i % 7 } else { 0 }).collect();
+        data.extend([u32::MAX, 0, u32::MAX]);
+        round_trip(&data);
+
+        let mut model = AdaptiveHuffman::new();
+        let mut writer = BitWriter::new();
+        for &symbol in &data {
+            model.encode_symbol(symbol, &mut writer);
+            check_sibling_property(&model);
+        }
+        assert_eq!(model.nodes[model.root].weight, data.len() as u64);
+    }
+
+    #[test]
+    fn truncated_streams_are_errors() {
+        let bits = round_trip(&[1u8, 2, 3, 1, 1]);
+        let truncated = BitBuffer::from_bytes(bits.as_bytes().to_vec(), bits.len() - 3).unwrap();
+        assert!(matches!(adaptive_huffman_decode::<u8>(&truncated), Err(DecodeError::InvalidCode { .. })));
+    }
+}
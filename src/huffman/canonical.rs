@@ -0,0 +1,331 @@
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hash;
+
+use crate::bitio::{BitBuffer, BitReader, BitWriter, DecodeError};
+
+use super::HuffmanNode;
+
+// Header field widths
+const COUNT_BITS: u32 = 32;
+const WIDTH_BITS: u32 = 7;
+const LENGTH_BITS: u32 = 6;
+
+/// A symbol that can be written to a canonical Huffman header.
+///
+/// `to_u64` must preserve the order of the symbols, so that the header can store
+/// every symbol as a (small) offset from the smallest one.
+pub trait HuffmanSymbol: Ord + Copy + Hash {
+    const BITS: u32;
+
+    fn to_u64(self) -> u64;
+    fn from_u64(value: u64) -> Self;
+}
+
+macro_rules! impl_unsigned_symbol {
+    ($($t:ty),*) => {$(
+        impl HuffmanSymbol for $t {
+            const BITS: u32 = <$t>::BITS;
+
+            fn to_u64(self) -> u64 {
+                self as u64
+            }
+
+            fn from_u64(value: u64) -> Self {
+                value as $t
+            }
+        }
+    )*};
+}
+
+// Signed values are offset by their minimum so that the mapping stays monotonic
+macro_rules! impl_signed_symbol {
+    ($($t:ty => $u:ty),*) => {$(
+        impl HuffmanSymbol for $t {
+            const BITS: u32 = <$t>::BITS;
+
+            fn to_u64(self) -> u64 {
+                ((self as $u) ^ (1 << (<$t>::BITS - 1))) as u64
+            }
+
+            fn from_u64(value: u64) -> Self {
+                ((value as $u) ^ (1 << (<$t>::BITS - 1))) as $t
+            }
+        }
+    )*};
+}
+
+impl_unsigned_symbol!(u8, u16, u32, u64);
+impl_signed_symbol!(i8 => u8, i16 => u16, i32 => u32, i64 => u64);
+
+/// Canonical Huffman code, fully described by the code length of each symbol.
+///
+/// Codes are assigned in order of (length, symbol): the first symbol gets the all-zero
+/// code of its length and every following code is the previous one plus one, shifted
+/// left whenever the length grows. Only the lengths need to be transmitted.
+///
+/// A code without symbols is allowed: it's the code of empty data.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CanonicalHuffman<T>
+where
+    T: HuffmanSymbol,
+{
+    lengths: BTreeMap<T, u32>,
+    // Symbols sorted by (length, symbol), i.e. in code order
+    sorted: Vec<T>,
+    // Number of codes of each length (index = length)
+    counts: Vec<u64>,
+}
+
+impl<T> CanonicalHuffman<T>
+where
+    T: HuffmanSymbol,
+{
+    pub fn from_lengths(lengths: BTreeMap<T, u32>) -> Self {
+        assert!(
+            lengths.values().all(|&len| (1..64).contains(&len)),
+            "code lengths must be between 1 and 63"
+        );
+        let mut sorted: Vec<T> = lengths.keys().copied().collect();
+        sorted.sort_by_key(|symbol| (lengths[symbol], *symbol));
+
+        let max_length = lengths.values().copied().max().unwrap_or(0) as usize;
+        let mut counts = vec![0u64; max_length + 1];
+        for &len in lengths.values() {
+            counts[len as usize] += 1;
+        }
+        CanonicalHuffman { lengths, sorted, counts }
+    }
+
+    pub fn from_tree(tree: &HuffmanNode<T>) -> Self {
+        Self::from_lengths(code_lengths(tree))
+    }
+
+    pub fn lengths(&self) -> &BTreeMap<T, u32> {
+        &self.lengths
+    }
+
+    pub fn max_length(&self) -> u32 {
+        (self.counts.len() - 1) as u32
+    }
+
+    /// Map each symbol to its (code, length) pair, code right-aligned in the u64.
+    pub fn codes(&self) -> HashMap<T, (u64, u32)> {
+        let mut codes = HashMap::new();
+        let mut code = 0u64;
+        let mut prev_len = self.sorted.first().map_or(0, |symbol| self.lengths[symbol]);
+        for &symbol in &self.sorted {
+            let len = self.lengths[&symbol];
+            code <<= len - prev_len;
+            codes.insert(symbol, (code, len));
+            code += 1;
+            prev_len = len;
+        }
+        codes
+    }
+
+    /// Write the (symbol, length) table.
+    ///
+    /// Layout: number of entries (32 bits), smallest symbol (`T::BITS` bits),
+    /// width of the symbol offsets (7 bits), then one (offset, length) pair per
+    /// symbol in increasing symbol order, with lengths on 6 bits.
+    pub fn write_header(&self, writer: &mut BitWriter) {
+        let (min, width) = self.symbol_range();
+
+        writer.write_bits(self.lengths.len() as u64, COUNT_BITS);
+        writer.write_bits(min, T::BITS);
+        writer.write_bits(width as u64, WIDTH_BITS);
+        for (&symbol, &len) in &self.lengths {
+            writer.write_bits(symbol.to_u64() - min, width);
+            writer.write_bits(len as u64, LENGTH_BITS);
+        }
+    }
+
+    /// Rebuild the code from a header written by `write_header`.
+    pub fn read_header(reader: &mut BitReader) -> Option<Self> {
+        let count = reader.read_bits(COUNT_BITS)? as usize;
+        let min = reader.read_bits(T::BITS)?;
+        let width = reader.read_bits(WIDTH_BITS)? as u32;
+        if width > 64 {
+            return None;
+        }
+        let mut lengths = BTreeMap::new();
+        for _ in 0..count {
+            let symbol = T::from_u64(min.wrapping_add(reader.read_bits(width)?));
+            let len = reader.read_bits(LENGTH_BITS)? as u32;
+            if len == 0 {
+                return None;
+            }
+            lengths.insert(symbol, len);
+        }
+        // The lengths of a prefix code satisfy the Kraft inequality: sum of 2^-len <= 1
+        let kraft: u128 = lengths.values().map(|&len| 1u128 << (64 - len)).sum();
+        if kraft > 1 << 64 {
+            return None;
+        }
+        Some(Self::from_lengths(lengths))
+    }
+
+    // Size of the header written by `write_header`, in bits.
+    pub fn header_bits(&self) -> usize {
+        let (_, width) = self.symbol_range();
+        (COUNT_BITS + T::BITS + WIDTH_BITS) as usize + self.lengths.len() * (width + LENGTH_BITS) as usize
+    }
+
+    // Smallest symbol and number of bits of the offsets from it (both 0 without symbols).
+    fn symbol_range(&self) -> (u64, u32) {
+        match (self.lengths.keys().next(), self.lengths.keys().next_back()) {
+            (Some(min), Some(max)) => (min.to_u64(), u64::BITS - (max.to_u64() - min.to_u64()).leading_zeros()),
+            _ => (0, 0),
+        }
+    }
+
+    /// Decode one symbol, reading one bit at a time and comparing against the
+    /// first canonical code of each length. Returns `None` at the end of the stream.
+    pub fn decode_symbol(&self, reader: &mut BitReader) -> Option<T> {
+        let mut code = 0u64;
+        // First code of the current length and index of its symbol in `sorted`
+        let mut first = 0u64;
+        let mut index = 0u64;
+        for &count in &self.counts[1..] {
+            code |= reader.read_bit()? as u64;
+            if code.wrapping_sub(first) < count {
+                return Some(self.sorted[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        None
+    }
+
+    pub fn encode<I>(&self, data: I, writer: &mut BitWriter)
+    where
+        I: Iterator<Item = T>,
+    {
+        let codes = self.codes();
+        for symbol in data {
+            let (code, len) = codes[&symbol];
+            writer.write_bits(code, len);
+        }
+    }
+
+    // Decode symbols until the reader is exhausted.
+    pub fn decode(&self, reader: &mut BitReader) -> Result<Vec<T>, DecodeError> {
+        let mut decoded = Vec::new();
+        while !reader.is_empty() {
+            let position = reader.position();
+            let symbol = self.decode_symbol(reader).ok_or(DecodeError::InvalidCode { position })?;
+            decoded.push(symbol);
+        }
+        Ok(decoded)
+    }
+}
+
+/// Code length of every leaf of the tree (its depth, at least 1).
+pub fn code_lengths<T>(tree: &HuffmanNode<T>) -> BTreeMap<T, u32>
+where
+    T: Ord + Copy,
+{
+    fn walk<T: Ord + Copy>(node: &HuffmanNode<T>, depth: u32, lengths: &mut BTreeMap<T, u32>) {
+        match node {
+            HuffmanNode::Internal { left, right } => {
+                walk(left, depth + 1, lengths);
+                walk(right, depth + 1, lengths);
+            }
+            HuffmanNode::Leaf { value, .. } => {
+                lengths.insert(*value, depth.max(1));
+            }
+        }
+    }
+    let mut lengths = BTreeMap::new();
+    walk(tree, 0, &mut lengths);
+    lengths
+}
+
+/// Decode a stream made of a canonical header followed by the encoded symbols.
+pub fn canonical_decode<T>(bits: &BitBuffer) -> Result<Vec<T>, DecodeError>
+where
+    T: HuffmanSymbol,
+{
+    let mut reader = bits.reader();
+    let table = CanonicalHuffman::read_header(&mut reader).ok_or(DecodeError::InvalidHeader)?;
+    table.decode(&mut reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn code<T: HuffmanSymbol>(lengths: &[(T, u32)]) -> CanonicalHuffman<T> {
+        CanonicalHuffman::from_lengths(lengths.iter().copied().collect())
+    }
+
+    // Header followed by `data`
+    fn encode<T: HuffmanSymbol>(table: &CanonicalHuffman<T>, data: &[T]) -> BitBuffer {
+        let mut writer = BitWriter::new();
+        table.write_header(&mut writer);
+        assert_eq!(writer.position(), table.header_bits());
+        table.encode(data.iter().copied(), &mut writer);
+        writer.finish()
+    }
+
+    #[test]
+    fn codes_are_canonical() {
+        let table = code(&[(b'a', 2), (b'b', 1), (b'c', 3), (b'd', 3)]);
+        let codes = table.codes();
+        assert_eq!(codes[&b'b'], (0b0, 1));
+        assert_eq!(codes[&b'a'], (0b10, 2));
+        assert_eq!(codes[&b'c'], (0b110, 3));
+        assert_eq!(codes[&b'd'], (0b111, 3));
+        assert_eq!(table.max_length(), 3);
+    }
+
+    #[test]
+    fn round_trips() {
+        let skewed = code(&[(-3i64, 1), (0, 2), (i64::MAX, 3), (i64::MIN, 3)]);
+        let data = [-3, -3, i64::MIN, 0, -3, i64::MAX, -3];
+        assert_eq!(canonical_decode::<i64>(&encode(&skewed, &data)).unwrap(), data);
+
+        // One symbol, and no data at all
+        let single = code(&[(9u16, 1)]);
+        assert_eq!(canonical_decode::<u16>(&encode(&single, &[9; 5])).unwrap(), vec![9; 5]);
+        assert_eq!(canonical_decode::<u16>(&encode(&single, &[])).unwrap(), Vec::<u16>::new());
+        let empty = CanonicalHuffman::<u8>::from_lengths(BTreeMap::new());
+        assert_eq!(canonical_decode::<u8>(&encode(&empty, &[])).unwrap(), Vec::<u8>::new());
+    }
+
+    #[test]
+    fn header_round_trips() {
+        let original = code(&[(200u8, 2), (3, 2), (17, 2), (90, 3), (91, 3)]);
+        let bits = encode(&original, &[]);
+        assert_eq!(CanonicalHuffman::read_header(&mut bits.reader()), Some(original));
+    }
+
+    #[test]
+    fn invalid_streams_are_errors() {
+        // Lengths 1, 1 and 2 violate the Kraft inequality
+        let mut writer = BitWriter::new();
+        writer.write_bits(3, COUNT_BITS);
+        writer.write_bits(0, u8::BITS);
+        writer.write_bits(2, WIDTH_BITS);
+        for (offset, len) in [(0, 1), (1, 1), (2, 2)] {
+            writer.write_bits(offset, 2);
+            writer.write_bits(len, LENGTH_BITS);
+        }
+        assert_eq!(canonical_decode::<u8>(&writer.finish()), Err(DecodeError::InvalidHeader));
+
+        // A truncated code
+        let table = code(&[(1u8, 1), (2, 2), (3, 2)]);
+        let bits = encode(&table, &[3]);
+        let truncated = BitBuffer::from_bytes(bits.as_bytes().to_vec(), bits.len() - 1).unwrap();
+        let position = table.header_bits();
+        assert_eq!(canonical_decode::<u8>(&truncated), Err(DecodeError::InvalidCode { position }));
+
+        // An incomplete code leaves unused bit patterns
+        let table = code(&[(1u8, 1), (2, 2)]);
+        let mut writer = BitWriter::new();
+        table.write_header(&mut writer);
+        writer.write_bits(0b11, 2);
+        assert!(matches!(canonical_decode::<u8>(&writer.finish()), Err(DecodeError::InvalidCode { .. })));
+    }
+}
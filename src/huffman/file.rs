@@ -0,0 +1,246 @@
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use crate::bitio::BitBuffer;
+use crate::entropy::data_entropy;
+use crate::fileio::{read_prologue, write_checksum, write_prologue, FileError};
+
+use super::canonical::CanonicalHuffman;
+use super::{huffman_encode, HuffmanEncoded};
+
+/*
+    Huffman compression of arbitrary files, byte by byte.
+
+    The data is split into blocks of `block_size` bytes (the last one may be shorter;
+    a block size of 0 keeps the whole file in one block), and every block gets its own
+    canonical Huffman table, so the code follows the statistics of each part of the file.
+
+    Compressed file layout (integers are little-endian):
+
+        magic        4 bytes   "EETH"
+        version      u8        `VERSION`
+        block size   u32       0 = a single block
+        blocks       u32
+        then, for every block:
+            bytes        u32       number of bytes in the block
+            payload len  u64       number of payload bits
+            payload      ...       canonical code-length header and codes, packed,
+                                   the last byte zero-padded
+        checksum     u32       CRC-32 of every byte above
+*/
+
+pub const MAGIC: [u8; 4] = *b"EETH";
+pub const VERSION: u8 = 1;
+
+/// A Huffman-coded block and the number of bytes it holds.
+pub struct Block {
+    pub len: usize,
+    pub encoded: HuffmanEncoded<u8>,
+}
+
+/// Sizes and entropy of a block.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockStats {
+    pub bytes: usize,
+    pub entropy: f64,
+    pub table_bits: usize,
+    pub payload_bits: usize,
+}
+
+impl BlockStats {
+    // Bits per byte, code-length table included.
+    pub fn bits_per_byte(&self) -> f64 {
+        (self.table_bits + self.payload_bits) as f64 / self.bytes as f64
+    }
+}
+
+/// A compressed file.
+pub struct HuffmanFile {
+    pub block_size: u32,
+    pub blocks: Vec<Block>,
+}
+
+impl HuffmanFile {
+    /// Compress `data` in blocks of `block_size` bytes (0 = a single block).
+    pub fn compress(data: &[u8], block_size: u32) -> Self {
+        let size = if block_size == 0 { data.len().max(1) } else { block_size as usize };
+        let blocks = data
+            .chunks(size)
+            .map(|chunk| Block { len: chunk.len(), encoded: huffman_encode(chunk.iter().copied()) })
+            .collect();
+        HuffmanFile { block_size, blocks }
+    }
+
+    /// Decode every block, checking that each one gives back the stored number of bytes.
+    pub fn decompress(&self) -> Result<Vec<u8>, FileError> {
+        // The block lengths come from the file, so don't trust them for the allocation:
+        // every byte takes at least one bit
+        let mut data = Vec::with_capacity(self.len().min(self.bits()));
+        for block in &self.blocks {
+            let decoded = block.encoded.decode().map_err(|_| FileError::Corrupt("block doesn't decode"))?;
+            if decoded.len() != block.len {
+                return Err(FileError::Corrupt("block decodes to the wrong number of bytes"));
+            }
+            data.extend(decoded);
+        }
+        Ok(data)
+    }
+
+    // Size of the original data.
+    pub fn len(&self) -> usize {
+        self.blocks.iter().map(|block| block.len).sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Size of the coded blocks, tables included.
+    pub fn bits(&self) -> usize {
+        self.blocks.iter().map(|block| block.encoded.bits()).sum()
+    }
+
+    /// Entropy and coded size of every block. `data` must be the bytes that were
+    /// compressed: the entropy is computed from them rather than from decoded blocks.
+    pub fn block_stats(&self, data: &[u8]) -> Vec<BlockStats> {
+        assert_eq!(data.len(), self.len(), "the data doesn't match the compressed file");
+        let mut rest = data;
+        self.blocks
+            .iter()
+            .map(|block| {
+                let (bytes, tail) = rest.split_at(block.len);
+                rest = tail;
+                BlockStats {
+                    bytes: block.len,
+                    entropy: data_entropy(bytes.iter().copied()),
+                    table_bits: block.encoded.table_bits,
+                    payload_bits: block.encoded.payload_bits(),
+                }
+            })
+            .collect()
+    }
+
+    /// Serialize the file (see the layout above).
+    pub fn to_bytes(&self) -> Result<Vec<u8>, FileError> {
+        let blocks = u32::try_from(self.blocks.len()).map_err(|_| FileError::Corrupt("too many blocks"))?;
+        let mut bytes = write_prologue(MAGIC, VERSION);
+        bytes.extend_from_slice(&self.block_size.to_le_bytes());
+        bytes.extend_from_slice(&blocks.to_le_bytes());
+        for block in &self.blocks {
+            let len = u32::try_from(block.len).map_err(|_| FileError::Corrupt("block too large"))?;
+            bytes.extend_from_slice(&len.to_le_bytes());
+            bytes.extend_from_slice(&(block.encoded.bits() as u64).to_le_bytes());
+            bytes.extend_from_slice(block.encoded.encoded_bits.as_bytes());
+        }
+        write_checksum(&mut bytes);
+        Ok(bytes)
+    }
+
+    /// Parse a file written by `to_bytes`, checking the checksum first.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, FileError> {
+        let (version, mut fields) = read_prologue(bytes, MAGIC)?;
+        if version != VERSION {
+            return Err(FileError::UnsupportedVersion(version));
+        }
+        let block_size = fields.u32()?;
+        let count = fields.u32()?;
+        let mut blocks = Vec::new();
+        for _ in 0..count {
+            let len = fields.u32()? as usize;
+            let bits = usize::try_from(fields.u64()?).map_err(|_| FileError::Corrupt("payload too large"))?;
+            let payload = fields.take(bits.div_ceil(8))?;
+            let encoded_bits =
+                BitBuffer::from_bytes(payload.to_vec(), bits).map_err(|_| FileError::Corrupt("block too short"))?;
+            let mut reader = encoded_bits.reader();
+            let table = CanonicalHuffman::read_header(&mut reader).ok_or(FileError::Corrupt("invalid Huffman table"))?;
+            let table_bits = reader.position();
+            blocks.push(Block { len, encoded: HuffmanEncoded { table, table_bits, encoded_bits } });
+        }
+        fields.finish("trailing bytes after the last block")?;
+        Ok(HuffmanFile { block_size, blocks })
+    }
+}
+
+pub fn save_huffman_file<P: AsRef<Path>>(path: P, file: &HuffmanFile) -> Result<(), FileError> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    writer.write_all(&file.to_bytes()?)?;
+    writer.flush()?;
+    Ok(())
+}
+
+pub fn load_huffman_file<P: AsRef<Path>>(path: P) -> Result<HuffmanFile, FileError> {
+    let mut bytes = Vec::new();
+    BufReader::new(File::open(path)?).read_to_end(&mut bytes)?;
+    HuffmanFile::from_bytes(&bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Replace the checksum of a modified file with a valid one
+    fn rechecksum(mut bytes: Vec<u8>) -> Vec<u8> {
+        bytes.truncate(bytes.len() - 4);
+        write_checksum(&mut bytes);
+        bytes
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut data = b"abracadabra ".repeat(50);
+        data.extend(0..=255);
+        data
+    }
+
+    #[test]
+    fn round_trips() {
+        let data = sample();
+        for block_size in [0, 1, 100, 10_000] {
+            let file = HuffmanFile::compress(&data, block_size);
+            let parsed = HuffmanFile::from_bytes(&file.to_bytes().unwrap()).unwrap();
+            assert_eq!(parsed.block_size, block_size);
+            assert_eq!(parsed.blocks.len(), file.blocks.len());
+            assert_eq!(parsed.decompress().unwrap(), data);
+        }
+        let empty = HuffmanFile::compress(&[], 0);
+        assert!(HuffmanFile::from_bytes(&empty.to_bytes().unwrap()).unwrap().decompress().unwrap().is_empty());
+    }
+
+    #[test]
+    fn bad_magic_and_checksum() {
+        let bytes = HuffmanFile::compress(&sample(), 100).to_bytes().unwrap();
+
+        let mut wrong_magic = bytes.clone();
+        wrong_magic[0] = b'X';
+        assert!(matches!(HuffmanFile::from_bytes(&wrong_magic), Err(FileError::BadMagic(_))));
+
+        let mut flipped = bytes.clone();
+        flipped[20] ^= 1;
+        assert!(matches!(HuffmanFile::from_bytes(&flipped), Err(FileError::ChecksumMismatch { .. })));
+    }
+
+    #[test]
+    fn truncated_blocks_are_errors() {
+        let bytes = HuffmanFile::compress(&sample(), 0).to_bytes().unwrap();
+        // Header: magic, version, block size and count; the first block starts with its length
+        let block = 4 + 1 + 4 + 4;
+
+        // The file ends in the middle of the payload
+        let cut = rechecksum(bytes[..bytes.len() - 20].to_vec());
+        assert!(matches!(HuffmanFile::from_bytes(&cut), Err(FileError::Corrupt(_))));
+
+        // The payload holds fewer bits than the block needs
+        let mut short = bytes.clone();
+        let bits = u64::from_le_bytes(short[block + 4..block + 12].try_into().unwrap());
+        short[block + 4..block + 12].copy_from_slice(&(bits - 8).to_le_bytes());
+        short.remove(block + 12 + (bits as usize).div_ceil(8) - 1);
+        let file = HuffmanFile::from_bytes(&rechecksum(short)).unwrap();
+        assert!(matches!(file.decompress(), Err(FileError::Corrupt(_))));
+
+        // A block claiming 4 GB decodes to an error instead of allocating them
+        let mut huge = bytes;
+        huge[block..block + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        let file = HuffmanFile::from_bytes(&rechecksum(huge)).unwrap();
+        assert!(matches!(file.decompress(), Err(FileError::Corrupt(_))));
+    }
+}
@@ -0,0 +1,212 @@
+use std::collections::BTreeMap;
+
+use super::canonical::{CanonicalHuffman, HuffmanSymbol};
+use super::{build_histogram, encode_with_table, HuffmanEncoded};
+
+#[derive(Clone, Copy)]
+enum Item {
+    // Index of the symbol in the sorted leaf list
+    Leaf(usize),
+    // Package made of items 2i and 2i + 1 of the previous level
+    Package(usize),
+}
+
+/*
+    Package-merge (Larmore & Hirschberg) computes optimal prefix code lengths
+    subject to a maximum code length `L`:
+
+    1. Start with the list of leaves (one item per symbol) sorted by weight.
+    2. Repeat `L - 1` times: pair adjacent items of the current list into packages
+       (weight = sum of both), drop the last item if unpaired, and merge the packages
+       with a fresh copy of the leaves, keeping the list sorted by weight.
+    3. Select the `2n - 2` lightest items of the final list. The code length of a symbol
+       is the number of times its leaf appears inside the selected items.
+*/
+pub fn package_merge<T>(frequencies: &BTreeMap<T, u32>, max_length: u32) -> BTreeMap<T, u32>
+where
+    T: Ord + Copy,
+{
+    let n = frequencies.len();
+    assert!(
+        (1..64).contains(&max_length) && (n as u64) <= 1u64 << max_length,
+        "{} symbols do not fit in codes of at most {} bits",
+        n,
+        max_length
+    );
+    // No data, or a single symbol (which still needs a one-bit code)
+    if n <= 1 {
+        return frequencies.keys().map(|&symbol| (symbol, 1)).collect();
+    }
+
+    let mut leaves: Vec<(T, u64)> = frequencies.iter().map(|(&s, &f)| (s, f as u64)).collect();
+    leaves.sort_by_key(|&(symbol, weight)| (weight, symbol));
+
+    // levels[0] is the deepest level (the bare leaves), levels.last() the top one
+    let mut levels: Vec<Vec<(u64, Item)>> = Vec::with_capacity(max_length as usize);
+    levels.push(
+        leaves
+            .iter()
+            .enumerate()
+            .map(|(i, &(_, weight))| (weight, Item::Leaf(i)))
+            .collect(),
+    );
+    for _ in 1..max_length {
+        let previous = levels.last().unwrap();
+        let packages = previous
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(i, pair)| (pair[0].0 + pair[1].0, Item::Package(i)));
+
+        // Merge leaves and packages by weight (leaves first on ties)
+        let mut merged = Vec::with_capacity(n + previous.len() / 2);
+        let mut leaf_iter = levels[0].iter().copied().peekable();
+        let mut package_iter = packages.peekable();
+        loop {
+            let take_leaf = match (leaf_iter.peek(), package_iter.peek()) {
+                (Some(leaf), Some(package)) => leaf.0 <= package.0,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            if take_leaf {
+                merged.push(leaf_iter.next().unwrap());
+            } else {
+                merged.push(package_iter.next().unwrap());
+            }
+        }
+        levels.push(merged);
+    }
+
+    // Count the occurrences of each leaf inside the 2n - 2 selected items
+    let mut lengths = vec![0u32; n];
+    let mut stack: Vec<(usize, Item)> = levels
+        .last()
+        .unwrap()
+        .iter()
+        .take(2 * n - 2)
+        .map(|&(_, item)| (levels.len() - 1, item))
+        .collect();
+    while let Some((level, item)) = stack.pop() {
+        match item {
+            Item::Leaf(i) => lengths[i] += 1,
+            Item::Package(i) => {
+                stack.push((level - 1, levels[level - 1][2 * i].1));
+                stack.push((level - 1, levels[level - 1][2 * i + 1].1));
+            }
+        }
+    }
+
+    leaves
+        .iter()
+        .zip(lengths)
+        .map(|(&(symbol, _), len)| (symbol, len))
+        .collect()
+}
+
+// Canonical code with lengths limited to `max_length` bits.
+pub fn length_limited_table<T, I>(data: I, max_length: u32) -> CanonicalHuffman<T>
+where
+    T: HuffmanSymbol,
+    I: Iterator<Item = T>,
+{
+    let frequencies = build_histogram(data);
+    CanonicalHuffman::from_lengths(package_merge(&frequencies, max_length))
+}
+
+pub fn huffman_encode_limited<T, I>(data: I, max_length: u32) -> HuffmanEncoded<T>
+where
+    T: HuffmanSymbol,
+    I: Iterator<Item = T> + Clone,
+{
+    let table = length_limited_table(data.clone(), max_length);
+    encode_with_table(table, data)
+}
+
+// Same as `weighted_path_length`, but for the length-limited code (0 for no data).
+pub fn limited_weighted_path_length<T, I>(data: I, max_length: u32) -> f64
+where
+    T: Ord + Copy + std::hash::Hash,
+    I: Iterator<Item = T> + Clone,
+{
+    let frequencies = build_histogram(data);
+    let lengths = package_merge(&frequencies, max_length);
+
+    let total = frequencies.values().sum::<u32>() as f64;
+    if total == 0.0 {
+        return 0.0;
+    }
+    let mut result = 0.0;
+    for (symbol, len) in lengths {
+        result += len as f64 * frequencies[&symbol] as f64;
+    }
+    result / total
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::huffman::weighted_path_length;
+
+    // Sum of 2^-len, scaled by 2^max_length
+    fn kraft_sum(lengths: &BTreeMap<u32, u32>, max_length: u32) -> u64 {
+        lengths.values().map(|&len| 1u64 << (max_length - len)).sum()
+    }
+
+    // Fibonacci frequencies give the deepest Huffman trees
+    fn fibonacci_frequencies(n: u32) -> BTreeMap<u32, u32> {
+        let mut frequencies = BTreeMap::new();
+        let (mut a, mut b) = (1, 1);
+        for symbol in 0..n {
+            frequencies.insert(symbol, a);
+            (a, b) = (b, a + b);
+        }
+        frequencies
+    }
+
+    #[test]
+    fn lengths_are_limited_and_complete() {
+        let frequencies = fibonacci_frequencies(20);
+        for max_length in 5..=20 {
+            let lengths = package_merge(&frequencies, max_length);
+            assert_eq!(lengths.len(), frequencies.len());
+            assert!(lengths.values().all(|&len| (1..=max_length).contains(&len)), "limit {}", max_length);
+            assert_eq!(kraft_sum(&lengths, max_length), 1 << max_length, "limit {}", max_length);
+        }
+        // The tightest limit gives a fixed-length code
+        let lengths = package_merge(&fibonacci_frequencies(16), 4);
+        assert!(lengths.values().all(|&len| len == 4));
+    }
+
+    #[test]
+    fn loose_limit_matches_huffman() {
+        let data: Vec<u32> = (0..2000u32).map(|i| (i * i) % 37 % 11).collect();
+        let huffman = weighted_path_length(data.iter().copied());
+        let limited = limited_weighted_path_length(data.iter().copied(), 30);
+        assert!((huffman - limited).abs() < 1e-12);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut skewed: Vec<u32> = fibonacci_frequencies(20)
+            .iter()
+            .flat_map(|(&symbol, &count)| std::iter::repeat_n(symbol, count as usize))
+            .collect();
+        skewed.push(u32::MAX);
+        for data in [vec![], vec![42; 10], skewed] {
+            let encoded = huffman_encode_limited(data.iter().copied(), 8);
+            assert!(encoded.table.max_length() <= 8);
+            assert_eq!(encoded.decode().unwrap(), data);
+        }
+    }
+
+    #[test]
+    fn weighted_path_lengths() {
+        assert_eq!(limited_weighted_path_length(std::iter::empty::<u8>(), 8), 0.0);
+        assert_eq!(limited_weighted_path_length([7u8; 5].into_iter(), 8), 1.0);
+        assert_eq!(weighted_path_length(std::iter::empty::<u8>()), 0.0);
+        let data = [1u8, 1, 1, 1, 2, 2, 3, 4];
+        assert_eq!(limited_weighted_path_length(data.into_iter(), 8), weighted_path_length(data.into_iter()));
+        // 4 symbols with at most 2 bits each
+        assert_eq!(limited_weighted_path_length(data.into_iter(), 2), 2.0);
+    }
+}
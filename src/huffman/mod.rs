@@ -5,6 +5,15 @@ use std::collections::{BTreeMap, BinaryHeap};
 use std::fmt;
 use std::hash::Hash;
 
+use crate::bitio::{BitBuffer, BitWriter, DecodeError};
+
+pub mod adaptive;
+pub mod canonical;
+pub mod file;
+pub mod length_limited;
+
+use canonical::{canonical_decode, CanonicalHuffman, HuffmanSymbol};
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum HuffmanNode<T>
 where
@@ -190,7 +199,14 @@ fn generate_codes<T>(
             generate_codes(right, right_code, codes);
         }
         HuffmanNode::Leaf { value, .. } => {
-            codes.insert(*value, current_code.into_iter().collect());
+            // A tree with a single leaf would give it an empty code, which can't be
+            // decoded (the number of symbols would be lost), so use a 1-bit code instead.
+            let code: Vec<u8> = if current_code.is_empty() {
+                vec![0]
+            } else {
+                current_code.into_iter().collect()
+            };
+            codes.insert(*value, code);
         }
     }
 }
@@ -204,23 +220,73 @@ pub fn normalize_histogram(histogram: &BTreeMap<u8, u32>) -> BTreeMap<u8, f32> {
     normalized
 }
 
-pub fn huffman_encode<T, I>(data: I) -> Vec<u8>
+/// Huffman-coded data: a canonical code-length header followed by the encoded symbols.
+pub struct HuffmanEncoded<T>
 where
-    T: Ord + Copy + Hash,
+    T: HuffmanSymbol,
+{
+    pub table: CanonicalHuffman<T>,
+    pub table_bits: usize,
+    pub encoded_bits: BitBuffer,
+}
+
+impl<T> HuffmanEncoded<T>
+where
+    T: HuffmanSymbol,
+{
+    /// Total size, code-length table included.
+    pub fn bits(&self) -> usize {
+        self.encoded_bits.len()
+    }
+
+    /// Size of the encoded symbols alone.
+    pub fn payload_bits(&self) -> usize {
+        self.encoded_bits.len() - self.table_bits
+    }
+
+    /// Decode using only the bitstream (the table is read back from the header).
+    pub fn decode(&self) -> Result<Vec<T>, DecodeError> {
+        canonical_decode(&self.encoded_bits)
+    }
+}
+
+pub fn huffman_encode<T, I>(data: I) -> HuffmanEncoded<T>
+where
+    T: HuffmanSymbol,
     I: Iterator<Item = T> + Clone,
 {
     let frequencies = build_histogram(data.clone());
+    if frequencies.is_empty() {
+        return encode_with_table(CanonicalHuffman::from_lengths(BTreeMap::new()), data);
+    }
     let tree = build_huffman_tree(&frequencies);
-    let mut code_map = HashMap::new();
-    generate_codes(&tree, VecDeque::new(), &mut code_map);
+    encode_with_table(CanonicalHuffman::from_tree(&tree), data)
+}
 
-    let mut result = Vec::new();
-    for byte in data {
-        if let Some(code) = code_map.get(&byte) {
-            result.extend(code.iter());
-        }
+// Write the code-length header of `table`, then the symbols of `data`.
+fn encode_with_table<T, I>(table: CanonicalHuffman<T>, data: I) -> HuffmanEncoded<T>
+where
+    T: HuffmanSymbol,
+    I: Iterator<Item = T>,
+{
+    let mut writer = BitWriter::new();
+    table.write_header(&mut writer);
+    let table_bits = writer.position();
+    table.encode(data, &mut writer);
+    HuffmanEncoded {
+        table,
+        table_bits,
+        encoded_bits: writer.finish(),
     }
-    result
+}
+
+/// Decode symbols coded with `table` and no header (e.g. the payload of a
+/// `HuffmanEncoded` past its `table_bits`), up to the end of `bits`.
+pub fn huffman_decode<T>(bits: &BitBuffer, table: &CanonicalHuffman<T>) -> Result<Vec<T>, DecodeError>
+where
+    T: HuffmanSymbol,
+{
+    table.decode(&mut bits.reader())
 }
 
 pub fn huffman_tree<T, I>(data: I) -> HuffmanNode<T>
@@ -238,6 +304,9 @@ where
     I: Iterator<Item = T> + Clone,
 {
     let frequencies = build_histogram(data.clone());
+    if frequencies.is_empty() {
+        return 0.0;
+    }
     let tree = build_huffman_tree(&frequencies);
     let mut code_map = HashMap::new();
     generate_codes(&tree, VecDeque::new(), &mut code_map);
@@ -249,4 +318,58 @@ where
         result += code.len() as f64 * frequencies[&byte] as f64;
     }
     result / total
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(data: &[i32]) -> HuffmanEncoded<i32> {
+        let encoded = huffman_encode(data.iter().copied());
+        assert_eq!(encoded.decode().unwrap(), data);
+        assert_eq!(encoded.table_bits, encoded.table.header_bits());
+        assert_eq!(encoded.bits(), encoded.table_bits + encoded.payload_bits());
+        encoded
+    }
+
+    #[test]
+    fn empty_input() {
+        let encoded = round_trip(&[]);
+        assert_eq!(encoded.payload_bits(), 0);
+        assert!(encoded.table.lengths().is_empty());
+    }
+
+    #[test]
+    fn one_symbol() {
+        // A single symbol still takes one bit per value
+        let encoded = round_trip(&[-7; 100]);
+        assert_eq!(encoded.payload_bits(), 100);
+        assert_eq!(weighted_path_length([-7; 100].into_iter()), 1.0);
+    }
+
+    #[test]
+    fn skewed_input() {
+        let mut data = vec![0; 1000];
+        data.extend([1; 100]);
+        data.extend([-1; 10]);
+        data.extend([i32::MIN, i32::MAX]);
+        let encoded = round_trip(&data);
+        assert_eq!(encoded.table.lengths()[&0], 1);
+        let expected = weighted_path_length(data.iter().copied()) * data.len() as f64;
+        assert_eq!(encoded.payload_bits() as f64, expected);
+    }
+
+    #[test]
+    fn decode_with_the_table() {
+        let data = [3u8, 1, 4, 1, 5, 9, 2, 6, 5, 3, 5];
+        let encoded = huffman_encode(data.iter().copied());
+        let mut writer = BitWriter::new();
+        encoded.table.encode(data.iter().copied(), &mut writer);
+        let payload = writer.finish();
+        assert_eq!(payload.len(), encoded.payload_bits());
+        assert_eq!(huffman_decode(&payload, &encoded.table).unwrap(), data);
+
+        let truncated = BitBuffer::from_bytes(payload.as_bytes().to_vec(), payload.len() - 1).unwrap();
+        assert!(matches!(huffman_decode(&truncated, &encoded.table), Err(DecodeError::InvalidCode { .. })));
+    }
+}
@@ -0,0 +1,458 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use image::{DynamicImage, GrayImage, ImageBuffer, RgbImage};
+
+use crate::color::plane_count;
+use crate::depth::{check_maxval, image_bit_depth, max_value, maxval_bit_depth, DepthError, Gray16Image, MAX_BIT_DEPTH};
+
+/*
+    Image files read and written without the image crate:
+
+    - PGM (P5, grayscale) and PPM (P6, RGB). The header is the magic number, width,
+      height and maxval, separated by whitespace (with `#` comments up to the end of
+      the line), then a single whitespace character before the samples. Samples take
+      1 byte if maxval < 256 and 2 bytes (big-endian) otherwise. Sample values are kept
+      as they are, and the maxval gives the bit depth, so a 12-bit image (maxval 4095)
+      stays 12-bit. The maxval is kept with the image and written back as it was.
+      The ASCII variants (P2 and P3), with decimal samples separated by whitespace, are
+      read but not written.
+
+    - Raw grayscale dumps: width * height samples in raster order, with no header.
+      Samples of up to 8 bits take 1 byte, deeper ones 2 bytes in the given byte order.
+*/
+
+// Extensions handled by the PNM reader and writer
+pub const PNM_EXTENSIONS: [&str; 3] = ["pgm", "ppm", "pnm"];
+
+#[derive(Debug)]
+pub enum ImageIoError {
+    Io(io::Error),
+    Image(image::ImageError),
+    // The PNM header or the raw data don't make sense
+    Malformed(&'static str),
+    // The image can't be written in the requested format
+    Unsupported(&'static str),
+    // The image doesn't fit in the bit depth it's written with
+    Depth(DepthError),
+}
+
+impl fmt::Display for ImageIoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ImageIoError::Io(err) => write!(f, "I/O error: {}", err),
+            ImageIoError::Image(err) => write!(f, "{}", err),
+            ImageIoError::Malformed(what) => write!(f, "malformed image: {}", what),
+            ImageIoError::Unsupported(what) => write!(f, "unsupported: {}", what),
+            ImageIoError::Depth(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl Error for ImageIoError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ImageIoError::Io(err) => Some(err),
+            ImageIoError::Image(err) => Some(err),
+            ImageIoError::Depth(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ImageIoError {
+    fn from(err: io::Error) -> Self {
+        ImageIoError::Io(err)
+    }
+}
+
+impl From<image::ImageError> for ImageIoError {
+    fn from(err: image::ImageError) -> Self {
+        ImageIoError::Image(err)
+    }
+}
+
+/// Byte order of 16-bit raw samples.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Endianness {
+    Little,
+    Big,
+}
+
+/// Layout of a raw grayscale dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RawFormat {
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub endianness: Endianness,
+}
+
+/// An image and the largest sample value its file declares: the maxval of PNM files,
+/// 2^depth - 1 for raw dumps and for other formats (see `depth`).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SourceImage {
+    pub image: DynamicImage,
+    pub maxval: u16,
+}
+
+impl SourceImage {
+    /// An image with the maxval of its pixel type.
+    pub fn new(image: DynamicImage) -> Self {
+        let maxval = max_value(image_bit_depth(&image)) as u16;
+        SourceImage { image, maxval }
+    }
+
+    /// Bit depth the image is coded with (see `depth::maxval_bit_depth`).
+    pub fn bit_depth(&self) -> u8 {
+        maxval_bit_depth(self.maxval)
+    }
+}
+
+/// Read a PGM (P5, or P2 in ASCII) or PPM (P6, or P3 in ASCII) image.
+pub fn read_pnm<R: Read>(reader: &mut R) -> Result<SourceImage, ImageIoError> {
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let mut header = Header { bytes: &bytes, position: 0 };
+    let magic = header.take(2)?;
+    let (channels, ascii) = match magic {
+        b"P2" => (1, true),
+        b"P3" => (3, true),
+        b"P5" => (1, false),
+        b"P6" => (3, false),
+        _ => return Err(ImageIoError::Malformed("not a PGM or PPM file")),
+    };
+    let width = header.number()?;
+    let height = header.number()?;
+    let maxval = header.number()?;
+    if width == 0 || height == 0 || !(1..=65535).contains(&maxval) {
+        return Err(ImageIoError::Malformed("invalid PNM header"));
+    }
+    let maxval = maxval as u16;
+    let count = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or(ImageIoError::Malformed("image dimensions too large"))?;
+
+    let samples: Vec<u16> = if ascii {
+        // Every sample takes at least a digit: don't trust the header for the allocation
+        let mut samples = Vec::with_capacity(count.min(bytes.len()));
+        for _ in 0..count {
+            samples.push(u16::try_from(header.number()?).map_err(|_| ImageIoError::Malformed("sample above maxval"))?);
+        }
+        samples
+    } else {
+        if !header.take(1)?[0].is_ascii_whitespace() {
+            return Err(ImageIoError::Malformed("no whitespace after maxval"));
+        }
+        let sample_bytes = if maxval < 256 { 1 } else { 2 };
+        let size = count.checked_mul(sample_bytes).ok_or(ImageIoError::Malformed("image dimensions too large"))?;
+        let data = header.take(size)?;
+        match sample_bytes {
+            1 => data.iter().map(|&b| b as u16).collect(),
+            _ => data.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect(),
+        }
+    };
+    if samples.iter().any(|&sample| sample > maxval) {
+        return Err(ImageIoError::Malformed("sample above maxval"));
+    }
+
+    let malformed = ImageIoError::Malformed("wrong number of samples");
+    let image = match (channels, maxval < 256) {
+        (1, true) => DynamicImage::ImageLuma8(
+            GrayImage::from_raw(width, height, samples.iter().map(|&s| s as u8).collect()).ok_or(malformed)?,
+        ),
+        (1, false) => DynamicImage::ImageLuma16(Gray16Image::from_raw(width, height, samples).ok_or(malformed)?),
+        (_, true) => DynamicImage::ImageRgb8(
+            RgbImage::from_raw(width, height, samples.iter().map(|&s| s as u8).collect()).ok_or(malformed)?,
+        ),
+        (_, false) => DynamicImage::ImageRgb16(ImageBuffer::from_raw(width, height, samples).ok_or(malformed)?),
+    };
+    Ok(SourceImage { image, maxval })
+}
+
+/// Write `img` as a binary PGM (grayscale) or PPM (RGB) with the given maxval, which
+/// must be at least every sample.
+pub fn write_pnm<W: Write>(writer: &mut W, img: &DynamicImage, maxval: u16) -> Result<(), ImageIoError> {
+    if img.color().has_alpha() {
+        return Err(ImageIoError::Unsupported("PNM images have no alpha channel"));
+    }
+    check_maxval(img, maxval).map_err(ImageIoError::Depth)?;
+    let (width, height) = (img.width(), img.height());
+    let magic = if plane_count(img) == 3 { "P6" } else { "P5" };
+    write!(writer, "{}\n{} {}\n{}\n", magic, width, height, maxval)?;
+    match (plane_count(img), maxval > 255) {
+        (3, true) => write_be_samples(writer, img.to_rgb16().as_raw())?,
+        (3, false) => writer.write_all(img.to_rgb8().as_raw())?,
+        (_, true) => write_be_samples(writer, img.to_luma16().as_raw())?,
+        (_, false) => writer.write_all(img.to_luma8().as_raw())?,
+    }
+    Ok(())
+}
+
+// 16-bit PNM samples are big-endian
+fn write_be_samples<W: Write>(writer: &mut W, samples: &[u16]) -> io::Result<()> {
+    for sample in samples {
+        writer.write_all(&sample.to_be_bytes())?;
+    }
+    Ok(())
+}
+
+/// Read a raw grayscale dump laid out as `format`.
+pub fn read_raw<R: Read>(reader: &mut R, format: &RawFormat) -> Result<SourceImage, ImageIoError> {
+    if !(1..=MAX_BIT_DEPTH).contains(&format.bit_depth) {
+        return Err(ImageIoError::Unsupported("raw bit depths above 16"));
+    }
+    let mut bytes = Vec::new();
+    reader.read_to_end(&mut bytes)?;
+    let sample_bytes = if format.bit_depth > 8 { 2 } else { 1 };
+    let size = (format.width as usize)
+        .checked_mul(format.height as usize)
+        .and_then(|pixels| pixels.checked_mul(sample_bytes));
+    if size != Some(bytes.len()) {
+        return Err(ImageIoError::Malformed("raw data size doesn't match the dimensions"));
+    }
+
+    let max = max_value(format.bit_depth) as u16;
+    let samples: Vec<u16> = match (sample_bytes, format.endianness) {
+        (1, _) => bytes.iter().map(|&b| b as u16).collect(),
+        (_, Endianness::Little) => bytes.chunks_exact(2).map(|pair| u16::from_le_bytes([pair[0], pair[1]])).collect(),
+        (_, Endianness::Big) => bytes.chunks_exact(2).map(|pair| u16::from_be_bytes([pair[0], pair[1]])).collect(),
+    };
+    if samples.iter().any(|&sample| sample > max) {
+        return Err(ImageIoError::Malformed("sample above the bit depth"));
+    }
+
+    let (width, height) = (format.width, format.height);
+    let malformed = ImageIoError::Malformed("empty raw image");
+    if sample_bytes == 1 {
+        let samples = samples.into_iter().map(|s| s as u8).collect();
+        let image = DynamicImage::ImageLuma8(GrayImage::from_raw(width, height, samples).ok_or(malformed)?);
+        Ok(SourceImage { image, maxval: max })
+    } else {
+        let image = DynamicImage::ImageLuma16(Gray16Image::from_raw(width, height, samples).ok_or(malformed)?);
+        Ok(SourceImage { image, maxval: max })
+    }
+}
+
+/// Write the samples of a grayscale image with no header: 1 byte per sample for
+/// 8-bit images, 2 bytes in `endianness` order for deeper ones.
+pub fn write_raw<W: Write>(writer: &mut W, img: &DynamicImage, endianness: Endianness) -> Result<(), ImageIoError> {
+    if plane_count(img) != 1 || img.color().has_alpha() {
+        return Err(ImageIoError::Unsupported("raw output of color images"));
+    }
+    if image_bit_depth(img) > 8 {
+        for pixel in img.to_luma16().pixels() {
+            let bytes = match endianness {
+                Endianness::Little => pixel[0].to_le_bytes(),
+                Endianness::Big => pixel[0].to_be_bytes(),
+            };
+            writer.write_all(&bytes)?;
+        }
+    } else {
+        writer.write_all(img.to_luma8().as_raw())?;
+    }
+    Ok(())
+}
+
+/// Open an image file: PGM/PPM with the reader above, a raw dump if `raw` is given,
+/// anything else with the image crate.
+pub fn open_image<P: AsRef<Path>>(path: P, raw: Option<&RawFormat>) -> Result<SourceImage, ImageIoError> {
+    let path = path.as_ref();
+    match raw {
+        Some(format) => read_raw(&mut BufReader::new(File::open(path)?), format),
+        None if is_pnm(path) => read_pnm(&mut BufReader::new(File::open(path)?)),
+        None => Ok(SourceImage::new(image::open(path)?)),
+    }
+}
+
+/// Save an image: a raw dump if `raw` is given, PGM/PPM (with the given maxval) by
+/// extension, anything else with the image crate (the format follows the extension).
+pub fn save_image<P: AsRef<Path>>(
+    path: P,
+    img: &DynamicImage,
+    maxval: u16,
+    raw: Option<Endianness>,
+) -> Result<(), ImageIoError> {
+    let path = path.as_ref();
+    if raw.is_none() && !is_pnm(path) {
+        return Ok(img.save(path)?);
+    }
+    let mut writer = BufWriter::new(File::create(path)?);
+    match raw {
+        Some(endianness) => write_raw(&mut writer, img, endianness)?,
+        None => write_pnm(&mut writer, img, maxval)?,
+    }
+    Ok(writer.flush()?)
+}
+
+fn is_pnm(path: &Path) -> bool {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| PNM_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+// Reads the whitespace-separated fields of a PNM header
+struct Header<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Header<'a> {
+    fn take(&mut self, count: usize) -> Result<&'a [u8], ImageIoError> {
+        let end = match self.position.checked_add(count) {
+            Some(end) if end <= self.bytes.len() => end,
+            _ => return Err(ImageIoError::Malformed("file truncated")),
+        };
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    // Skip whitespace and comments, then read a decimal number
+    fn number(&mut self) -> Result<u32, ImageIoError> {
+        loop {
+            match self.bytes.get(self.position) {
+                Some(b) if b.is_ascii_whitespace() => self.position += 1,
+                Some(b'#') => {
+                    while self.bytes.get(self.position).is_some_and(|&b| b != b'\n') {
+                        self.position += 1;
+                    }
+                }
+                _ => break,
+            }
+        }
+        let start = self.position;
+        while self.bytes.get(self.position).is_some_and(u8::is_ascii_digit) {
+            self.position += 1;
+        }
+        std::str::from_utf8(&self.bytes[start..self.position])
+            .ok()
+            .and_then(|digits| digits.parse().ok())
+            .ok_or(ImageIoError::Malformed("invalid number in the PNM header"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{Luma, Rgb};
+
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Result<SourceImage, ImageIoError> {
+        read_pnm(&mut &bytes[..])
+    }
+
+    fn written(source: &SourceImage) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        write_pnm(&mut bytes, &source.image, source.maxval).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn reads_headers_with_comments_and_whitespace() {
+        let bytes = b"P5 # comment\n#another one\n 3\t\r\n2 # maxval next\n255\n\x00\x01\x02\x7f\x80\xff";
+        let source = read(bytes).unwrap();
+        assert_eq!(source.maxval, 255);
+        assert_eq!(source.bit_depth(), 8);
+        assert_eq!(source.image.to_luma8().into_raw(), [0, 1, 2, 127, 128, 255]);
+        assert_eq!(written(&source), b"P5\n3 2\n255\n\x00\x01\x02\x7f\x80\xff");
+    }
+
+    #[test]
+    fn binary_and_ascii_files_agree() {
+        let gray = [b"P2\n3 1\n1000\n0 999\n# comment\n1000\n".to_vec(), b"P5\n3 1\n1000\n\x00\x00\x03\xe7\x03\xe8".to_vec()];
+        let rgb = [b"P3 2 1 100 1 2 3 4 5 100".to_vec(), b"P6 2 1 100\n\x01\x02\x03\x04\x05\x64".to_vec()];
+        for [ascii, binary] in [gray, rgb] {
+            let (ascii, binary) = (read(&ascii).unwrap(), read(&binary).unwrap());
+            assert_eq!(ascii, binary);
+            // The maxval is kept, not rounded up to that of the bit depth
+            assert_eq!(read(&written(&ascii)).unwrap(), ascii);
+        }
+        let gray = read(b"P5\n3 1\n1000\n\x00\x00\x03\xe7\x03\xe8").unwrap();
+        assert_eq!((gray.maxval, gray.bit_depth()), (1000, 10));
+        assert_eq!(gray.image, DynamicImage::ImageLuma16(Gray16Image::from_raw(3, 1, vec![0, 999, 1000]).unwrap()));
+        assert!(written(&gray).starts_with(b"P5\n3 1\n1000\n"));
+    }
+
+    #[test]
+    fn deep_images_round_trip() {
+        let gray = Gray16Image::from_fn(5, 4, |x, y| Luma([(x * 800 + y * 3) as u16]));
+        let rgb = ImageBuffer::from_fn(5, 4, |x, y| Rgb([(x * 16000) as u16, (y * 20000) as u16, 65535]));
+        let sources = [
+            SourceImage { image: DynamicImage::ImageLuma16(gray), maxval: 4095 },
+            SourceImage::new(DynamicImage::ImageRgb16(rgb)),
+        ];
+        for source in sources {
+            assert_eq!(read(&written(&source)).unwrap(), source);
+        }
+        let bytes = written(&SourceImage::new(DynamicImage::ImageLuma16(Gray16Image::from_pixel(1, 1, Luma([258])))));
+        assert_eq!(bytes, b"P5\n1 1\n65535\n\x01\x02");
+    }
+
+    #[test]
+    fn malformed_headers_are_errors() {
+        let malformed: [&[u8]; 12] = [
+            b"",
+            b"P7\n1 1\n255\n\x00",
+            b"P5\n0 1\n255\n",
+            b"P5\n1 1\n0\n\x00",
+            b"P5\n1 1\n65536\n\x00\x00",
+            b"P5\n1 x\n255\n\x00",
+            b"P5\n1 1\n255",
+            b"P5\n1 1\n255x\x00",
+            b"P5\n2 2\n255\n\x00\x00\x00",
+            b"P5\n1 1\n100\n\x65",
+            b"P5\n4294967295 4294967295\n65535\n\x00\x00",
+            b"P2\n2 1\n255\n1",
+        ];
+        for bytes in malformed {
+            assert!(matches!(read(bytes), Err(ImageIoError::Malformed(_))), "{:?}", String::from_utf8_lossy(bytes));
+        }
+        assert!(matches!(read(b"P2\n1 1\n255\n70000"), Err(ImageIoError::Malformed(_))));
+    }
+
+    #[test]
+    fn samples_above_the_maxval_are_not_written() {
+        let img = DynamicImage::ImageLuma16(Gray16Image::from_pixel(2, 2, Luma([1001])));
+        assert!(matches!(write_pnm(&mut Vec::new(), &img, 1000), Err(ImageIoError::Depth(_))));
+        assert!(matches!(write_pnm(&mut Vec::new(), &img, 255), Err(ImageIoError::Depth(_))));
+    }
+
+    #[test]
+    fn raw_byte_orders() {
+        let samples: [u16; 6] = [0, 1, 256, 1000, 4094, 4095];
+        let little: Vec<u8> = samples.iter().flat_map(|s| s.to_le_bytes()).collect();
+        let big: Vec<u8> = samples.iter().flat_map(|s| s.to_be_bytes()).collect();
+        let format = |endianness| RawFormat { width: 3, height: 2, bit_depth: 12, endianness };
+        let expected = SourceImage {
+            image: DynamicImage::ImageLuma16(Gray16Image::from_raw(3, 2, samples.to_vec()).unwrap()),
+            maxval: 4095,
+        };
+        for (bytes, endianness) in [(little, Endianness::Little), (big, Endianness::Big)] {
+            let source = read_raw(&mut &bytes[..], &format(endianness)).unwrap();
+            assert_eq!(source, expected);
+            let mut written = Vec::new();
+            write_raw(&mut written, &source.image, endianness).unwrap();
+            assert_eq!(written, bytes);
+        }
+
+        let eight = RawFormat { width: 2, height: 2, bit_depth: 8, endianness: Endianness::Big };
+        let source = read_raw(&mut &[0u8, 1, 2, 255][..], &eight).unwrap();
+        assert_eq!((source.image.to_luma8().into_raw(), source.maxval), (vec![0, 1, 2, 255], 255));
+    }
+
+    #[test]
+    fn malformed_raw_data_are_errors() {
+        let format = RawFormat { width: 2, height: 2, bit_depth: 10, endianness: Endianness::Little };
+        // Too short, too long and a sample above 1023
+        for bytes in [vec![0; 7], vec![0; 9], vec![0, 0, 0, 0, 0, 0, 0, 4]] {
+            assert!(matches!(read_raw(&mut &bytes[..], &format), Err(ImageIoError::Malformed(_))));
+        }
+        let deep = RawFormat { bit_depth: 17, ..format };
+        assert!(matches!(read_raw(&mut &[0u8; 8][..], &deep), Err(ImageIoError::Unsupported(_))));
+    }
+}
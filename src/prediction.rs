@@ -0,0 +1,568 @@
+use std::error::Error;
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use image::{GrayImage, Luma};
+use ndarray::Array2;
+
+use crate::depth::{gray_plane, max_value, GrayBuffer, Sample};
+
+/*
+    Predictive coding: every pixel I(x, y) is replaced by the prediction error
+        P(x, y) = I(x, y) - pred(x, y)
+    where the prediction only uses pixels that come before it in raster order,
+    so the decoder can rebuild the image pixel by pixel:
+        I(x, y) = pred(x, y) + P(x, y)
+
+    Causal neighbours of the current pixel x (pixels outside the image count as 0):
+                NN   NNE
+           NW   N    NE
+       WW  W    x
+
+    Prediction error matrices are indexed [[x, y]], with shape (width, height).
+*/
+
+// Range of the prediction error of every predictor for 8-bit images (W + N - NW is the widest)
+pub const PRED_ERR_ALPHABET: std::ops::RangeInclusive<i32> = -510..=510;
+
+/// `PRED_ERR_ALPHABET` for images of `bit_depth` bits.
+pub fn pred_err_alphabet(bit_depth: u8) -> RangeInclusive<i32> {
+    let max = max_value(bit_depth);
+    -2 * max..=2 * max
+}
+
+/// Already-decoded neighbours of a pixel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Neighbours {
+    pub w: i32,
+    pub n: i32,
+    pub nw: i32,
+    pub ne: i32,
+    pub ww: i32,
+    pub nn: i32,
+    pub nne: i32,
+}
+
+impl Neighbours {
+    // Neighbours of (x, y), with `pixel` returning the value of an in-bounds pixel
+    fn gather<F>(x: usize, y: usize, width: usize, pixel: F) -> Self
+    where
+        F: Fn(usize, usize) -> i32,
+    {
+        let at = |dx: isize, dy: isize| -> i32 {
+            match (x.checked_add_signed(dx), y.checked_add_signed(dy)) {
+                (Some(x), Some(y)) if x < width => pixel(x, y),
+                _ => 0,
+            }
+        };
+        Neighbours {
+            w: at(-1, 0),
+            n: at(0, -1),
+            nw: at(-1, -1),
+            ne: at(1, -1),
+            ww: at(-2, 0),
+            nn: at(0, -2),
+            nne: at(1, -2),
+        }
+    }
+}
+
+/// A causal pixel predictor.
+pub trait Predictor {
+    fn name(&self) -> String;
+
+    fn predict(&self, neighbours: &Neighbours) -> i32;
+
+    fn residuals(&self, img: &GrayImage) -> Array2<i32> {
+        prediction_err_matrix(self, img)
+    }
+
+    fn reconstruct(&self, residuals: &Array2<i32>) -> Result<GrayImage, ReconstructionError> {
+        reconstruct_image(self, residuals)
+    }
+}
+
+/// Errors of `reconstruct_image` and `reconstruct_plane`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReconstructionError {
+    // pred + P falls outside 0..=255 (or the range of the plane): the matrix is not the
+    // prediction error of any image
+    OutOfRange { x: usize, y: usize, value: i32 },
+    // Images are limited to u32 dimensions
+    TooLarge { width: usize, height: usize },
+    // Pixels of this bit depth don't fit in the pixel type of the image
+    BitDepth { bit_depth: u8, pixel_bits: u32 },
+}
+
+impl fmt::Display for ReconstructionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReconstructionError::OutOfRange { x, y, value } => write!(
+                f,
+                "reconstructed pixel ({}, {}) is out of range: {}",
+                x, y, value
+            ),
+            ReconstructionError::TooLarge { width, height } => {
+                write!(f, "a {}x{} image is too large", width, height)
+            }
+            ReconstructionError::BitDepth { bit_depth, pixel_bits } => {
+                write!(f, "{}-bit pixels don't fit in {} bits", bit_depth, pixel_bits)
+            }
+        }
+    }
+}
+
+impl Error for ReconstructionError {}
+
+/// Image rebuilt with clamping, and the pixels where clamping was needed.
+///
+/// The reconstruction is the exact inverse of `prediction_err_matrix` only if
+/// nothing was clamped.
+pub struct Reconstruction<T: Sample = u8> {
+    pub image: GrayBuffer<T>,
+    pub clamped: Vec<(usize, usize)>,
+}
+
+impl<T: Sample> Reconstruction<T> {
+    pub fn is_exact(&self) -> bool {
+        self.clamped.is_empty()
+    }
+}
+
+/// Lossless JPEG predictors 1 to 7 (selection value of the SOF3 scan header).
+///
+/// 4 (W + N - NW) is the planar predictor I - W - N + NW used by the lab.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LosslessJpeg(u8);
+
+impl LosslessJpeg {
+    // None unless 1 <= selection <= 7.
+    pub fn new(selection: u8) -> Option<Self> {
+        (1..=7).contains(&selection).then_some(LosslessJpeg(selection))
+    }
+
+    pub fn selection(&self) -> u8 {
+        self.0
+    }
+}
+
+impl Predictor for LosslessJpeg {
+    fn name(&self) -> String {
+        format!("jpeg{}", self.0)
+    }
+
+    fn predict(&self, p: &Neighbours) -> i32 {
+        let (a, b, c) = (p.w, p.n, p.nw);
+        match self.0 {
+            1 => a,
+            2 => b,
+            3 => c,
+            4 => a + b - c,
+            5 => a + ((b - c) >> 1),
+            6 => b + ((a - c) >> 1),
+            7 => (a + b) >> 1,
+            s => unreachable!("lossless JPEG predictors go from 1 to 7, got {}", s),
+        }
+    }
+}
+
+/// Median edge detector of LOCO-I / JPEG-LS.
+///
+/// Picks min(W, N) or max(W, N) when NW suggests an edge, W + N - NW otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Med;
+
+impl Predictor for Med {
+    fn name(&self) -> String {
+        "med".to_string()
+    }
+
+    fn predict(&self, p: &Neighbours) -> i32 {
+        let (a, b, c) = (p.w, p.n, p.nw);
+        if c >= a.max(b) {
+            a.min(b)
+        } else if c <= a.min(b) {
+            a.max(b)
+        } else {
+            a + b - c
+        }
+    }
+}
+
+/// Gradient adjusted predictor of CALIC.
+///
+/// Estimates the horizontal and vertical gradients and follows the edge when one
+/// dominates; otherwise blends (W + N) / 2 + (NE - NW) / 4 towards the smoother side.
+/// The thresholds on the difference of the gradients (80, 32 and 8) are those of
+/// CALIC for 8-bit images, scaled by 2^(bit_depth - 8) for deeper ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Gap {
+    shift: u32,
+}
+
+impl Gap {
+    pub fn new(bit_depth: u8) -> Self {
+        Gap { shift: bit_depth.saturating_sub(8) as u32 }
+    }
+
+    fn threshold(&self, threshold: i32) -> i32 {
+        threshold << self.shift
+    }
+}
+
+impl Predictor for Gap {
+    fn name(&self) -> String {
+        "gap".to_string()
+    }
+
+    fn predict(&self, p: &Neighbours) -> i32 {
+        let dh = (p.w - p.ww).abs() + (p.n - p.nw).abs() + (p.n - p.ne).abs();
+        let dv = (p.w - p.nw).abs() + (p.n - p.nn).abs() + (p.ne - p.nne).abs();
+        let diff = dv - dh;
+        let (sharp, strong, weak) = (self.threshold(80), self.threshold(32), self.threshold(8));
+        if diff > sharp {
+            return p.w;
+        }
+        if diff < -sharp {
+            return p.n;
+        }
+        let pred = ((p.w + p.n) >> 1) + ((p.ne - p.nw) >> 2);
+        if diff > strong {
+            (pred + p.w) >> 1
+        } else if diff > weak {
+            (3 * pred + p.w) >> 2
+        } else if diff < -strong {
+            (pred + p.n) >> 1
+        } else if diff < -weak {
+            (3 * pred + p.n) >> 2
+        } else {
+            pred
+        }
+    }
+}
+
+/// Paeth predictor of PNG: whichever of W, N and NW is closest to W + N - NW.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Paeth;
+
+impl Predictor for Paeth {
+    fn name(&self) -> String {
+        "paeth".to_string()
+    }
+
+    fn predict(&self, p: &Neighbours) -> i32 {
+        let (a, b, c) = (p.w, p.n, p.nw);
+        let estimate = a + b - c;
+        let (da, db, dc) = ((estimate - a).abs(), (estimate - b).abs(), (estimate - c).abs());
+        if da <= db && da <= dc {
+            a
+        } else if db <= dc {
+            b
+        } else {
+            c
+        }
+    }
+}
+
+/// Names accepted by `predictor_from_name`.
+pub const PREDICTOR_NAMES: [&str; 10] = [
+    "jpeg1", "jpeg2", "jpeg3", "jpeg4", "jpeg5", "jpeg6", "jpeg7", "med", "gap", "paeth",
+];
+
+/// The predictor called `name`, for images of `bit_depth` bits (only GAP depends on it).
+pub fn predictor_from_name(name: &str, bit_depth: u8) -> Option<Box<dyn Predictor>> {
+    match name {
+        "med" => Some(Box::new(Med)),
+        "gap" => Some(Box::new(Gap::new(bit_depth))),
+        "paeth" => Some(Box::new(Paeth)),
+        _ => {
+            let selection = name.strip_prefix("jpeg")?.parse().ok()?;
+            LosslessJpeg::new(selection).map(|predictor| Box::new(predictor) as Box<dyn Predictor>)
+        }
+    }
+}
+
+pub fn all_predictors(bit_depth: u8) -> Vec<Box<dyn Predictor>> {
+    PREDICTOR_NAMES
+        .iter()
+        .map(|name| predictor_from_name(name, bit_depth).unwrap())
+        .collect()
+}
+
+/// Prediction error matrix P = I - pred(I), for images of any bit depth.
+pub fn prediction_err_matrix<P, T>(predictor: &P, original: &GrayBuffer<T>) -> Array2<i32>
+where
+    P: Predictor + ?Sized,
+    T: Sample,
+{
+    plane_residuals(predictor, &gray_plane(original))
+}
+
+/// Prediction error of a plane of arbitrary integers (e.g. a color-transformed channel),
+/// indexed [[x, y]] like the prediction error matrix.
+pub fn plane_residuals<P>(predictor: &P, plane: &Array2<i32>) -> Array2<i32>
+where
+    P: Predictor + ?Sized,
+{
+    let width = plane.dim().0;
+    let pixel = |x: usize, y: usize| plane[[x, y]];
+    Array2::from_shape_fn(plane.dim(), |(x, y)| {
+        let neighbours = Neighbours::gather(x, y, width, pixel);
+        pixel(x, y) - predictor.predict(&neighbours)
+    })
+}
+
+/// Inverse of `plane_residuals`, failing on the first value outside `range`.
+pub fn reconstruct_plane<P>(
+    predictor: &P,
+    matrix: &Array2<i32>,
+    range: RangeInclusive<i32>,
+) -> Result<Array2<i32>, ReconstructionError>
+where
+    P: Predictor + ?Sized,
+{
+    reconstruct_values(predictor, matrix, |x, y, value| {
+        if range.contains(&value) {
+            Ok(value)
+        } else {
+            Err(ReconstructionError::OutOfRange { x, y, value })
+        }
+    })
+}
+
+/// Rebuild the image from its prediction error matrix, in raster order.
+///
+/// Fails on the first pixel outside 0..=255 instead of clamping it.
+pub fn reconstruct_image<P>(predictor: &P, matrix: &Array2<i32>) -> Result<GrayImage, ReconstructionError>
+where
+    P: Predictor + ?Sized,
+{
+    reconstruct_gray(predictor, matrix, 8)
+}
+
+/// Rebuild the image, clamping out of range pixels to 0..=255 and recording where.
+pub fn reconstruct_image_clamped<P>(predictor: &P, matrix: &Array2<i32>) -> Result<Reconstruction, ReconstructionError>
+where
+    P: Predictor + ?Sized,
+{
+    reconstruct_gray_clamped(predictor, matrix, 8)
+}
+
+/// `reconstruct_image` for images of `bit_depth` bits, stored in `T` pixels.
+pub fn reconstruct_gray<P, T>(
+    predictor: &P,
+    matrix: &Array2<i32>,
+    bit_depth: u8,
+) -> Result<GrayBuffer<T>, ReconstructionError>
+where
+    P: Predictor + ?Sized,
+    T: Sample,
+{
+    reconstruct_with(predictor, matrix, bit_depth, |x, y, value| {
+        Err(ReconstructionError::OutOfRange { x, y, value })
+    })
+}
+
+/// `reconstruct_image_clamped` for images of `bit_depth` bits, stored in `T` pixels.
+pub fn reconstruct_gray_clamped<P, T>(
+    predictor: &P,
+    matrix: &Array2<i32>,
+    bit_depth: u8,
+) -> Result<Reconstruction<T>, ReconstructionError>
+where
+    P: Predictor + ?Sized,
+    T: Sample,
+{
+    let mut clamped = Vec::new();
+    let image = reconstruct_with(predictor, matrix, bit_depth, |x, y, value| {
+        clamped.push((x, y));
+        Ok(value.clamp(0, max_value(bit_depth)))
+    })?;
+    Ok(Reconstruction { image, clamped })
+}
+
+// `out_of_range` decides what to do with a pixel outside 0..=2^bit_depth - 1
+fn reconstruct_with<P, T, F>(
+    predictor: &P,
+    matrix: &Array2<i32>,
+    bit_depth: u8,
+    mut out_of_range: F,
+) -> Result<GrayBuffer<T>, ReconstructionError>
+where
+    P: Predictor + ?Sized,
+    T: Sample,
+    F: FnMut(usize, usize, i32) -> Result<i32, ReconstructionError>,
+{
+    let (width, height) = matrix.dim();
+    if u32::try_from(width).is_err() || u32::try_from(height).is_err() {
+        return Err(ReconstructionError::TooLarge { width, height });
+    }
+    let max = max_value(bit_depth);
+    if max > T::DEFAULT_MAX_VALUE.into() {
+        let pixel_bits = std::mem::size_of::<T>() as u32 * 8;
+        return Err(ReconstructionError::BitDepth { bit_depth, pixel_bits });
+    }
+    let values = reconstruct_values(predictor, matrix, |x, y, value| {
+        if (0..=max).contains(&value) {
+            Ok(value)
+        } else {
+            out_of_range(x, y, value)
+        }
+    })?;
+    Ok(GrayBuffer::from_fn(width as u32, height as u32, |x, y| {
+        Luma([num_traits::cast(values[[x as usize, y as usize]]).expect("pixels fit in T")])
+    }))
+}
+
+// Undo the prediction in raster order; `check` sees every value before it is stored
+// (and used to predict the next ones)
+fn reconstruct_values<P, F>(predictor: &P, matrix: &Array2<i32>, mut check: F) -> Result<Array2<i32>, ReconstructionError>
+where
+    P: Predictor + ?Sized,
+    F: FnMut(usize, usize, i32) -> Result<i32, ReconstructionError>,
+{
+    let (width, height) = matrix.dim();
+    let mut values = Array2::zeros(matrix.dim());
+    for y in 0..height {
+        for x in 0..width {
+            let pixel = |x: usize, y: usize| values[[x, y]];
+            let neighbours = Neighbours::gather(x, y, width, pixel);
+            let value = predictor.predict(&neighbours).saturating_add(matrix[[x, y]]);
+            values[[x, y]] = check(x, y, value)?;
+        }
+    }
+    Ok(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::verify::verify_equality_imgs;
+
+    // Small deterministic pseudo-random generator (no external crate needed)
+    fn lcg(seed: &mut u64) -> u8 {
+        *seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+        (*seed >> 56) as u8
+    }
+
+    fn synthetic_images() -> Vec<GrayImage> {
+        let mut seed = 1;
+        vec![
+            GrayImage::new(1, 1),
+            GrayImage::from_pixel(7, 1, Luma([255])),
+            GrayImage::from_fn(1, 9, |_, y| Luma([(y * 30) as u8])),
+            GrayImage::from_fn(16, 12, |x, y| Luma([(x * 16 + y * 4) as u8])),
+            // Checkerboard of the extreme values, the worst case for every predictor
+            GrayImage::from_fn(13, 11, |x, y| Luma([if (x + y) % 2 == 0 { 0 } else { 255 }])),
+            GrayImage::from_fn(10, 10, |x, y| Luma([if x > y { 255 } else { 0 }])),
+            GrayImage::from_fn(31, 17, |_, _| Luma([lcg(&mut seed)])),
+        ]
+    }
+
+    #[test]
+    fn every_predictor_is_exactly_invertible() {
+        for predictor in all_predictors(8) {
+            for img in synthetic_images() {
+                let residuals = predictor.residuals(&img);
+                assert_eq!(residuals.dim(), (img.width() as usize, img.height() as usize));
+                assert!(residuals.iter().all(|v| (-510..=510).contains(v)), "{}", predictor.name());
+
+                let reconstructed = predictor.reconstruct(&residuals).unwrap();
+                assert_eq!(verify_equality_imgs(&img, &reconstructed), Ok(()), "{}", predictor.name());
+
+                let clamped = reconstruct_image_clamped(predictor.as_ref(), &residuals).unwrap();
+                assert!(clamped.is_exact(), "{}", predictor.name());
+            }
+        }
+    }
+
+    #[test]
+    fn jpeg4_is_the_planar_predictor() {
+        let img = GrayImage::from_fn(6, 5, |x, y| Luma([(x * x * 7 + y * 13) as u8]));
+        let p = |x: u32, y: u32| img.get_pixel(x, y)[0] as i32;
+        let residuals = LosslessJpeg::new(4).unwrap().residuals(&img);
+        assert_eq!(residuals[[0, 0]], p(0, 0));
+        assert_eq!(residuals[[3, 0]], p(3, 0) - p(2, 0));
+        assert_eq!(residuals[[0, 2]], p(0, 2) - p(0, 1));
+        assert_eq!(residuals[[3, 2]], p(3, 2) - p(2, 2) - p(3, 1) + p(2, 1));
+    }
+
+    #[test]
+    fn clamping_is_reported() {
+        let mut residuals = Array2::zeros((3, 2));
+        residuals[[0, 0]] = 200;
+        residuals[[1, 0]] = 100;
+        residuals[[2, 1]] = -300;
+        assert_eq!(
+            LosslessJpeg::new(1).unwrap().reconstruct(&residuals),
+            Err(ReconstructionError::OutOfRange { x: 1, y: 0, value: 300 })
+        );
+
+        let reconstruction = reconstruct_image_clamped(&LosslessJpeg::new(1).unwrap(), &residuals).unwrap();
+        assert!(!reconstruction.is_exact());
+        assert_eq!(reconstruction.clamped, vec![(1, 0), (2, 1)]);
+        assert_eq!(reconstruction.image.get_pixel(1, 0)[0], 255);
+        assert_eq!(reconstruction.image.get_pixel(2, 1)[0], 0);
+    }
+
+    #[test]
+    fn deep_images_are_exactly_invertible() {
+        let mut seed = 7;
+        for bit_depth in [12, 16] {
+            let max = max_value(bit_depth) as u32;
+            let noise = GrayBuffer::from_fn(23, 19, |_, _| Luma([(lcg(&mut seed) as u32 * max / 255) as u16]));
+            let ramp = GrayBuffer::from_fn(23, 19, |x, y| Luma([((x * 157 + y * 61) % (max + 1)) as u16]));
+            for predictor in all_predictors(bit_depth) {
+                for img in [&noise, &ramp] {
+                    let residuals = prediction_err_matrix(predictor.as_ref(), img);
+                    assert!(residuals.iter().all(|v| pred_err_alphabet(bit_depth).contains(v)), "{}", predictor.name());
+                    let reconstructed = reconstruct_gray::<_, u16>(predictor.as_ref(), &residuals, bit_depth).unwrap();
+                    assert_eq!(&reconstructed, img, "{} {}", predictor.name(), bit_depth);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn bit_depths_too_deep_for_the_pixels_are_errors() {
+        let residuals = Array2::from_elem((4, 3), 1000);
+        let predictor = LosslessJpeg::new(1).unwrap();
+        let error = Err(ReconstructionError::BitDepth { bit_depth: 12, pixel_bits: 8 });
+        assert_eq!(reconstruct_gray_clamped::<_, u8>(&predictor, &residuals, 12).map(|r| r.image), error);
+        assert_eq!(reconstruct_gray::<_, u8>(&predictor, &residuals, 12), error);
+        assert!(reconstruct_gray_clamped::<_, u16>(&predictor, &residuals, 12).is_ok());
+    }
+
+    #[test]
+    fn gap_thresholds_scale_with_bit_depth() {
+        // dh = 0 and dv = 50: between the 32 and 80 thresholds at 8 bits
+        let shallow = Neighbours { w: 0, ww: 0, n: 20, nw: 20, ne: 20, nn: 20, nne: 50 };
+        let scale = |value: i32| value << 8;
+        let deep = Neighbours {
+            w: scale(shallow.w),
+            ww: scale(shallow.ww),
+            n: scale(shallow.n),
+            nw: scale(shallow.nw),
+            ne: scale(shallow.ne),
+            nn: scale(shallow.nn),
+            nne: scale(shallow.nne),
+        };
+        assert_eq!(Gap::new(8).predict(&shallow), 5);
+        assert_eq!(Gap::new(16).predict(&deep), scale(5));
+        // Unscaled thresholds see a sharp edge and return W
+        assert_eq!(Gap::new(8).predict(&deep), 0);
+    }
+
+    #[test]
+    fn predictor_names() {
+        for name in PREDICTOR_NAMES {
+            assert_eq!(predictor_from_name(name, 8).unwrap().name(), name);
+        }
+        assert!(predictor_from_name("jpeg0", 8).is_none());
+        assert!(predictor_from_name("jpeg8", 8).is_none());
+        assert!(predictor_from_name("planar", 8).is_none());
+        assert!(LosslessJpeg::new(0).is_none());
+        assert!(LosslessJpeg::new(8).is_none());
+        assert_eq!(LosslessJpeg::new(7).map(|predictor| predictor.selection()), Some(7));
+    }
+}
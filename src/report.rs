@@ -0,0 +1,495 @@
+use std::error::Error;
+use std::fmt::Write;
+
+use image::DynamicImage;
+use ndarray::Array2;
+use serde::Serialize;
+
+use crate::ans::{rans_encode, scale_bits_for};
+use crate::arithmetic::{arithmetic_encode, AdaptiveModel, StaticModel, MAX_ADAPTIVE_SYMBOLS, MAX_STATIC_SYMBOLS};
+use crate::color::{check_color, image_planes, plane_count, ColorTransform};
+use crate::container::{Codec, Container, ContainerError, Payload};
+use crate::depth::{check_bit_depth, gray_plane, max_value, GrayBuffer, Sample};
+use crate::entropy::{data_entropy, histogram_entropy};
+use crate::golomb::adaptive::{adaptive_golomb_encode, GolombContexts};
+use crate::golomb::encode::{custom_encode_with_limit, default_limit};
+use crate::golomb::mapping::SignedMapping;
+use crate::golomb::search::golomb_cost_curve;
+use crate::histogram::Histogram;
+use crate::huffman::adaptive::adaptive_huffman_encode;
+use crate::huffman::{huffman_encode, weighted_path_length};
+use crate::prediction::{pred_err_alphabet, prediction_err_matrix, plane_residuals, Predictor};
+use crate::wavelet::{max_levels, subbands, wavelet_forward, DEFAULT_LEVELS};
+
+/// Numbers of the lab report for one grayscale image.
+///
+/// Compression ratios compare against `bit_depth` bits per pixel for the image (I)
+/// and `bit_depth + 1` bits per value for the prediction error (P), as in the text
+/// report. Arithmetic and rANS sizes are missing when the alphabet is too large for
+/// the coder (deep images).
+///
+/// `entropy_wavelet` is the mean entropy of the subbands of the 5/3 wavelet transform of
+/// the image (weighted by their size), to compare with `entropy_p`; the `wavelet_*`
+/// sizes add up the subbands coded one by one. The subbands themselves are in
+/// `subbands`, which `Report` lists in a table of its own.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageReport {
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    pub bit_depth: u8,
+    pub predictor: String,
+    pub entropy_i: f64,
+    pub entropy_p: f64,
+    pub entropy_wavelet: f64,
+    pub entropy_abs_p: f64,
+    pub entropy_sign_p: f64,
+    pub golomb_m: u32,
+    pub golomb_bits: usize,
+    pub golomb_ratio: f64,
+    pub golomb_optimal_m: u32,
+    pub golomb_optimal_bits: usize,
+    pub adaptive_golomb_bits: usize,
+    pub huffman_i_bits: usize,
+    pub huffman_i_ratio: f64,
+    pub weighted_path_length_i: f64,
+    pub huffman_p_bits: usize,
+    pub huffman_p_ratio: f64,
+    pub weighted_path_length_p: f64,
+    pub adaptive_huffman_p_bits: usize,
+    pub arithmetic_static_p_bits: Option<usize>,
+    pub arithmetic_adaptive_p_bits: Option<usize>,
+    pub rans_p_bits: Option<usize>,
+    pub wavelet_levels: u32,
+    pub wavelet_golomb_bits: usize,
+    pub wavelet_huffman_bits: usize,
+    #[serde(skip)]
+    pub subbands: Vec<SubbandReport>,
+}
+
+impl ImageReport {
+    /// Compute the report of `img` (named `name`, of `bit_depth` bits), predicting with
+    /// `predictor`.
+    pub fn new<T: Sample>(name: &str, img: &GrayBuffer<T>, bit_depth: u8, predictor: &dyn Predictor) -> Self {
+        let pixels = img.pixels().map(|p| p[0]);
+        let histogram = Histogram::from_iter(pixels.clone());
+        let prediction_err = prediction_err_matrix(predictor, img);
+        let pred_err = prediction_err.iter().copied();
+        let values = prediction_err.len() as f64;
+        let (bits_i, bits_p) = (bit_depth as f64, bit_depth as f64 + 1.0);
+
+        let limit = default_limit(bit_depth);
+        let golomb = custom_encode_with_limit(&prediction_err, limit);
+        let curve = golomb_cost_curve(&prediction_err, limit, SignedMapping::SignMagnitude, None);
+        let adaptive_golomb =
+            adaptive_golomb_encode(&prediction_err, GolombContexts::Gradient, bit_depth, limit, SignedMapping::Zigzag);
+        let huffman_i = huffman_encode(pixels.clone());
+        let huffman_p = huffman_encode(pred_err.clone());
+        let pred_err_histogram = Histogram::from_iter(pred_err.clone());
+        let symbols = pred_err_histogram.counts().len();
+        let alphabet = pred_err_alphabet(bit_depth);
+        let plane = gray_plane(img);
+        let levels = DEFAULT_LEVELS.min(max_levels(plane.dim()));
+        let subbands = SubbandReport::subbands(name, &plane, levels, bit_depth);
+
+        ImageReport {
+            image: name.to_string(),
+            width: img.width(),
+            height: img.height(),
+            bit_depth,
+            predictor: predictor.name(),
+            entropy_i: histogram_entropy(&histogram),
+            entropy_p: data_entropy(&prediction_err),
+            entropy_wavelet: subbands.iter().map(|band| band.entropy * band.coefficients() as f64).sum::<f64>() / values,
+            entropy_abs_p: data_entropy(prediction_err.iter().map(|v| v.abs())),
+            entropy_sign_p: data_entropy(prediction_err.iter().map(|&v| v < 0)),
+            golomb_m: golomb.m,
+            golomb_bits: golomb.bits(),
+            golomb_ratio: values * bits_p / golomb.bits() as f64,
+            golomb_optimal_m: curve.best_m,
+            golomb_optimal_bits: curve.best_bits,
+            adaptive_golomb_bits: adaptive_golomb.bits(),
+            huffman_i_bits: huffman_i.bits(),
+            huffman_i_ratio: values * bits_i / huffman_i.bits() as f64,
+            weighted_path_length_i: weighted_path_length(pixels.clone()),
+            huffman_p_bits: huffman_p.bits(),
+            huffman_p_ratio: values * bits_p / huffman_p.bits() as f64,
+            weighted_path_length_p: weighted_path_length(pred_err.clone()),
+            adaptive_huffman_p_bits: adaptive_huffman_encode(pred_err.clone()).len(),
+            arithmetic_static_p_bits: (symbols <= MAX_STATIC_SYMBOLS)
+                .then(|| arithmetic_encode(StaticModel::from_histogram(&pred_err_histogram), pred_err.clone()))
+                .and_then(Result::ok)
+                .map(|encoded| encoded.bits()),
+            arithmetic_adaptive_p_bits: (alphabet.clone().count() <= MAX_ADAPTIVE_SYMBOLS)
+                .then(|| arithmetic_encode(AdaptiveModel::new(alphabet), pred_err.clone()))
+                .and_then(Result::ok)
+                .map(|encoded| encoded.bits()),
+            rans_p_bits: scale_bits_for(symbols).map(|scale_bits| rans_encode(pred_err, scale_bits).bits()),
+            wavelet_levels: levels,
+            wavelet_golomb_bits: subbands.iter().map(|band| band.golomb_bits).sum(),
+            wavelet_huffman_bits: subbands.iter().map(|band| band.huffman_bits).sum(),
+            subbands,
+        }
+    }
+}
+
+/// Numbers of a subband of the 5/3 wavelet transform of a grayscale image, coded on
+/// its own with Golomb (optimal M) and Huffman, as the planes of a `Container`.
+#[derive(Debug, Clone, Serialize)]
+pub struct SubbandReport {
+    pub image: String,
+    pub subband: String,
+    pub level: u32,
+    pub width: usize,
+    pub height: usize,
+    pub entropy: f64,
+    pub golomb_bits: usize,
+    pub huffman_bits: usize,
+}
+
+impl SubbandReport {
+    /// Reports of every subband of `plane` (of `bit_depth` bits) after `levels` levels.
+    pub fn subbands(name: &str, plane: &Array2<i32>, levels: u32, bit_depth: u8) -> Vec<Self> {
+        let coefficients = wavelet_forward(plane, levels);
+        subbands(plane.dim(), levels)
+            .into_iter()
+            .map(|band| {
+                let matrix = band.extract(&coefficients);
+                let (width, height) = band.shape();
+                SubbandReport {
+                    image: name.to_string(),
+                    subband: band.name,
+                    level: band.level,
+                    width,
+                    height,
+                    entropy: data_entropy(&matrix),
+                    golomb_bits: Payload::encode(&matrix, bit_depth, Codec::Golomb, None).bits(),
+                    huffman_bits: Payload::encode(&matrix, bit_depth, Codec::Huffman, None).bits(),
+                }
+            })
+            .collect()
+    }
+
+    pub fn coefficients(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+/// Numbers of a color image coded with one color transform.
+///
+/// `entropy_plane_*` are the entropies of the transformed planes (named in
+/// `planes`) and `entropy_p_*` those of their prediction error. Sizes are of the
+/// whole compressed file; `exact` tells whether both files decode to the image.
+#[derive(Debug, Clone, Serialize)]
+pub struct ColorReport {
+    pub image: String,
+    pub width: u32,
+    pub height: u32,
+    pub channels: usize,
+    pub predictor: String,
+    pub transform: String,
+    pub entropy_r: f64,
+    pub entropy_g: f64,
+    pub entropy_b: f64,
+    pub entropy_a: Option<f64>,
+    pub planes: String,
+    pub entropy_plane_0: f64,
+    pub entropy_plane_1: f64,
+    pub entropy_plane_2: f64,
+    pub entropy_p_0: f64,
+    pub entropy_p_1: f64,
+    pub entropy_p_2: f64,
+    pub entropy_p_a: Option<f64>,
+    pub golomb_file_bits: usize,
+    pub golomb_bpp: f64,
+    pub huffman_file_bits: usize,
+    pub huffman_bpp: f64,
+    pub exact: bool,
+}
+
+impl ColorReport {
+    /// Compute the report of the RGB(A) image `img`, of `bit_depth` bits, with `transform`.
+    pub fn new(
+        name: &str,
+        img: &DynamicImage,
+        bit_depth: u8,
+        predictor: &dyn Predictor,
+        transform: ColorTransform,
+    ) -> Result<Self, ContainerError> {
+        let channels = image_planes(img, ColorTransform::None)?;
+        let planes = image_planes(img, transform)?;
+        let entropy_p: Vec<f64> =
+            planes.iter().map(|plane| data_entropy(plane_residuals(predictor, plane))).collect();
+        let pixels = img.width() as f64 * img.height() as f64;
+
+        let mut exact = true;
+        let mut file_bits = |codec: Codec| -> Result<usize, ContainerError> {
+            let bytes = Container::encode(img, max_value(bit_depth) as u16, &predictor.name(), transform, codec, None)?.to_bytes()?;
+            let decoded = Container::from_bytes(&bytes).and_then(|container| container.decode_image());
+            exact &= decoded.is_ok_and(|decoded| decoded == *img);
+            Ok(bytes.len() * 8)
+        };
+        let golomb_file_bits = file_bits(Codec::Golomb)?;
+        let huffman_file_bits = file_bits(Codec::Huffman)?;
+
+        Ok(ColorReport {
+            image: name.to_string(),
+            width: img.width(),
+            height: img.height(),
+            channels: channels.len(),
+            predictor: predictor.name(),
+            transform: transform.name().to_string(),
+            entropy_r: data_entropy(&channels[0]),
+            entropy_g: data_entropy(&channels[1]),
+            entropy_b: data_entropy(&channels[2]),
+            entropy_a: channels.get(3).map(data_entropy),
+            planes: transform.plane_names().join(" "),
+            entropy_plane_0: data_entropy(&planes[0]),
+            entropy_plane_1: data_entropy(&planes[1]),
+            entropy_plane_2: data_entropy(&planes[2]),
+            entropy_p_0: entropy_p[0],
+            entropy_p_1: entropy_p[1],
+            entropy_p_2: entropy_p[2],
+            entropy_p_a: entropy_p.get(3).copied(),
+            golomb_file_bits,
+            golomb_bpp: golomb_file_bits as f64 / pixels,
+            huffman_file_bits,
+            huffman_bpp: huffman_file_bits as f64 / pixels,
+            exact,
+        })
+    }
+}
+
+// Header row and data rows, as strings
+type Table = (Vec<String>, Vec<Vec<String>>);
+
+/// An image that couldn't be analyzed.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReportFailure {
+    pub image: String,
+    pub error: String,
+}
+
+/// Reports of several images, written as up to three tables: the grayscale images,
+/// the subbands of their wavelet transform and the color images.
+///
+/// In the CSV and Markdown tables every failure is a row of the first table with
+/// only the image and the `error` column filled.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub images: Vec<ImageReport>,
+    pub subbands: Vec<SubbandReport>,
+    pub colors: Vec<ColorReport>,
+    pub failures: Vec<ReportFailure>,
+}
+
+impl Report {
+    /// Analyze `img`, of `bit_depth` bits: grayscale images get an `ImageReport`,
+    /// color images a `ColorReport` per color transform. Images of a type the codecs
+    /// don't handle (see `color::check_color`) or that don't fit in `bit_depth` are
+    /// recorded as failures.
+    pub fn add_image(&mut self, name: &str, img: &DynamicImage, bit_depth: u8, predictor: &dyn Predictor) {
+        let failure = |error: &dyn Error| ReportFailure { image: name.to_string(), error: error.to_string() };
+        if let Err(err) = check_color(img) {
+            self.failures.push(failure(&err));
+            return;
+        }
+        if let Err(err) = check_bit_depth(img, bit_depth) {
+            self.failures.push(failure(&err));
+            return;
+        }
+        let mut report = if plane_count(img) == 1 && bit_depth > 8 {
+            ImageReport::new(name, &img.to_luma16(), bit_depth, predictor)
+        } else if plane_count(img) == 1 {
+            ImageReport::new(name, &img.to_luma8(), bit_depth, predictor)
+        } else {
+            let reports: Result<Vec<ColorReport>, ContainerError> = ColorTransform::ALL
+                .into_iter()
+                .map(|transform| ColorReport::new(name, img, bit_depth, predictor, transform))
+                .collect();
+            match reports {
+                Ok(mut reports) => self.colors.append(&mut reports),
+                Err(err) => self.failures.push(failure(&err)),
+            }
+            return;
+        };
+        self.subbands.append(&mut report.subbands);
+        self.images.push(report);
+    }
+
+    // Move the entries of `other` to the end of this report.
+    pub fn append(&mut self, other: &mut Report) {
+        self.images.append(&mut other.images);
+        self.subbands.append(&mut other.subbands);
+        self.colors.append(&mut other.colors);
+        self.failures.append(&mut other.failures);
+    }
+
+    pub fn to_json(&self) -> Result<String, Box<dyn Error>> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    // One row per image, with a header row; tables are separated by a blank line.
+    pub fn to_csv(&self) -> Result<String, Box<dyn Error>> {
+        let mut tables = Vec::new();
+        for (header, rows) in self.tables()? {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(&header)?;
+            for row in rows {
+                writer.write_record(&row)?;
+            }
+            tables.push(String::from_utf8(writer.into_inner()?)?);
+        }
+        Ok(tables.join("\n"))
+    }
+
+    // Same columns as the CSV, as Markdown tables.
+    pub fn to_markdown(&self) -> Result<String, Box<dyn Error>> {
+        let mut markdown = String::new();
+        for (index, (header, rows)) in self.tables()?.into_iter().enumerate() {
+            if index > 0 {
+                writeln!(markdown)?;
+            }
+            writeln!(markdown, "| {} |", header.join(" | "))?;
+            writeln!(markdown, "|{}", " --- |".repeat(header.len()))?;
+            for row in rows {
+                let cells: Vec<String> = row.iter().map(|cell| cell.replace('|', "\\|")).collect();
+                writeln!(markdown, "| {} |", cells.join(" | "))?;
+            }
+        }
+        Ok(markdown)
+    }
+
+    // The grayscale table (unless there are only color images), the subband and color
+    // ones (if any), with an `error` column; the failures go in the first table.
+    fn tables(&self) -> Result<Vec<Table>, Box<dyn Error>> {
+        let mut tables = Vec::new();
+        if !self.images.is_empty() || self.colors.is_empty() {
+            tables.push(table(&self.images)?);
+        }
+        if !self.subbands.is_empty() {
+            tables.push(table(&self.subbands)?);
+        }
+        if !self.colors.is_empty() {
+            tables.push(table(&self.colors)?);
+        }
+
+        let (header, rows) = &mut tables[0];
+        for failure in &self.failures {
+            let mut row = vec![String::new(); header.len()];
+            row[0] = failure.image.clone();
+            row[header.len() - 1] = failure.error.clone();
+            rows.push(row);
+        }
+        Ok(tables)
+    }
+}
+
+// Header and rows of the table of `entries`: their fields plus an empty `error` column.
+fn table<T: Serialize>(entries: &[T]) -> Result<Table, Box<dyn Error>> {
+    // Let serde name and format the fields, then read them back as strings
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    for entry in entries {
+        writer.serialize(entry)?;
+    }
+    let csv = writer.into_inner()?;
+    let mut reader = csv::Reader::from_reader(csv.as_slice());
+    let mut header: Vec<String> = reader.headers()?.iter().map(String::from).collect();
+    let mut rows = Vec::with_capacity(entries.len());
+    for record in reader.records() {
+        let mut row: Vec<String> = record?.iter().map(String::from).collect();
+        row.push(String::new());
+        rows.push(row);
+    }
+
+    if header.is_empty() {
+        header.push("image".to_string());
+    }
+    header.push("error".to_string());
+    Ok((header, rows))
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GrayAlphaImage, GrayImage, Luma, LumaA, Rgb, RgbImage};
+
+    use super::*;
+    use crate::prediction::predictor_from_name;
+
+    fn report() -> Report {
+        let predictor = predictor_from_name("med", 8).unwrap();
+        let gray = DynamicImage::ImageLuma8(GrayImage::from_fn(12, 9, |x, y| Luma([(x * 17 + y * 5) as u8])));
+        let rgb = DynamicImage::ImageRgb8(RgbImage::from_fn(6, 5, |x, y| Rgb([(x * 40) as u8, (y * 50) as u8, 7])));
+        let gray_alpha = DynamicImage::ImageLumaA8(GrayAlphaImage::from_pixel(2, 2, LumaA([1, 2])));
+        let mut report = Report::default();
+        report.add_image("gray", &gray, 8, predictor.as_ref());
+        report.add_image("rgb", &rgb, 8, predictor.as_ref());
+        report.add_image("alpha", &gray_alpha, 8, predictor.as_ref());
+        report.add_image("deep", &gray, 12, predictor.as_ref());
+        report
+    }
+
+    #[test]
+    fn gray_and_color_images() {
+        let report = report();
+        assert_eq!(report.images.len(), 1);
+        let image = &report.images[0];
+        assert_eq!((image.image.as_str(), image.width, image.height, image.bit_depth), ("gray", 12, 9, 8));
+        assert!(image.entropy_p <= image.entropy_i);
+        assert!(image.golomb_optimal_bits <= image.golomb_bits);
+        assert_eq!(report.subbands.len(), image.wavelet_levels as usize * 3 + 1);
+        assert_eq!(report.subbands.iter().map(SubbandReport::coefficients).sum::<usize>(), 12 * 9);
+
+        assert_eq!(report.colors.len(), ColorTransform::ALL.len());
+        for (color, transform) in report.colors.iter().zip(ColorTransform::ALL) {
+            assert_eq!((color.image.as_str(), color.channels), ("rgb", 3));
+            assert_eq!(color.transform, transform.name());
+            assert!(color.exact);
+        }
+
+        let failures: Vec<&str> = report.failures.iter().map(|failure| failure.image.as_str()).collect();
+        assert_eq!(failures, ["alpha", "deep"]);
+    }
+
+    #[test]
+    fn tables() {
+        let report = report();
+        let csv = report.to_csv().unwrap();
+        let tables: Vec<&str> = csv.split("\n\n").collect();
+        assert_eq!(tables.len(), 3);
+        let mut reader = csv::Reader::from_reader(tables[0].as_bytes());
+        let header: Vec<String> = reader.headers().unwrap().iter().map(String::from).collect();
+        let rows: Vec<csv::StringRecord> = reader.records().map(Result::unwrap).collect();
+        let columns = header.len();
+        assert_eq!(header[..5], ["image", "width", "height", "bit_depth", "predictor"]);
+        assert_eq!(header[columns - 1], "error");
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].iter().take(5).collect::<Vec<_>>(), ["gray", "12", "9", "8", "med"]);
+        assert_eq!(&rows[0][columns - 1], "");
+        // Failures only fill the image and error columns
+        for (row, failure) in rows[1..].iter().zip(&report.failures) {
+            assert_eq!(row.len(), columns);
+            assert_eq!(&row[0], failure.image);
+            assert!(row.iter().skip(1).take(columns - 2).all(str::is_empty));
+            assert_eq!(&row[columns - 1], failure.error);
+        }
+        assert!(tables[1].starts_with("image,subband,level,"));
+        assert_eq!(tables[2].lines().count(), 1 + ColorTransform::ALL.len());
+
+        let markdown = report.to_markdown().unwrap();
+        let tables: Vec<&str> = markdown.split("\n\n").collect();
+        assert_eq!(tables.len(), 3);
+        let rows: Vec<&str> = tables[0].lines().collect();
+        assert!(rows[0].starts_with("| image | width | height |") && rows[0].ends_with("| error |"));
+        assert_eq!(rows[1], format!("|{}", " --- |".repeat(columns)));
+        assert_eq!(rows[3], format!("| alpha |{} {} |", "  |".repeat(columns - 2), report.failures[0].error));
+    }
+
+    #[test]
+    fn failures_alone_get_a_table() {
+        let mut report = Report::default();
+        report.failures.push(ReportFailure { image: "missing.png".to_string(), error: "a | b".to_string() });
+        assert_eq!(report.to_csv().unwrap(), "image,error\nmissing.png,a | b\n");
+        assert_eq!(report.to_markdown().unwrap(), "| image | error |\n| --- | --- |\n| missing.png | a \\| b |\n");
+    }
+}
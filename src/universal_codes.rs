@@ -0,0 +1,275 @@
+use ndarray::Array2;
+
+use crate::bitio::{values_to_matrix, BitBuffer, BitReader, BitWriter, DecodeError};
+use crate::golomb::mapping::{read_signed, write_signed, SignedMapping};
+
+/*
+    Universal codes: prefix codes for the non-negative integers whose length grows
+    only logarithmically, so heavy tails don't blow up the way a fixed-parameter
+    Golomb code does. Elias and Fibonacci codes are defined for n >= 1, so a value
+    x >= 0 is coded as n = x + 1.
+
+    - Exp-Golomb of order k: w = x + 2^k has N + k + 1 bits; write N zeroes, then w.
+    - Elias gamma: n has N + 1 bits; write N zeroes, then n (same as Exp-Golomb k = 0).
+    - Elias delta: n has L bits; write L with Elias gamma, then n without its leading 1.
+    - Fibonacci: write the Zeckendorf representation of n (sum of non-consecutive
+      Fibonacci numbers 1, 2, 3, 5, 8, ...) smallest first, then an extra 1. No
+      representation has two consecutive 1s, so "11" marks the end of every code.
+
+    Example (x = 8, n = 9):
+        Exp-Golomb k = 1: w = 10 = 1010b, N = 2 -> 00 1010
+        Elias gamma:      9 = 1001b, N = 3    -> 000 1001
+        Elias delta:      L = 4 = gamma 00100 -> 00100 001
+        Fibonacci:        9 = 8 + 1           -> 10001 1
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UniversalCode {
+    ExpGolomb(u32),
+    EliasGamma,
+    EliasDelta,
+    Fibonacci,
+}
+
+// Fibonacci numbers 1, 2, 3, 5, ... up to the first one above u32::MAX + 1
+const FIBONACCI: [u64; 47] = {
+    let mut fib = [0u64; 47];
+    fib[0] = 1;
+    fib[1] = 2;
+    let mut i = 2;
+    while i < fib.len() {
+        fib[i] = fib[i - 1] + fib[i - 2];
+        i += 1;
+    }
+    fib
+};
+
+// Number of bits of n (n >= 1)
+fn bit_length(n: u64) -> u32 {
+    u64::BITS - n.leading_zeros()
+}
+
+impl UniversalCode {
+    pub fn name(self) -> String {
+        match self {
+            UniversalCode::ExpGolomb(k) => format!("Exp-Golomb (k = {})", k),
+            UniversalCode::EliasGamma => "Elias gamma".to_string(),
+            UniversalCode::EliasDelta => "Elias delta".to_string(),
+            UniversalCode::Fibonacci => "Fibonacci".to_string(),
+        }
+    }
+
+    pub fn write(self, writer: &mut BitWriter, x: u32) {
+        match self {
+            UniversalCode::ExpGolomb(k) => write_exp_golomb(writer, x as u64, k),
+            UniversalCode::EliasGamma => write_elias_gamma(writer, x as u64 + 1),
+            UniversalCode::EliasDelta => write_elias_delta(writer, x as u64 + 1),
+            UniversalCode::Fibonacci => write_fibonacci(writer, x as u64 + 1),
+        }
+    }
+
+    pub fn read(self, reader: &mut BitReader) -> Option<u32> {
+        let x = match self {
+            UniversalCode::ExpGolomb(k) => read_exp_golomb(reader, k)?,
+            UniversalCode::EliasGamma => read_elias_gamma(reader)? - 1,
+            UniversalCode::EliasDelta => read_elias_delta(reader)? - 1,
+            UniversalCode::Fibonacci => read_fibonacci(reader)? - 1,
+        };
+        u32::try_from(x).ok()
+    }
+}
+
+pub fn write_exp_golomb(writer: &mut BitWriter, x: u64, k: u32) {
+    let w = x + (1 << k);
+    let n = bit_length(w) - 1 - k;
+    writer.write_repeated(0, n as usize);
+    writer.write_bits(w, n + k + 1);
+}
+
+pub fn read_exp_golomb(reader: &mut BitReader, k: u32) -> Option<u64> {
+    // The run stops at the leading 1 of w, which is already consumed
+    let n = reader.read_run(0)? as u32;
+    if n + k >= u64::BITS {
+        return None;
+    }
+    let w = (1 << (n + k)) | reader.read_bits(n + k)?;
+    Some(w - (1 << k))
+}
+
+pub fn write_elias_gamma(writer: &mut BitWriter, n: u64) {
+    assert!(n >= 1, "Elias gamma codes start at 1");
+    write_exp_golomb(writer, n - 1, 0);
+}
+
+pub fn read_elias_gamma(reader: &mut BitReader) -> Option<u64> {
+    Some(read_exp_golomb(reader, 0)? + 1)
+}
+
+pub fn write_elias_delta(writer: &mut BitWriter, n: u64) {
+    assert!(n >= 1, "Elias delta codes start at 1");
+    let len = bit_length(n);
+    write_elias_gamma(writer, len as u64);
+    writer.write_bits(n, len - 1);
+}
+
+pub fn read_elias_delta(reader: &mut BitReader) -> Option<u64> {
+    let len = read_elias_gamma(reader)?;
+    if len > u64::BITS as u64 {
+        return None;
+    }
+    let len = len as u32;
+    Some((1 << (len - 1)) | reader.read_bits(len - 1)?)
+}
+
+pub fn write_fibonacci(writer: &mut BitWriter, n: u64) {
+    assert!(
+        (1..=*FIBONACCI.last().unwrap()).contains(&n),
+        "value out of range for the Fibonacci code"
+    );
+    // Greedy Zeckendorf decomposition, from the largest Fibonacci number down
+    let top = FIBONACCI.partition_point(|&f| f <= n);
+    let mut bits = vec![0u8; top];
+    let mut rest = n;
+    for i in (0..top).rev() {
+        if FIBONACCI[i] <= rest {
+            bits[i] = 1;
+            rest -= FIBONACCI[i];
+        }
+    }
+    writer.write_slice(&bits);
+    writer.write_bit(1);
+}
+
+pub fn read_fibonacci(reader: &mut BitReader) -> Option<u64> {
+    let mut n = 0u64;
+    let mut previous = 0;
+    for &fib in FIBONACCI.iter() {
+        let bit = reader.read_bit()?;
+        if bit == 1 && previous == 1 {
+            return Some(n);
+        }
+        if bit == 1 {
+            n += fib;
+        }
+        previous = bit;
+    }
+    // Only the terminating 1 can follow the largest Fibonacci number
+    (reader.read_bit()? == 1 && previous == 1).then_some(n)
+}
+
+pub struct UniversalEncodedImage {
+    pub code: UniversalCode,
+    pub mapping: SignedMapping,
+    pub encoded_bits: BitBuffer,
+    pub shape: (usize, usize),
+}
+
+impl UniversalEncodedImage {
+    pub fn bits(&self) -> usize {
+        self.encoded_bits.len()
+    }
+
+    pub fn decode(&self) -> Result<Array2<i32>, DecodeError> {
+        universal_decode(self)
+    }
+}
+
+pub fn universal_encode(matrix: &Array2<i32>, code: UniversalCode, mapping: SignedMapping) -> UniversalEncodedImage {
+    let mut writer = BitWriter::new();
+    for &v in matrix {
+        write_signed(&mut writer, v, mapping, |writer, x| code.write(writer, x));
+    }
+    let shape = matrix.shape();
+    let shape = (shape[0], shape[1]);
+    UniversalEncodedImage {
+        code,
+        mapping,
+        encoded_bits: writer.finish(),
+        shape,
+    }
+}
+
+fn universal_decode(data: &UniversalEncodedImage) -> Result<Array2<i32>, DecodeError> {
+    let mut decoded_pixels: Vec<i32> = Vec::new();
+    let mut reader = data.encoded_bits.reader();
+    while !reader.is_empty() {
+        let position = reader.position();
+        let v = read_signed(&mut reader, data.mapping, |reader| data.code.read(reader));
+        decoded_pixels.push(v.ok_or(DecodeError::InvalidCode { position })?);
+    }
+    values_to_matrix(data.shape, decoded_pixels)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CODES: [UniversalCode; 6] = [
+        UniversalCode::ExpGolomb(0),
+        UniversalCode::ExpGolomb(1),
+        UniversalCode::ExpGolomb(5),
+        UniversalCode::EliasGamma,
+        UniversalCode::EliasDelta,
+        UniversalCode::Fibonacci,
+    ];
+
+    fn round_trip(values: Vec<i32>) {
+        let matrix = Array2::from_shape_vec((1, values.len()), values).unwrap();
+        for code in CODES {
+            for mapping in SignedMapping::ALL {
+                let encoded = universal_encode(&matrix, code, mapping);
+                assert_eq!(encoded.decode().unwrap(), matrix, "{}, {}", code.name(), mapping.name());
+            }
+        }
+    }
+
+    #[test]
+    fn example_codes() {
+        let expected: [(UniversalCode, &[u8]); 4] = [
+            (UniversalCode::ExpGolomb(1), &[0, 0, 1, 0, 1, 0]),
+            (UniversalCode::EliasGamma, &[0, 0, 0, 1, 0, 0, 1]),
+            (UniversalCode::EliasDelta, &[0, 0, 1, 0, 0, 0, 0, 1]),
+            (UniversalCode::Fibonacci, &[1, 0, 0, 0, 1, 1]),
+        ];
+        for (code, bits) in expected {
+            let mut writer = BitWriter::new();
+            code.write(&mut writer, 8);
+            let buffer = writer.finish();
+            assert_eq!(buffer.to_bits(), bits, "{}", code.name());
+            assert_eq!(code.read(&mut buffer.reader()), Some(8), "{}", code.name());
+        }
+    }
+
+    #[test]
+    fn empty_input() {
+        round_trip(Vec::new());
+    }
+
+    #[test]
+    fn one_symbol() {
+        round_trip(vec![0; 20]);
+        round_trip(vec![-3; 20]);
+    }
+
+    #[test]
+    fn skewed_input() {
+        let mut values: Vec<i32> = (0..300).map(|i| if i % 13 == 0 { i } else { 0 }).collect();
+        values.extend([i32::MAX, i32::MIN, -1, 1 << 30]);
+        round_trip(values);
+    }
+
+    #[test]
+    fn invalid_streams_are_errors() {
+        // Zeroes never end an Elias or Exp-Golomb code, and a Fibonacci code needs "11"
+        let zeroes = BitBuffer::from_bytes(vec![0; 4], 32).unwrap();
+        for code in CODES {
+            let encoded = UniversalEncodedImage {
+                code,
+                mapping: SignedMapping::Zigzag,
+                encoded_bits: zeroes.clone(),
+                shape: (1, 1),
+            };
+            assert_eq!(encoded.decode(), Err(DecodeError::InvalidCode { position: 0 }), "{}", code.name());
+        }
+    }
+}
@@ -0,0 +1,110 @@
+use std::error::Error;
+use std::fmt;
+
+use ndarray::Array2;
+
+use crate::depth::{GrayBuffer, Sample};
+
+/// First difference found between two images or matrices.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MismatchError {
+    Dimensions {
+        expected: (usize, usize),
+        found: (usize, usize),
+    },
+    Value {
+        position: (usize, usize),
+        expected: i64,
+        found: i64,
+    },
+}
+
+impl fmt::Display for MismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MismatchError::Dimensions { expected, found } => write!(
+                f,
+                "dimensions are different: {:?} != {:?}",
+                expected, found
+            ),
+            MismatchError::Value { position, expected, found } => write!(
+                f,
+                "values differ at ({}, {}): {} != {}",
+                position.0, position.1, expected, found
+            ),
+        }
+    }
+}
+
+impl Error for MismatchError {}
+
+pub fn verify_equality_imgs<T: Sample>(a: &GrayBuffer<T>, b: &GrayBuffer<T>) -> Result<(), MismatchError> {
+    if a.dimensions() != b.dimensions() {
+        let dims = |img: &GrayBuffer<T>| (img.width() as usize, img.height() as usize);
+        return Err(MismatchError::Dimensions { expected: dims(a), found: dims(b) });
+    }
+    for (x, y, pixel) in a.enumerate_pixels() {
+        let other = b.get_pixel(x, y)[0];
+        if pixel[0] != other {
+            return Err(MismatchError::Value {
+                position: (x as usize, y as usize),
+                expected: Into::<i32>::into(pixel[0]) as i64,
+                found: Into::<i32>::into(other) as i64,
+            });
+        }
+    }
+    Ok(())
+}
+
+pub fn verify_equality_arrays(a: &Array2<i32>, b: &Array2<i32>) -> Result<(), MismatchError> {
+    if a.dim() != b.dim() {
+        return Err(MismatchError::Dimensions { expected: a.dim(), found: b.dim() });
+    }
+    for (position, &value) in a.indexed_iter() {
+        if value != b[position] {
+            return Err(MismatchError::Value {
+                position,
+                expected: value as i64,
+                found: b[position] as i64,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn equal_images_and_arrays() {
+        let img = GrayImage::from_fn(5, 3, |x, y| Luma([(x * 40 + y) as u8]));
+        assert_eq!(verify_equality_imgs(&img, &img.clone()), Ok(()));
+        let matrix = Array2::from_shape_fn((4, 2), |(i, j)| i as i32 - j as i32);
+        assert_eq!(verify_equality_arrays(&matrix, &matrix.clone()), Ok(()));
+    }
+
+    #[test]
+    fn reports_first_difference() {
+        let img = GrayImage::new(4, 4);
+        let mut other = img.clone();
+        other.put_pixel(2, 1, Luma([7]));
+        assert_eq!(
+            verify_equality_imgs(&img, &other),
+            Err(MismatchError::Value { position: (2, 1), expected: 0, found: 7 })
+        );
+        assert_eq!(
+            verify_equality_imgs(&img, &GrayImage::new(4, 5)),
+            Err(MismatchError::Dimensions { expected: (4, 4), found: (4, 5) })
+        );
+
+        let matrix = Array2::zeros((3, 3));
+        let mut other = matrix.clone();
+        other[[1, 2]] = -3;
+        assert_eq!(
+            verify_equality_arrays(&matrix, &other),
+            Err(MismatchError::Value { position: (1, 2), expected: 0, found: -3 })
+        );
+    }
+}
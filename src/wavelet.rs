@@ -0,0 +1,198 @@
+use std::ops::Range;
+
+use ndarray::{s, Array2, ArrayViewMut1, Axis};
+
+/*
+    Reversible integer wavelet transform: LeGall 5/3 with lifting, as in lossless JPEG 2000.
+
+    In one dimension, a signal x of length N is split into ceil(N/2) low-pass
+    coefficients s and floor(N/2) high-pass coefficients d:
+
+        d[n] = x[2n+1] - floor((x[2n] + x[2n+2]) / 2)
+        s[n] = x[2n]   + floor((d[n-1] + d[n] + 2) / 4)
+
+    with symmetric extension at the borders (x[N] = x[N-2], d[-1] = d[0] and, for odd N,
+    the missing last d mirrors the one before it). The inverse undoes the two steps in
+    reverse order with the same integer rounding, so it is exact.
+
+    One level of the 2-D transform filters every row of the current low-pass region,
+    then every column, giving four subbands (H = high-pass, L = low-pass, horizontal
+    filter first):
+
+        LL | HL
+        ---+---
+        LH | HH
+
+    The next level transforms LL again. Subbands are named with their level, 1 being the
+    finest (HL1, LH1, HH1, ..., LL<levels>), and the coefficients are stored in place
+    in a matrix of the size of the input (Mallat layout), indexed [[x, y]].
+*/
+
+pub const DEFAULT_LEVELS: u32 = 3;
+
+// Split `line` into its low-pass coefficients followed by its high-pass ones
+fn lift_forward(mut line: ArrayViewMut1<i32>) {
+    let x: Vec<i32> = line.to_vec();
+    let n = x.len();
+    if n < 2 {
+        return;
+    }
+    let (low, high) = (n.div_ceil(2), n / 2);
+    let at = |i: usize| if i < n { x[i] } else { x[n - 2] };
+    let d: Vec<i32> = (0..high).map(|i| x[2 * i + 1] - ((x[2 * i] + at(2 * i + 2)) >> 1)).collect();
+    let dd = |i: isize| d[i.clamp(0, high as isize - 1) as usize];
+    for i in 0..low {
+        line[i] = x[2 * i] + ((dd(i as isize - 1) + dd(i as isize) + 2) >> 2);
+    }
+    for i in 0..high {
+        line[low + i] = d[i];
+    }
+}
+
+// Inverse of `lift_forward`
+fn lift_inverse(mut line: ArrayViewMut1<i32>) {
+    let c: Vec<i32> = line.to_vec();
+    let n = c.len();
+    if n < 2 {
+        return;
+    }
+    let (low, high) = (n.div_ceil(2), n / 2);
+    let d = &c[low..];
+    let dd = |i: isize| d[i.clamp(0, high as isize - 1) as usize];
+    let mut x = vec![0; n];
+    for i in 0..low {
+        x[2 * i] = c[i] - ((dd(i as isize - 1) + dd(i as isize) + 2) >> 2);
+    }
+    for i in 0..high {
+        let right = if 2 * i + 2 < n { x[2 * i + 2] } else { x[2 * i] };
+        x[2 * i + 1] = d[i] + ((x[2 * i] + right) >> 1);
+    }
+    for (value, restored) in line.iter_mut().zip(x) {
+        *value = restored;
+    }
+}
+
+// Size of the low-pass region after `level` levels
+fn low_shape((width, height): (usize, usize), level: u32) -> (usize, usize) {
+    let mut shape = (width, height);
+    for _ in 0..level {
+        shape = (shape.0.div_ceil(2), shape.1.div_ceil(2));
+    }
+    shape
+}
+
+/// Largest number of levels for a matrix of `shape`: every level needs at least two
+/// coefficients in each direction.
+pub fn max_levels(shape: (usize, usize)) -> u32 {
+    let mut levels = 0;
+    while {
+        let (w, h) = low_shape(shape, levels);
+        w >= 2 && h >= 2
+    } {
+        levels += 1;
+    }
+    levels
+}
+
+/// Wavelet coefficients of `matrix` after `levels` levels (at most `max_levels`).
+pub fn wavelet_forward(matrix: &Array2<i32>, levels: u32) -> Array2<i32> {
+    assert!(levels <= max_levels(matrix.dim()), "too many wavelet levels for a {:?} matrix", matrix.dim());
+    let mut coefficients = matrix.clone();
+    for level in 0..levels {
+        let (w, h) = low_shape(matrix.dim(), level);
+        let mut region = coefficients.slice_mut(s![..w, ..h]);
+        for row in region.lanes_mut(Axis(0)) {
+            lift_forward(row);
+        }
+        for column in region.lanes_mut(Axis(1)) {
+            lift_forward(column);
+        }
+    }
+    coefficients
+}
+
+/// Inverse of `wavelet_forward`.
+pub fn wavelet_inverse(coefficients: &Array2<i32>, levels: u32) -> Array2<i32> {
+    assert!(levels <= max_levels(coefficients.dim()), "too many wavelet levels for a {:?} matrix", coefficients.dim());
+    let mut matrix = coefficients.clone();
+    for level in (0..levels).rev() {
+        let (w, h) = low_shape(coefficients.dim(), level);
+        let mut region = matrix.slice_mut(s![..w, ..h]);
+        for column in region.lanes_mut(Axis(1)) {
+            lift_inverse(column);
+        }
+        for row in region.lanes_mut(Axis(0)) {
+            lift_inverse(row);
+        }
+    }
+    matrix
+}
+
+/// Position of a subband in the coefficient matrix.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Subband {
+    pub name: String,
+    pub level: u32,
+    pub x: Range<usize>,
+    pub y: Range<usize>,
+}
+
+impl Subband {
+    // Copy of the coefficients of the subband.
+    pub fn extract(&self, coefficients: &Array2<i32>) -> Array2<i32> {
+        coefficients.slice(s![self.x.clone(), self.y.clone()]).to_owned()
+    }
+
+    // Inverse of `extract`: write `matrix` over the subband.
+    pub fn insert(&self, coefficients: &mut Array2<i32>, matrix: &Array2<i32>) {
+        coefficients.slice_mut(s![self.x.clone(), self.y.clone()]).assign(matrix);
+    }
+
+    pub fn shape(&self) -> (usize, usize) {
+        (self.x.len(), self.y.len())
+    }
+}
+
+/// Subbands of a matrix of `shape` after `levels` levels, from the coarsest (LL) to
+/// the finest.
+pub fn subbands(shape: (usize, usize), levels: u32) -> Vec<Subband> {
+    let (w, h) = low_shape(shape, levels);
+    let mut bands = vec![Subband { name: format!("LL{}", levels), level: levels, x: 0..w, y: 0..h }];
+    for level in (1..=levels).rev() {
+        let (w, h) = low_shape(shape, level - 1);
+        let (lw, lh) = low_shape(shape, level);
+        let band = |name: &str, x: Range<usize>, y: Range<usize>| Subband { name: format!("{}{}", name, level), level, x, y };
+        bands.push(band("HL", lw..w, 0..lh));
+        bands.push(band("LH", 0..lw, lh..h));
+        bands.push(band("HH", lw..w, lh..h));
+    }
+    bands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transform_is_reversible() {
+        for (width, height) in [(8, 8), (7, 5), (2, 9), (33, 17), (1, 4)] {
+            let matrix = Array2::from_shape_fn((width, height), |(x, y)| ((x * 37 + y * 91) % 256) as i32 - (x * y % 7) as i32);
+            for levels in 0..=max_levels((width, height)) {
+                let coefficients = wavelet_forward(&matrix, levels);
+                assert_eq!(wavelet_inverse(&coefficients, levels), matrix, "{}x{}, {} levels", width, height, levels);
+                let covered: usize = subbands((width, height), levels).iter().map(|band| band.x.len() * band.y.len()).sum();
+                assert_eq!(covered, width * height);
+            }
+        }
+    }
+
+    #[test]
+    fn constant_signal_has_empty_high_pass() {
+        let matrix = Array2::from_elem((16, 12), 100);
+        let coefficients = wavelet_forward(&matrix, 2);
+        for band in subbands(matrix.dim(), 2) {
+            let expected = if band.name == "LL2" { 100 } else { 0 };
+            assert!(band.extract(&coefficients).iter().all(|&c| c == expected), "{}", band.name);
+        }
+    }
+}